@@ -0,0 +1,203 @@
+use std::fmt;
+use crate::value::{Arithmetic, Value};
+
+#[derive(Debug)]
+pub enum AggregateError {
+    /// `SUM` of integers does not fit in an integer.
+    IntegerOverflow,
+}
+
+impl fmt::Display for AggregateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AggregateError::IntegerOverflow => write!(f, "integer overflow"),
+        }
+    }
+}
+
+/// A function that computes one value from the rows of a group.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Function {
+    /// `COUNT(*)` counts rows and `COUNT(x)` the rows where `x` is not
+    /// NULL.
+    Count,
+    Sum,
+    Avg,
+    Min,
+    Max,
+    /// `GROUP_CONCAT(x [, separator])` joins the text of each `x` that
+    /// is not NULL, separated by `,` unless a separator is given.
+    GroupConcat,
+}
+
+impl Function {
+    /// Returns the aggregate function called `name`, ignoring case.
+    pub fn from_name(name: &str) -> Option<Self> {
+        let function = match name.to_ascii_lowercase().as_str() {
+            "count" => Function::Count,
+            "sum" => Function::Sum,
+            "avg" => Function::Avg,
+            "min" => Function::Min,
+            "max" => Function::Max,
+            "group_concat" => Function::GroupConcat,
+            _ => return None,
+        };
+        Some(function)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Function::Count => "count",
+            Function::Sum => "sum",
+            Function::Avg => "avg",
+            Function::Min => "min",
+            Function::Max => "max",
+            Function::GroupConcat => "group_concat",
+        }
+    }
+
+    /// Returns whether the function can be called with `num_args`
+    /// arguments, where `COUNT(*)` has none.
+    pub fn accepts(self, num_args: usize) -> bool {
+        match self {
+            Function::Count => num_args <= 1,
+            Function::GroupConcat => num_args == 1 || num_args == 2,
+            _ => num_args == 1,
+        }
+    }
+}
+
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// The state of an aggregate function part way through a group.
+#[derive(Debug, Clone)]
+pub struct Accumulator {
+    function: Function,
+    /// Number of rows counted by `COUNT`, or of values added up by
+    /// `SUM` and `AVG`.
+    count: i64,
+    /// Total for `SUM` and `AVG`, smallest or largest value for `MIN`
+    /// and `MAX`, and joined text for `GROUP_CONCAT`. NULL until the
+    /// first value that is not NULL.
+    value: Value,
+}
+
+impl Accumulator {
+    pub fn new(function: Function) -> Self {
+        Self { function, count: 0, value: Value::Null }
+    }
+
+    /// Adds a row with the arguments `args` to the group.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `SUM` overflows an integer.
+    pub fn step(&mut self, args: &[Value]) -> Result<(), AggregateError> {
+        let arg = args.first().unwrap_or(&Value::Null);
+        if self.function == Function::Count {
+            if args.is_empty() || *arg != Value::Null {
+                self.count += 1;
+            }
+            return Ok(());
+        }
+        if *arg == Value::Null {
+            return Ok(());
+        }
+        self.value = match (self.function, &self.value) {
+            // A NULL total after the first row is one that was not a
+            // number, and stays NULL.
+            (Function::Sum | Function::Avg, Value::Null) if self.count == 0 => arg.to_numeric(),
+            (Function::Sum, Value::Integer(total)) => match arg.to_numeric() {
+                Value::Integer(value) => Value::Integer(total.checked_add(value).ok_or(AggregateError::IntegerOverflow)?),
+                value => Value::Real(*total as f64).arithmetic(Arithmetic::Add, &value),
+            },
+            (Function::Sum | Function::Avg, total) => total.arithmetic(Arithmetic::Add, arg),
+            (Function::Min | Function::Max, Value::Null) => arg.clone(),
+            (Function::Min | Function::Max, best) => {
+                let ordering = arg.compare(best).unwrap_or(std::cmp::Ordering::Equal);
+                let is_better = if self.function == Function::Min { ordering.is_lt() } else { ordering.is_gt() };
+                if is_better { arg.clone() } else { best.clone() }
+            },
+            (Function::GroupConcat, Value::Null) => Value::Text(String::new()).concat(arg),
+            (_, text) => {
+                let separator = match args.get(1) {
+                    Some(Value::Null) => Value::Text(String::new()),
+                    Some(separator) => separator.clone(),
+                    None => Value::Text(String::from(",")),
+                };
+                text.concat(&separator).concat(arg)
+            },
+        };
+        self.count += 1;
+        Ok(())
+    }
+
+    /// Returns the function's result for the rows added so far.
+    pub fn finish(self) -> Value {
+        match self.function {
+            Function::Count => Value::Integer(self.count),
+            Function::Avg if self.count == 0 => Value::Null,
+            // Adding 0.0 makes the total floating point before dividing.
+            Function::Avg => self.value
+                .arithmetic(Arithmetic::Add, &Value::Real(0.0))
+                .arithmetic(Arithmetic::Divide, &Value::Integer(self.count)),
+            _ => self.value,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn aggregate(function: Function, values: &[Value]) -> Result<Value, AggregateError> {
+        let mut accumulator = Accumulator::new(function);
+        for value in values {
+            accumulator.step(std::slice::from_ref(value))?;
+        }
+        Ok(accumulator.finish())
+    }
+
+    #[test]
+    fn aggregates_skip_nulls() {
+        let values = [Value::Integer(3), Value::Null, Value::Integer(1), Value::Real(2.5)];
+        assert_eq!(Value::Integer(3), aggregate(Function::Count, &values).unwrap());
+        assert_eq!(Value::Real(6.5), aggregate(Function::Sum, &values).unwrap());
+        assert_eq!(Value::Integer(4), aggregate(Function::Sum, &values[..3]).unwrap());
+        assert_eq!(Value::Real(6.5 / 3.0), aggregate(Function::Avg, &values).unwrap());
+        assert_eq!(Value::Integer(1), aggregate(Function::Min, &values).unwrap());
+        assert_eq!(Value::Integer(3), aggregate(Function::Max, &values).unwrap());
+        assert_eq!(Value::Text(String::from("3,1,2.5")), aggregate(Function::GroupConcat, &values).unwrap());
+    }
+
+    #[test]
+    fn aggregates_of_no_values_are_null_except_count() {
+        assert_eq!(Value::Integer(0), aggregate(Function::Count, &[Value::Null]).unwrap());
+        assert_eq!(Value::Null, aggregate(Function::Sum, &[Value::Null]).unwrap());
+        assert_eq!(Value::Null, aggregate(Function::Avg, &[]).unwrap());
+        assert_eq!(Value::Null, aggregate(Function::GroupConcat, &[]).unwrap());
+
+        let mut count_rows = Accumulator::new(Function::Count);
+        count_rows.step(&[]).unwrap();
+        count_rows.step(&[]).unwrap();
+        assert_eq!(Value::Integer(2), count_rows.finish());
+    }
+
+    #[test]
+    fn integer_sum_overflow_is_an_error() {
+        let values = [Value::Integer(i64::MAX), Value::Integer(1)];
+        assert!(matches!(aggregate(Function::Sum, &values), Err(AggregateError::IntegerOverflow)));
+        assert_eq!(Value::Real(i64::MAX as f64 / 2.0), aggregate(Function::Avg, &values).unwrap());
+    }
+
+    #[test]
+    fn sums_that_are_not_a_number_are_null() {
+        let values = [Value::Real(f64::INFINITY), Value::Real(f64::NEG_INFINITY), Value::Integer(5)];
+        assert_eq!(Value::Null, aggregate(Function::Sum, &values).unwrap());
+        assert_eq!(Value::Null, aggregate(Function::Avg, &values).unwrap());
+    }
+}
@@ -0,0 +1,315 @@
+use std::fmt;
+use crate::lexer::Keyword;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Statement {
+    Select(Box<Select>),
+    Insert(Insert),
+    Update(Update),
+    Delete(Delete),
+    CreateTable(CreateTable),
+    CreateIndex(CreateIndex),
+    /// `VACUUM`, which rebuilds the database file without free space.
+    Vacuum,
+    /// `ANALYZE [table]`, which gathers statistics about the given table,
+    /// or every table, for the query planner.
+    Analyze(Option<String>),
+    /// `EXPLAIN statement`, which lists the compiled program instead of
+    /// running it.
+    Explain(Box<Statement>),
+    /// `EXPLAIN QUERY PLAN statement`, which describes how each table
+    /// the statement reads would be searched.
+    ExplainQueryPlan(Box<Statement>),
+}
+
+/// `CREATE TABLE [IF NOT EXISTS] name (columns)`
+///
+/// Displaying a `CreateTable` gives the statement in the form stored in
+/// the schema catalog.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CreateTable {
+    pub name: String,
+    pub if_not_exists: bool,
+    pub columns: Vec<ColumnDef>,
+}
+
+impl fmt::Display for CreateTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "CREATE TABLE {} (", Quoted(&self.name))?;
+        for (i, column) in self.columns.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", Quoted(&column.name))?;
+            if let Some(type_name) = &column.type_name {
+                write!(f, " {type_name}")?;
+            }
+            if column.primary_key {
+                write!(f, " PRIMARY KEY")?;
+            }
+            if column.not_null {
+                write!(f, " NOT NULL")?;
+            }
+        }
+        write!(f, ")")
+    }
+}
+
+/// `CREATE [UNIQUE] INDEX [IF NOT EXISTS] name ON table (columns)`
+///
+/// Displaying a `CreateIndex` gives the statement in the form stored in
+/// the schema catalog.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CreateIndex {
+    pub name: String,
+    pub table: String,
+    pub columns: Vec<String>,
+    /// Whether no two rows may have the same values in every indexed
+    /// column, unless one of them is NULL.
+    pub unique: bool,
+    pub if_not_exists: bool,
+}
+
+impl fmt::Display for CreateIndex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let unique = if self.unique { "UNIQUE " } else { "" };
+        write!(f, "CREATE {unique}INDEX {} ON {} (", Quoted(&self.name), Quoted(&self.table))?;
+        for (i, column) in self.columns.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", Quoted(column))?;
+        }
+        write!(f, ")")
+    }
+}
+
+/// A column definition in `CREATE TABLE`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnDef {
+    pub name: String,
+    /// Declared type, such as `INTEGER` or `VARCHAR(20)`, if any.
+    pub type_name: Option<String>,
+    pub primary_key: bool,
+    pub not_null: bool,
+}
+
+/// Displays an identifier, quoting it if it would not otherwise be read
+/// back as the same identifier.
+struct Quoted<'a>(&'a str);
+
+impl fmt::Display for Quoted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = self.0;
+        let is_plain = name.starts_with(|c: char| c.is_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_alphanumeric() || c == '_')
+            && Keyword::from_word(name).is_none();
+        if is_plain {
+            write!(f, "{name}")
+        } else {
+            write!(f, "\"{}\"", name.replace('"', "\"\""))
+        }
+    }
+}
+
+/// `INSERT INTO table [(columns)] VALUES (values), ...`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Insert {
+    pub table: String,
+    /// Columns named after the table, or empty if every column is
+    /// given in table order.
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<Expr>>,
+}
+
+/// `UPDATE table SET column = value, ... [WHERE condition]`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Update {
+    pub table: String,
+    /// Columns and the values they are set to, in the order given.
+    pub assignments: Vec<(String, Expr)>,
+    pub where_clause: Option<Expr>,
+}
+
+/// `DELETE FROM table [WHERE condition]`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Delete {
+    pub table: String,
+    pub where_clause: Option<Expr>,
+}
+
+/// `[WITH tables] SELECT columns [FROM tables] [WHERE condition]
+/// [GROUP BY exprs] [HAVING condition] [UNION [ALL] SELECT ...]
+/// [ORDER BY terms] [LIMIT count [OFFSET skipped]]`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Select {
+    /// Tables defined by a `WITH` clause for the select to read.
+    pub with: Vec<Cte>,
+    pub columns: Vec<ResultColumn>,
+    /// The tables in `FROM`, in order, which is empty without a `FROM`
+    /// clause.
+    pub from: Vec<Join>,
+    pub where_clause: Option<Expr>,
+    pub group_by: Vec<Expr>,
+    pub having: Option<Expr>,
+    /// Selects whose rows are added to those of this one, in order.
+    /// They have no `WITH`, `ORDER BY` or `LIMIT` of their own, since
+    /// this select's apply to all the rows.
+    pub compound: Vec<(CompoundOp, Select)>,
+    pub order_by: Vec<OrderingTerm>,
+    pub limit: Option<Limit>,
+}
+
+impl Select {
+    /// Returns every expression written in the select, outside of the
+    /// subqueries in them.
+    pub fn exprs(&self) -> impl Iterator<Item = &Expr> {
+        let columns = self.columns.iter().filter_map(|column| match column {
+            ResultColumn::Expr { expr, .. } => Some(expr),
+            _ => None,
+        });
+        let limit = self.limit.iter().flat_map(|limit| std::iter::once(&limit.count).chain(&limit.offset));
+        columns
+            .chain(self.from.iter().filter_map(|join| join.on.as_ref()))
+            .chain(&self.where_clause)
+            .chain(&self.group_by)
+            .chain(&self.having)
+            .chain(self.order_by.iter().map(|term| &term.expr))
+            .chain(limit)
+    }
+}
+
+/// `name [(columns)] AS (select)` in a `WITH` clause, a table holding
+/// the rows of the select. A select that is a `UNION` may read the
+/// table itself, which then holds the rows of the selects that do not,
+/// and those found by running the ones that do for each row added.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cte {
+    pub name: String,
+    /// Names of the table's columns, or empty to use the names of the
+    /// select's result columns.
+    pub columns: Vec<String>,
+    pub select: Select,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompoundOp {
+    /// `UNION`, which leaves out rows equal to one already added.
+    Union,
+    /// `UNION ALL`, which keeps every row.
+    UnionAll,
+}
+
+impl fmt::Display for CompoundOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompoundOp::Union => write!(f, "UNION"),
+            CompoundOp::UnionAll => write!(f, "UNION ALL"),
+        }
+    }
+}
+
+/// A table in a `FROM` clause and how it is joined to the tables before
+/// it, as in `JOIN table [AS alias] [ON condition]`. The first table is
+/// an inner join without a condition.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Join {
+    pub table: String,
+    pub alias: Option<String>,
+    pub kind: JoinKind,
+    pub on: Option<Expr>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoinKind {
+    /// `[INNER] JOIN`, `CROSS JOIN` or a comma, which pairs each row of
+    /// the tables before with each row of the table where the `ON`
+    /// condition holds.
+    Inner,
+    /// `LEFT [OUTER] JOIN`, which also keeps the rows of the tables
+    /// before that pair with no row, with NULL for each column of the
+    /// table.
+    Left,
+}
+
+/// An expression in `ORDER BY`, which may also be the number or alias
+/// of a result column.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrderingTerm {
+    pub expr: Expr,
+    pub descending: bool,
+}
+
+/// `LIMIT count [OFFSET skipped]`, also written `LIMIT skipped, count`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Limit {
+    pub count: Expr,
+    pub offset: Option<Expr>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ResultColumn {
+    /// `*`, every column of every table.
+    All,
+    /// `table.*`, every column of one table.
+    TableAll(String),
+    Expr { expr: Expr, alias: Option<String> },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Literal(Literal),
+    Column { table: Option<String>, name: String },
+    Unary { op: UnaryOp, operand: Box<Expr> },
+    Binary { op: BinaryOp, left: Box<Expr>, right: Box<Expr> },
+    /// A function call such as `count(x)`, where `count(*)` has no
+    /// arguments.
+    Function { name: String, args: Vec<Expr> },
+    /// `expr [NOT] IN (values)`, where the list of values may be empty.
+    InList { expr: Box<Expr>, list: Vec<Expr>, negated: bool },
+    /// `expr [NOT] IN (SELECT ...)`, compared with the values of the
+    /// select's only column.
+    InSelect { expr: Box<Expr>, select: Box<Select>, negated: bool },
+    /// `EXISTS (SELECT ...)`, whether the select returns any row.
+    Exists(Box<Select>),
+    /// `(SELECT ...)`, the value of the only column of the first row the
+    /// select returns, or NULL if it returns none.
+    Subquery(Box<Select>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Literal {
+    Null,
+    Integer(i64),
+    Float(f64),
+    String(String),
+    Blob(Vec<u8>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    Negate,
+    Plus,
+    Not,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Or,
+    And,
+    Eq,
+    NotEq,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+    /// Equality where NULL equals NULL, as in `a IS NULL`.
+    Is,
+    IsNot,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+    Concat,
+}
@@ -0,0 +1,815 @@
+use std::{cmp::Ordering, fmt};
+use crate::{pager, table::Row};
+
+/// Node type flag of a table leaf node, which stores keys and their
+/// payloads.
+const LEAF_NODE: u8 = 0x0d;
+/// Node type flag of a table internal node, which stores keys and child
+/// page numbers.
+const INTERNAL_NODE: u8 = 0x05;
+/// Node type flags of the nodes of an index tree.
+const INDEX_LEAF_NODE: u8 = 0x0a;
+const INDEX_INTERNAL_NODE: u8 = 0x02;
+
+// Node header layout:
+// | type (1) | unused (1) | number of cells (2) | right child (4) |
+const NODE_TYPE_OFFSET: usize = 0;
+const NUM_CELLS_OFFSET: usize = 2;
+const RIGHT_CHILD_OFFSET: usize = 4;
+const NODE_HEADER_SIZE: usize = 8;
+
+// Leaf cell layout:
+// | key | payload length (4) | local payload | [overflow page (4)] |
+// The overflow page number is only present when the payload is too
+// large to be stored entirely in the cell. Keys of table trees are 8
+// byte integers, and those of index trees a 4 byte length followed by
+// a record.
+const LEAF_CELL_HEADER_SIZE: usize = 12;
+const PAYLOAD_LEN_SIZE: usize = 4;
+const OVERFLOW_POINTER_SIZE: usize = 4;
+const RECORD_KEY_LEN_SIZE: usize = 4;
+
+// Overflow page layout:
+// | next overflow page, or 0 for the last (4) | payload |
+const OVERFLOW_HEADER_SIZE: usize = 4;
+// Internal cell layout:
+// | child page number (4) | key |
+const CHILD_POINTER_SIZE: usize = 4;
+
+#[derive(Debug)]
+pub enum BTreeError {
+    PagerError(pager::PagerError),
+    /// A page does not contain a valid node.
+    CorruptNode(usize),
+    /// A payload is too large for its length to be stored.
+    CellTooLarge(usize),
+    /// An index key is too large to be stored in a node.
+    KeyTooLarge(usize),
+    /// A key being inserted is already in the tree.
+    DuplicateKey(Key),
+}
+
+impl fmt::Display for BTreeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BTreeError::PagerError(e) => write!(f, "{e}"),
+            BTreeError::CorruptNode(page_num) => write!(f, "page {page_num} is corrupt"),
+            BTreeError::CellTooLarge(size) => write!(f, "row of {size} bytes is too large"),
+            BTreeError::KeyTooLarge(size) => write!(f, "index entry of {size} bytes is too large"),
+            BTreeError::DuplicateKey(key) => write!(f, "duplicate key {key}"),
+        }
+    }
+}
+
+impl From<pager::PagerError> for BTreeError {
+    fn from(e: pager::PagerError) -> Self {
+        Self::PagerError(e)
+    }
+}
+
+/// Whether a tree holds the rows of a table or the entries of an index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TreeKind {
+    /// Rows are payloads stored under integer row ids.
+    Table,
+    /// Entries are records of the indexed values followed by the row
+    /// id, stored as keys with no payload.
+    Index,
+}
+
+/// The key that orders the cells of a tree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Key {
+    /// Row id in a table tree.
+    Integer(i64),
+    /// Serialised record in an index tree. Records compare value by
+    /// value in `ORDER BY` order, and a record that is a prefix of
+    /// another comes first.
+    Record(Vec<u8>),
+}
+
+impl Key {
+    /// Number of bytes the key takes up in a cell.
+    fn size(&self) -> usize {
+        match self {
+            Key::Integer(_) => 8,
+            Key::Record(record) => RECORD_KEY_LEN_SIZE + record.len(),
+        }
+    }
+
+    fn write(&self, buffer: &mut Vec<u8>) {
+        match self {
+            Key::Integer(key) => buffer.extend_from_slice(&key.to_le_bytes()),
+            Key::Record(record) => {
+                buffer.extend_from_slice(&(record.len() as u32).to_le_bytes());
+                buffer.extend_from_slice(record);
+            },
+        }
+    }
+}
+
+impl Ord for Key {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (Key::Integer(a), Key::Integer(b)) => a.cmp(b),
+            (Key::Record(a), Key::Record(b)) => {
+                // Records that cannot be read compare as if empty, so
+                // that reading the node they are in reports the error.
+                let values = |record| Row::deserialise(record).map(|row| row.values).unwrap_or_default();
+                let (a, b) = (values(a), values(b));
+                a.iter().zip(&b)
+                    .map(|(a, b)| a.sort_cmp(b))
+                    .find(|ordering| ordering.is_ne())
+                    .unwrap_or(a.len().cmp(&b.len()))
+            },
+            (Key::Integer(_), Key::Record(_)) => Ordering::Less,
+            (Key::Record(_), Key::Integer(_)) => Ordering::Greater,
+        }
+    }
+}
+
+impl PartialOrd for Key {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Key::Integer(key) => write!(f, "{key}"),
+            Key::Record(record) => match Row::deserialise(record) {
+                Ok(row) => {
+                    let values: Vec<String> = row.values.iter().map(ToString::to_string).collect();
+                    write!(f, "({})", values.join(", "))
+                },
+                Err(_) => write!(f, "(corrupt record)"),
+            },
+        }
+    }
+}
+
+/// An entry in a leaf node.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LeafCell {
+    pub key: Key,
+    /// The part of the payload stored in the cell, which is all of it
+    /// unless `overflow` is set.
+    pub payload: Vec<u8>,
+    /// Length of the whole payload.
+    pub payload_len: usize,
+    /// First page of the chain of overflow pages holding the rest of
+    /// the payload.
+    pub overflow: Option<usize>,
+}
+
+impl LeafCell {
+    /// Returns a cell holding `payload` under `key`, writing the part of
+    /// the payload that does not fit in the cell to overflow pages.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Err`] if the payload's length does not fit in 4 bytes,
+    /// or the key is a record larger than [`max_record_key_len`].
+    pub fn new(pager: &mut pager::Pager, key: Key, payload: &[u8]) -> Result<Self, BTreeError> {
+        if u32::try_from(payload.len()).is_err() {
+            return Err(BTreeError::CellTooLarge(payload.len()));
+        }
+        if let Key::Record(record) = &key {
+            if record.len() > max_record_key_len(pager.page_size()) {
+                return Err(BTreeError::KeyTooLarge(record.len()));
+            }
+        }
+        let local_len = local_payload_len(pager.page_size(), payload.len());
+        let overflow = if local_len < payload.len() {
+            Some(write_overflow(pager, &payload[local_len..])?)
+        } else {
+            None
+        };
+        Ok(Self { key, payload: payload[..local_len].to_vec(), payload_len: payload.len(), overflow })
+    }
+
+    /// Returns the whole payload, reading the part that does not fit in
+    /// the cell back from its overflow pages.
+    pub fn read_payload(self, pager: &mut pager::Pager) -> Result<Vec<u8>, BTreeError> {
+        let mut payload = self.payload;
+        if let Some(overflow) = self.overflow {
+            let remaining = self.payload_len - payload.len();
+            payload.extend_from_slice(&read_overflow(pager, overflow, remaining)?);
+        }
+        Ok(payload)
+    }
+
+    /// Returns the cell's overflow pages, if any, to the free list.
+    pub fn free_overflow(&self, pager: &mut pager::Pager) -> Result<(), BTreeError> {
+        let mut page_num = self.overflow.unwrap_or(0);
+        while page_num != 0 {
+            let page = pager.get_page(page_num)?.as_slice();
+            let next = u32::from_le_bytes(page[..OVERFLOW_HEADER_SIZE].try_into().unwrap()) as usize;
+            pager.free_page(page_num)?;
+            page_num = next;
+        }
+        Ok(())
+    }
+
+    /// Number of bytes the cell takes up in a node.
+    fn size(&self) -> usize {
+        let overflow_size = if self.overflow.is_some() { OVERFLOW_POINTER_SIZE } else { 0 };
+        self.key.size() + PAYLOAD_LEN_SIZE + self.payload.len() + overflow_size
+    }
+}
+
+/// An entry in an internal node pointing to the subtree whose largest
+/// key is `key`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InternalCell {
+    pub child: usize,
+    pub key: Key,
+}
+
+impl InternalCell {
+    /// Number of bytes the cell takes up in a node.
+    fn size(&self) -> usize {
+        CHILD_POINTER_SIZE + self.key.size()
+    }
+}
+
+/// The deserialised contents of a single B-tree page.
+///
+/// Keys in internal nodes are the largest key of the child to their
+/// left, and `right_child` holds every key larger than the last cell.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Node {
+    Leaf { kind: TreeKind, cells: Vec<LeafCell> },
+    Internal { kind: TreeKind, cells: Vec<InternalCell>, right_child: usize },
+}
+
+impl Node {
+    /// Reads the node stored in page number `page_num`.
+    pub fn read(pager: &mut pager::Pager, page_num: usize) -> Result<Self, BTreeError> {
+        let page_size = pager.page_size();
+        let page = pager.get_page(page_num)?.as_slice();
+        let corrupt = || BTreeError::CorruptNode(page_num);
+        let read_u32 = |loc: usize| -> Result<usize, BTreeError> {
+            let bytes = page.get(loc..loc + 4).ok_or_else(corrupt)?;
+            Ok(u32::from_le_bytes(bytes.try_into().unwrap()) as usize)
+        };
+        let kind = match page[NODE_TYPE_OFFSET] {
+            LEAF_NODE | INTERNAL_NODE => TreeKind::Table,
+            INDEX_LEAF_NODE | INDEX_INTERNAL_NODE => TreeKind::Index,
+            _ => return Err(corrupt()),
+        };
+        // Reads the key at `loc`, returning it and the location after it.
+        let read_key = |loc: usize| -> Result<(Key, usize), BTreeError> {
+            match kind {
+                TreeKind::Table => {
+                    let bytes = page.get(loc..loc + 8).ok_or_else(corrupt)?;
+                    Ok((Key::Integer(i64::from_le_bytes(bytes.try_into().unwrap())), loc + 8))
+                },
+                TreeKind::Index => {
+                    let start = loc + RECORD_KEY_LEN_SIZE;
+                    let end = start + read_u32(loc)?;
+                    let record = page.get(start..end).ok_or_else(corrupt)?;
+                    Ok((Key::Record(record.to_vec()), end))
+                },
+            }
+        };
+        let num_cells = u16::from_le_bytes(
+            page[NUM_CELLS_OFFSET..NUM_CELLS_OFFSET + 2].try_into().unwrap()
+        ) as usize;
+        let mut loc = NODE_HEADER_SIZE;
+        match page[NODE_TYPE_OFFSET] {
+            LEAF_NODE | INDEX_LEAF_NODE => {
+                let mut cells = Vec::with_capacity(num_cells);
+                for _ in 0..num_cells {
+                    let key;
+                    (key, loc) = read_key(loc)?;
+                    let payload_len = read_u32(loc)?;
+                    let local_len = local_payload_len(page_size, payload_len);
+                    loc += PAYLOAD_LEN_SIZE;
+                    let payload = page.get(loc..loc + local_len).ok_or_else(corrupt)?.to_vec();
+                    loc += local_len;
+                    let mut overflow = None;
+                    if local_len < payload_len {
+                        overflow = Some(read_u32(loc)?);
+                        loc += OVERFLOW_POINTER_SIZE;
+                    }
+                    cells.push(LeafCell { key, payload, payload_len, overflow });
+                }
+                Ok(Node::Leaf { kind, cells })
+            },
+            _ => {
+                let mut cells = Vec::with_capacity(num_cells);
+                for _ in 0..num_cells {
+                    let child = read_u32(loc)?;
+                    let key;
+                    (key, loc) = read_key(loc + CHILD_POINTER_SIZE)?;
+                    cells.push(InternalCell { child, key });
+                }
+                Ok(Node::Internal { kind, cells, right_child: read_u32(RIGHT_CHILD_OFFSET)? })
+            },
+        }
+    }
+
+    /// Writes the node into page number `page_num`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the node does not fit in a page.
+    pub fn write(&self, pager: &mut pager::Pager, page_num: usize) -> Result<(), BTreeError> {
+        let mut buffer = vec![0u8; NODE_HEADER_SIZE];
+        match self {
+            Node::Leaf { kind, cells } => {
+                buffer[NODE_TYPE_OFFSET] = match kind {
+                    TreeKind::Table => LEAF_NODE,
+                    TreeKind::Index => INDEX_LEAF_NODE,
+                };
+                for cell in cells {
+                    cell.key.write(&mut buffer);
+                    buffer.extend_from_slice(&(cell.payload_len as u32).to_le_bytes());
+                    buffer.extend_from_slice(&cell.payload);
+                    if let Some(overflow) = cell.overflow {
+                        buffer.extend_from_slice(&(overflow as u32).to_le_bytes());
+                    }
+                }
+            },
+            Node::Internal { kind, cells, right_child } => {
+                buffer[NODE_TYPE_OFFSET] = match kind {
+                    TreeKind::Table => INTERNAL_NODE,
+                    TreeKind::Index => INDEX_INTERNAL_NODE,
+                };
+                buffer[RIGHT_CHILD_OFFSET..RIGHT_CHILD_OFFSET + 4]
+                    .copy_from_slice(&(*right_child as u32).to_le_bytes());
+                for cell in cells {
+                    buffer.extend_from_slice(&(cell.child as u32).to_le_bytes());
+                    cell.key.write(&mut buffer);
+                }
+            },
+        }
+        buffer[NUM_CELLS_OFFSET..NUM_CELLS_OFFSET + 2]
+            .copy_from_slice(&(self.len() as u16).to_le_bytes());
+        pager.get_page_mut(page_num)?.copy_from_slice(0, &buffer);
+        Ok(())
+    }
+
+    /// Returns an empty leaf node of a tree of the given kind.
+    pub fn empty_leaf(kind: TreeKind) -> Self {
+        Node::Leaf { kind, cells: Vec::new() }
+    }
+
+    pub fn kind(&self) -> TreeKind {
+        match self {
+            Node::Leaf { kind, .. } | Node::Internal { kind, .. } => *kind,
+        }
+    }
+
+    /// Number of cells in the node.
+    pub fn len(&self) -> usize {
+        match self {
+            Node::Leaf { cells, .. } => cells.len(),
+            Node::Internal { cells, .. } => cells.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of bytes needed to store the node in a page.
+    pub fn size(&self) -> usize {
+        NODE_HEADER_SIZE + match self {
+            Node::Leaf { cells, .. } => cells.iter().map(LeafCell::size).sum::<usize>(),
+            Node::Internal { cells, .. } => cells.iter().map(InternalCell::size).sum::<usize>(),
+        }
+    }
+
+    /// Returns the page number of child number `index` of an internal
+    /// node, where `index` equal to the number of cells refers to the
+    /// right child.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the node is a leaf or `index` is out of range.
+    pub fn child(&self, index: usize) -> usize {
+        match self {
+            Node::Internal { cells, right_child, .. } => {
+                cells.get(index).map_or(*right_child, |cell| cell.child)
+            },
+            Node::Leaf { .. } => panic!("leaf nodes have no children"),
+        }
+    }
+
+    /// Records that child number `index` of an internal node has split,
+    /// leaving keys up to `split_key` in place and moving the rest to
+    /// `right_page`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the node is a leaf.
+    pub fn insert_child(&mut self, index: usize, split_key: Key, right_page: usize) {
+        match self {
+            Node::Internal { cells, right_child, .. } => {
+                if index == cells.len() {
+                    cells.push(InternalCell { child: *right_child, key: split_key });
+                    *right_child = right_page;
+                } else {
+                    let old_key = std::mem::replace(&mut cells[index].key, split_key);
+                    cells.insert(index + 1, InternalCell { child: right_page, key: old_key });
+                }
+            },
+            Node::Leaf { .. } => panic!("leaf nodes have no children"),
+        }
+    }
+
+    /// Points the child after child number `index` of an internal node
+    /// at the page of child number `index` and removes child `index`,
+    /// once the two children have been merged into that page.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the node is a leaf or `index` is not the index of a
+    /// cell.
+    pub fn remove_merged_child(&mut self, index: usize) {
+        match self {
+            Node::Internal { cells, right_child, .. } => {
+                let merged_page = cells.remove(index).child;
+                match cells.get_mut(index) {
+                    Some(cell) => cell.child = merged_page,
+                    None => *right_child = merged_page,
+                }
+            },
+            Node::Leaf { .. } => panic!("leaf nodes have no children"),
+        }
+    }
+
+    /// Sets the key separating children `index` and `index + 1` of an
+    /// internal node.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the node is a leaf or `index` is not the index of a
+    /// cell.
+    pub fn set_separator(&mut self, index: usize, key: Key) {
+        match self {
+            Node::Internal { cells, .. } => cells[index].key = key,
+            Node::Leaf { .. } => panic!("leaf nodes have no children"),
+        }
+    }
+
+    /// Appends the cells of `right`, the node after `self` at the same
+    /// level of the tree, where `separator` is the key between them in
+    /// their parent.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the nodes are not of the same type.
+    pub fn merge(&mut self, separator: Key, right: Node) {
+        match (self, right) {
+            (Node::Leaf { cells, .. }, Node::Leaf { cells: right, .. }) => cells.extend(right),
+            (Node::Internal { cells, right_child, .. }, Node::Internal { cells: right, right_child: right_right_child, .. }) => {
+                // The left node's right child now needs a cell of its
+                // own, keyed on the separator that moves down from the
+                // parent.
+                cells.push(InternalCell { child: *right_child, key: separator });
+                cells.extend(right);
+                *right_child = right_right_child;
+            },
+            _ => panic!("only nodes of the same type can be merged"),
+        }
+    }
+
+    /// Moves the upper half of the cells, by size, into a new node and
+    /// returns it together with the largest key left in `self`.
+    pub fn split(&mut self) -> (Key, Node) {
+        match self {
+            Node::Leaf { kind, cells } => {
+                let sizes: Vec<usize> = cells.iter().map(LeafCell::size).collect();
+                let right = cells.split_off(split_point(&sizes, false));
+                (cells.last().unwrap().key.clone(), Node::Leaf { kind: *kind, cells: right })
+            },
+            Node::Internal { kind, cells, right_child } => {
+                // The middle cell moves up to the parent, so its child
+                // becomes the right child of the left node.
+                let sizes: Vec<usize> = cells.iter().map(InternalCell::size).collect();
+                let mut right = cells.split_off(split_point(&sizes, true).min(cells.len() - 1));
+                let middle = right.remove(0);
+                let right = Node::Internal { kind: *kind, cells: right, right_child: *right_child };
+                *right_child = middle.child;
+                (middle.key, right)
+            },
+        }
+    }
+}
+
+/// Returns the index that divides cells of the given `sizes` into two
+/// groups, each holding at least one cell, so that the larger total size
+/// is as small as possible. If `skip_middle` is set, the cell at the
+/// index belongs to neither group, as when the middle cell of an
+/// internal node moves up to its parent.
+///
+/// Since any split of cells that came from two nodes is at least as
+/// even as the one between those nodes, both halves of the split fit in
+/// a page whenever the two nodes did.
+fn split_point(sizes: &[usize], skip_middle: bool) -> usize {
+    let total: usize = sizes.iter().sum();
+    let mut left = 0;
+    let mut best = (usize::MAX, 1);
+    for i in 1..sizes.len() {
+        left += sizes[i - 1];
+        let right = total - left - if skip_middle { sizes[i] } else { 0 };
+        if left.max(right) < best.0 {
+            best = (left.max(right), i);
+        }
+    }
+    best.1
+}
+
+/// Largest payload that is stored entirely in a leaf cell. Limiting
+/// cells to a quarter of a page guarantees that both halves of a split
+/// node fit in a page.
+pub fn max_local_payload(page_size: usize) -> usize {
+    (page_size - NODE_HEADER_SIZE) / 4 - LEAF_CELL_HEADER_SIZE
+}
+
+/// Largest record that can be stored as a key of an index tree. Like
+/// payloads, keys are limited to a quarter of a page so that both
+/// halves of a split node fit in a page.
+pub fn max_record_key_len(page_size: usize) -> usize {
+    (page_size - NODE_HEADER_SIZE) / 4 - RECORD_KEY_LEN_SIZE - PAYLOAD_LEN_SIZE - CHILD_POINTER_SIZE
+}
+
+/// Number of bytes of a payload of `payload_len` bytes that are stored
+/// in its leaf cell, leaving room for the overflow page number when the
+/// payload does not fit.
+fn local_payload_len(page_size: usize, payload_len: usize) -> usize {
+    let max_local = max_local_payload(page_size);
+    if payload_len <= max_local {
+        payload_len
+    } else {
+        max_local - OVERFLOW_POINTER_SIZE
+    }
+}
+
+/// Writes `data` to a chain of newly allocated overflow pages and
+/// returns the page number of the first.
+fn write_overflow(pager: &mut pager::Pager, data: &[u8]) -> Result<usize, BTreeError> {
+    let chunk_size = pager.page_size() - OVERFLOW_HEADER_SIZE;
+    let pages = (0..data.len().div_ceil(chunk_size))
+        .map(|_| pager.allocate_page())
+        .collect::<Result<Vec<usize>, _>>()?;
+    for (i, chunk) in data.chunks(chunk_size).enumerate() {
+        let next = pages.get(i + 1).copied().unwrap_or(0);
+        let page = pager.get_page_mut(pages[i])?;
+        page.copy_from_slice(0, &(next as u32).to_le_bytes());
+        page.copy_from_slice(OVERFLOW_HEADER_SIZE, chunk);
+    }
+    Ok(pages[0])
+}
+
+/// Reads `len` bytes from the chain of overflow pages starting at
+/// `page_num`.
+fn read_overflow(pager: &mut pager::Pager, mut page_num: usize, len: usize) -> Result<Vec<u8>, BTreeError> {
+    let chunk_size = pager.page_size() - OVERFLOW_HEADER_SIZE;
+    let mut data = Vec::with_capacity(len);
+    loop {
+        let page = pager.get_page(page_num)?.as_slice();
+        let chunk_len = chunk_size.min(len - data.len());
+        data.extend_from_slice(&page[OVERFLOW_HEADER_SIZE..OVERFLOW_HEADER_SIZE + chunk_len]);
+        if data.len() == len {
+            break;
+        }
+        let next = u32::from_le_bytes(page[..OVERFLOW_HEADER_SIZE].try_into().unwrap()) as usize;
+        if next == 0 {
+            return Err(BTreeError::CorruptNode(page_num));
+        }
+        page_num = next;
+    }
+    Ok(data)
+}
+
+/// Allocates a page holding an empty leaf node and returns its page
+/// number, to be used as the root of a new tree.
+pub fn create(pager: &mut pager::Pager, kind: TreeKind) -> Result<usize, BTreeError> {
+    let page_num = pager.allocate_page()?;
+    Node::empty_leaf(kind).write(pager, page_num)?;
+    Ok(page_num)
+}
+
+/// Builds a tree from rows given in ascending key order, filling each
+/// node as full as it will go, as when `VACUUM` copies a table.
+///
+/// The last two nodes on each level share their cells evenly if they do
+/// not fit in one node, so that no node other than the root is left
+/// less than a quarter full.
+pub struct TreeBuilder {
+    root_page: usize,
+    kind: TreeKind,
+    /// Nodes not yet written on each level, from the leaves up.
+    levels: Vec<Level>,
+}
+
+/// The nodes of one level of a [`TreeBuilder`] that are not yet written.
+struct Level {
+    /// The last full node and its largest key, held back until the
+    /// level either gets another full node or ends.
+    previous: Option<(Node, Key)>,
+    /// The node being filled. An internal node has no children until
+    /// its right child is set to a page other than 0.
+    current: Node,
+    /// Largest key in `current`, or `None` while it is empty.
+    max_key: Option<Key>,
+}
+
+impl Level {
+    fn new(kind: TreeKind, is_leaf: bool) -> Self {
+        let current = if is_leaf {
+            Node::empty_leaf(kind)
+        } else {
+            Node::Internal { kind, cells: Vec::new(), right_child: 0 }
+        };
+        Self { previous: None, current, max_key: None }
+    }
+}
+
+impl TreeBuilder {
+    /// Returns a builder for a tree of the given kind whose root will be
+    /// written to `root_page`.
+    pub fn new(root_page: usize, kind: TreeKind) -> Self {
+        Self { root_page, kind, levels: vec![Level::new(kind, true)] }
+    }
+
+    /// Adds `payload` under `key`, which must be larger than every key
+    /// added before it.
+    pub fn push(&mut self, pager: &mut pager::Pager, key: Key, payload: &[u8]) -> Result<(), BTreeError> {
+        let cell = LeafCell::new(pager, key.clone(), payload)?;
+        if self.levels[0].current.size() + cell.size() > pager.page_size() {
+            self.start_node(pager, 0)?;
+        }
+        let level = &mut self.levels[0];
+        if let Node::Leaf { cells, .. } = &mut level.current {
+            cells.push(cell);
+        }
+        level.max_key = Some(key);
+        Ok(())
+    }
+
+    /// Writes the nodes that are left and the root.
+    pub fn finish(mut self, pager: &mut pager::Pager) -> Result<(), BTreeError> {
+        let page_size = pager.page_size();
+        let mut index = 0;
+        while index < self.levels.len() {
+            let is_top = index + 1 == self.levels.len();
+            let Level { previous, current, max_key } = std::mem::replace(&mut self.levels[index], Level::new(self.kind, true));
+            let mut node = match previous {
+                Some((mut node, previous_max_key)) => {
+                    node.merge(previous_max_key, current);
+                    node
+                },
+                None => current,
+            };
+            if node.size() > page_size {
+                let (split_key, right) = node.split();
+                let left_page = pager.allocate_page()?;
+                node.write(pager, left_page)?;
+                let right_page = pager.allocate_page()?;
+                right.write(pager, right_page)?;
+                self.add_child(pager, index + 1, left_page, split_key)?;
+                // Can unwrap here since a node large enough to split has
+                // cells.
+                self.add_child(pager, index + 1, right_page, max_key.unwrap())?;
+            } else if is_top {
+                node.write(pager, self.root_page)?;
+            } else {
+                // Can unwrap here since only the top level can be left
+                // empty.
+                let max_key = max_key.unwrap();
+                let page_num = pager.allocate_page()?;
+                node.write(pager, page_num)?;
+                self.add_child(pager, index + 1, page_num, max_key)?;
+            }
+            index += 1;
+        }
+        Ok(())
+    }
+
+    /// Adds the node at `page_num`, whose largest key is `key`, as the
+    /// last child on level `index`.
+    fn add_child(&mut self, pager: &mut pager::Pager, index: usize, page_num: usize, key: Key) -> Result<(), BTreeError> {
+        if index == self.levels.len() {
+            self.levels.push(Level::new(self.kind, false));
+        }
+        let level = &self.levels[index];
+        let cell_size = level.max_key.as_ref().map_or(0, |key| CHILD_POINTER_SIZE + key.size());
+        if level.current.size() + cell_size > pager.page_size() {
+            self.start_node(pager, index)?;
+        }
+        let level = &mut self.levels[index];
+        if let Node::Internal { cells, right_child, .. } = &mut level.current {
+            if let Some(max_key) = level.max_key.take().filter(|_| *right_child != 0) {
+                cells.push(InternalCell { child: *right_child, key: max_key });
+            }
+            *right_child = page_num;
+        }
+        level.max_key = Some(key);
+        Ok(())
+    }
+
+    /// Ends the full node on level `index`, writing out the full node
+    /// before it.
+    fn start_node(&mut self, pager: &mut pager::Pager, index: usize) -> Result<(), BTreeError> {
+        let level = &mut self.levels[index];
+        let empty = Level::new(self.kind, matches!(level.current, Node::Leaf { .. })).current;
+        // Can unwrap here since only nodes with cells are full.
+        let full = (std::mem::replace(&mut level.current, empty), level.max_key.take().unwrap());
+        if let Some((node, max_key)) = level.previous.replace(full) {
+            let page_num = pager.allocate_page()?;
+            node.write(pager, page_num)?;
+            self.add_child(pager, index + 1, page_num, max_key)?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the index of the cell in an internal node whose child holds
+/// `key`, or the number of cells if `key` belongs to the right child.
+pub fn child_index(cells: &[InternalCell], key: &Key) -> usize {
+    cells.partition_point(|cell| cell.key < *key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_pager() -> pager::Pager {
+        pager::Pager::in_memory(pager::MIN_PAGE_SIZE, 16).unwrap()
+    }
+
+    #[test]
+    fn node_written_and_read_from_page_is_unchanged() {
+        let mut pager = test_pager();
+        let page_num = pager.allocate_page().unwrap();
+        let leaf = Node::Leaf {
+            kind: TreeKind::Table,
+            cells: vec![
+                LeafCell::new(&mut pager, Key::Integer(-3), b"hello").unwrap(),
+                LeafCell::new(&mut pager, Key::Integer(7), &[]).unwrap(),
+            ],
+        };
+        leaf.write(&mut pager, page_num).unwrap();
+        assert_eq!(leaf, Node::read(&mut pager, page_num).unwrap());
+        let internal = Node::Internal {
+            kind: TreeKind::Table,
+            cells: vec![InternalCell { child: 4, key: Key::Integer(10) }],
+            right_child: 5,
+        };
+        internal.write(&mut pager, page_num).unwrap();
+        assert_eq!(internal, Node::read(&mut pager, page_num).unwrap());
+    }
+
+    #[test]
+    fn built_tree_has_full_nodes_in_key_order() {
+        let mut pager = test_pager();
+        let root_page = pager.allocate_page().unwrap();
+        let mut builder = TreeBuilder::new(root_page, TreeKind::Table);
+        for key in 0..2000 {
+            builder.push(&mut pager, Key::Integer(key), &[key as u8; 10]).unwrap();
+        }
+        builder.finish(&mut pager).unwrap();
+        // Walks the tree, returning its keys and checking every node
+        // but the root is at least a quarter full.
+        fn walk(pager: &mut pager::Pager, page_num: usize, is_root: bool, keys: &mut Vec<Key>) {
+            let node = Node::read(pager, page_num).unwrap();
+            assert!(is_root || node.size() >= pager.page_size() / 4, "page {page_num} underfull");
+            match node {
+                Node::Leaf { cells, .. } => keys.extend(cells.into_iter().map(|cell| cell.key)),
+                Node::Internal { .. } => {
+                    for index in 0..=node.len() {
+                        walk(pager, node.child(index), false, keys);
+                    }
+                },
+            }
+        }
+        let mut keys = Vec::new();
+        walk(&mut pager, root_page, true, &mut keys);
+        assert_eq!((0..2000).map(Key::Integer).collect::<Vec<Key>>(), keys);
+        // 22 cells of 22 bytes fit in a leaf, so the rows fill 91 leaves
+        // under 3 internal nodes, the root, and the header page.
+        assert_eq!(96, pager.num_pages());
+    }
+
+    #[test]
+    fn large_payload_is_split_across_overflow_pages() {
+        let mut pager = test_pager();
+        let page_num = pager.allocate_page().unwrap();
+        let payload: Vec<u8> = (0..3000).map(|i| (i % 251) as u8).collect();
+        let cell = LeafCell::new(&mut pager, Key::Integer(1), &payload).unwrap();
+        assert!(cell.overflow.is_some());
+        assert!(cell.size() <= LEAF_CELL_HEADER_SIZE + max_local_payload(pager::MIN_PAGE_SIZE));
+        let leaf = Node::Leaf { kind: TreeKind::Table, cells: vec![cell] };
+        leaf.write(&mut pager, page_num).unwrap();
+        let Node::Leaf { mut cells, .. } = Node::read(&mut pager, page_num).unwrap() else {
+            panic!("expected leaf");
+        };
+        assert_eq!(payload, cells.remove(0).read_payload(&mut pager).unwrap());
+    }
+}
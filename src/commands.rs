@@ -1,77 +1,77 @@
 use std::process;
-use crate::table;
-
-const max_string_len: usize = 100;
+use crate::{compiler, database, parser, vm};
 
 pub enum ExitStatus {
     Success = 0,
     Failure,
 }
 
-pub enum StatementType {
-    Insert(table::Row),
-    Select,
-}
-
-pub struct InsertContents {
-    pub id: usize,
-    pub username: String,
-    pub email: String,
-}
-
-impl InsertContents {
-    pub fn new() -> Self {
-        Self { id: 0, username: String::new(), email: String::new() }
-    }
-}
-
-pub fn handle_meta_command(line: &str) -> Result<(), String> {
+pub fn handle_meta_command(line: &str, database: &mut database::Database) -> Result<(), String> {
     let line = line.get(1..line.len()).unwrap();
     match line {
-        "exit" => process::exit(ExitStatus::Success as i32),
+        "exit" => {
+            close_database(database)?;
+            process::exit(ExitStatus::Success as i32)
+        },
+        "tables" => {
+            let schema = database.schema().borrow();
+            let names: Vec<&str> = schema.tables().iter().map(|table| table.name.as_str()).collect();
+            if !names.is_empty() {
+                println!("{}", names.join("  "));
+            }
+            Ok(())
+        },
+        "schema" => {
+            let schema = database.schema().borrow();
+            for table in schema.tables() {
+                println!("{};", table.sql());
+                for index in schema.indexes_of(&table.name) {
+                    println!("{};", index.sql());
+                }
+            }
+            Ok(())
+        },
+        "dbinfo" => {
+            let mut pager = database.pager().borrow_mut();
+            let free_pages = pager.free_page_count().map_err(|e| format!("Error: {e}"))?;
+            println!("database page size:  {}", pager.page_size());
+            println!("database page count: {}", pager.num_pages());
+            println!("freelist page count: {free_pages}");
+            Ok(())
+        },
         _ => Err(format!("unknown command or invalid arguments:  \"{line}\". Enter \".help\" for help")),
     }
 }
 
-pub fn prepare_statement(line: &str) -> Result<StatementType, String> {
-     if line.starts_with("insert") {
-        let print_error = |statement: &str| {
-            let statement = statement.clone();
-            format!("Unable to parse statement {statement}");
-        };
-        let columns = line
-            .split(' ')
-            .count();
-        if columns != 4 {print_error(line)}
-        let mut contents = table::Row::with_max_str_len(max_string_len);
-        let mut elements = line.split(' ').skip(1);
-        match elements.next().unwrap().parse::<usize>() {
-            Ok(value) => contents.id = Some(value),
-            Err(_) => print_error(line),
-        }
-        contents.username = String::from(elements.next().unwrap());
-        contents.email = String::from(elements.next().unwrap());
-        return Ok(StatementType::Insert(contents));        
-    } else if line.starts_with("select") {
-        return Ok(StatementType::Select);
-    } else {
-        return Err(format!("Unrecognised keyword at start of \'{line}\'\n"));
-    }
+/// Parses `line` and compiles it into a program that runs against 
+/// `database`.
+pub fn prepare_statement(line: &str, database: &database::Database) -> Result<vm::Program, String> {
+    let statement = parser::parse(line).map_err(|e| format!("Error: {e}\n"))?;
+    compiler::compile(&statement, database).map_err(|e| format!("Error: {e}.\n"))
 }
 
+/// Runs `program` and prints every row it produces, followed by the
+/// number of rows it changed for statements that report it, or lists
+/// its instructions if it was prepared with `EXPLAIN`.
+pub fn execute_statement(program: vm::Program, database: &mut database::Database) -> Result<(), String> {
+    if program.explain {
+        print!("{program}");
+        return Ok(());
+    }
+    let changes_verb = program.changes_verb;
+    let mut vm = vm::Vm::new(program, database);
+    while let vm::StepResult::Row(values) = vm.step().map_err(|e| format!("Error: {e}."))? {
+        let values: Vec<String> = values.iter().map(|value| value.to_string()).collect();
+        println!("({})", values.join(", "));
+    }
+    if let Some(verb) = changes_verb {
+        let rows = if vm.changes() == 1 { "row" } else { "rows" };
+        println!("{} {rows} {verb}.", vm.changes());
+    }
+    Ok(())
+}
 
-pub fn execute_statement(statement: StatementType, table: &mut table::Table) {
-    match statement {
-        StatementType::Insert(contents) => {
-            table.push(&contents.serialise().unwrap())
-        },
-        StatementType::Select => {
-            for i in 0..table.len() {
-                let row = table.get(i, max_string_len).unwrap();
-                println!(
-                    "({}, {}, {})", row.id.unwrap(), row.username, row.email
-                );
-            }
-        }
-    };
+/// Writes all pending changes in `database` to the database file.
+pub fn close_database(database: &mut database::Database) -> Result<(), String> {
+    database.close().map_err(|e| format!("Error: unable to write database file: {e}"))
 }
\ No newline at end of file
@@ -0,0 +1,2900 @@
+use std::fmt;
+use crate::{aggregate, ast, database, planner, schema::{self, IndexSchema, Schema, TableSchema}, vm::{Instruction, Opcode, Program, P4}};
+
+#[derive(Debug, PartialEq)]
+pub enum CompileError {
+    NoSuchTable(String),
+    NoSuchColumn(String),
+    /// A column name without a table could refer to columns of more
+    /// than one table.
+    AmbiguousColumn(String),
+    /// An `INSERT` gives a different number of values than columns.
+    ValueCountMismatch { values: usize, columns: usize },
+    NoTablesSpecified,
+    TableExists(String),
+    IndexExists(String),
+    DuplicateColumn(String),
+    MultiplePrimaryKeys(String),
+    /// A table name starts with `sqlite_`, which is kept for tables
+    /// used internally.
+    ReservedName(String),
+    /// A statement tries to change a table used internally.
+    ReadOnlyTable(String),
+    /// An `ORDER BY` term, given by its position, refers to a result
+    /// column number past the last column.
+    OrderByOutOfRange { term: usize, columns: usize },
+    NoSuchFunction(String),
+    /// A function is called with a number of arguments it does not take.
+    WrongArgumentCount(String),
+    /// An aggregate function is called where there are no groups of rows,
+    /// such as in `WHERE` or inside another aggregate call.
+    MisuseOfAggregate(String),
+    /// A subquery whose value is used gives more than one column.
+    SubqueryColumns(usize),
+    /// The selects of a compound select give different numbers of
+    /// columns.
+    CompoundColumns(ast::CompoundOp),
+    /// A `WITH` clause names a different number of columns than its
+    /// select gives.
+    CteColumns { name: String, values: usize, columns: usize },
+    /// A table in a `WITH` clause reads itself without a `UNION`.
+    CircularReference(String),
+    /// The statement uses a feature the compiler does not support.
+    Unsupported(&'static str),
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompileError::NoSuchTable(name) => write!(f, "no such table: {name}"),
+            CompileError::NoSuchColumn(name) => write!(f, "no such column: {name}"),
+            CompileError::AmbiguousColumn(name) => write!(f, "ambiguous column name: {name}"),
+            CompileError::ValueCountMismatch { values, columns } => {
+                write!(f, "{values} values for {columns} columns")
+            },
+            CompileError::NoTablesSpecified => write!(f, "no tables specified"),
+            CompileError::TableExists(name) => write!(f, "table {name} already exists"),
+            CompileError::IndexExists(name) => write!(f, "index {name} already exists"),
+            CompileError::DuplicateColumn(name) => write!(f, "duplicate column name: {name}"),
+            CompileError::MultiplePrimaryKeys(table) => {
+                write!(f, "table \"{table}\" has more than one primary key")
+            },
+            CompileError::ReservedName(name) => {
+                write!(f, "object name reserved for internal use: {name}")
+            },
+            CompileError::ReadOnlyTable(name) => write!(f, "table {name} may not be modified"),
+            CompileError::OrderByOutOfRange { term, columns } => {
+                let suffix = match (term % 10, term % 100) {
+                    (1, 1) | (1, 21..) => "st",
+                    (2, 2) | (2, 22..) => "nd",
+                    (3, 3) | (3, 23..) => "rd",
+                    _ => "th",
+                };
+                write!(f, "{term}{suffix} ORDER BY term out of range - should be between 1 and {columns}")
+            },
+            CompileError::NoSuchFunction(name) => write!(f, "no such function: {name}"),
+            CompileError::WrongArgumentCount(name) => {
+                write!(f, "wrong number of arguments to function {name}()")
+            },
+            CompileError::MisuseOfAggregate(name) => write!(f, "misuse of aggregate function {name}()"),
+            CompileError::SubqueryColumns(columns) => write!(f, "sub-select returns {columns} columns - expected 1"),
+            CompileError::CompoundColumns(op) => {
+                write!(f, "SELECTs to the left and right of {op} do not have the same number of result columns")
+            },
+            CompileError::CteColumns { name, values, columns } => {
+                write!(f, "table {name} has {values} values for {columns} columns")
+            },
+            CompileError::CircularReference(name) => write!(f, "circular reference: {name}"),
+            CompileError::Unsupported(feature) => write!(f, "{feature} are not supported"),
+        }
+    }
+}
+
+/// Compiles `statement` into a program that runs against `database`.
+pub fn compile(statement: &ast::Statement, database: &database::Database) -> Result<Program, CompileError> {
+    match statement {
+        ast::Statement::Explain(statement) => {
+            let mut program = compile(statement, database)?;
+            program.explain = true;
+            Ok(program)
+        },
+        ast::Statement::ExplainQueryPlan(statement) => {
+            let plan = compile_statement(statement, database)?.plan;
+            let mut builder = ProgramBuilder::default();
+            let register = builder.alloc_register();
+            for detail in plan {
+                builder.emit(Opcode::String, 0, register, 0, P4::Text(detail), "");
+                builder.emit(Opcode::ResultRow, register, 1, 0, P4::None, "");
+            }
+            builder.emit(Opcode::Halt, 0, 0, 0, P4::None, "");
+            Ok(builder.build())
+        },
+        _ => Ok(compile_statement(statement, database)?.build()),
+    }
+}
+
+/// Compiles a statement that is not `EXPLAIN`, returning the builder so
+/// that its query plan can still be read.
+fn compile_statement(statement: &ast::Statement, database: &database::Database) -> Result<ProgramBuilder, CompileError> {
+    let schema = database.schema().borrow();
+    let mut builder = ProgramBuilder::default();
+    let init = builder.emit(Opcode::Init, 0, 0, 0, P4::None, "");
+    builder.patch_jump(init);
+    builder.instructions[init].comment = format!("Start at {}", builder.current_addr());
+    match statement {
+        ast::Statement::Select(select) => {
+            compile_select(&mut builder, select, Scope::new(&schema), Destination::ResultRow)?;
+        },
+        ast::Statement::Insert(insert) => compile_insert(&mut builder, insert, &schema)?,
+        ast::Statement::Update(update) => compile_update(&mut builder, update, &schema)?,
+        ast::Statement::Delete(delete) => compile_delete(&mut builder, delete, &schema)?,
+        ast::Statement::CreateTable(create) => compile_create_table(&mut builder, create, &schema)?,
+        ast::Statement::CreateIndex(create) => compile_create_index(&mut builder, create, &schema)?,
+        ast::Statement::Analyze(name) => compile_analyze(&mut builder, name.as_deref(), &schema)?,
+        ast::Statement::Vacuum => {
+            builder.emit(Opcode::Vacuum, 0, 0, 0, P4::None, "");
+            builder.emit(Opcode::ParseSchema, 0, 0, 0, P4::None, "");
+        },
+        ast::Statement::Explain(_) | ast::Statement::ExplainQueryPlan(_) => {
+            unreachable!("the parser rejects EXPLAIN of an EXPLAIN");
+        },
+    }
+    builder.emit(Opcode::Halt, 0, 0, 0, P4::None, "");
+    Ok(builder)
+}
+
+/// Compiles `select` as a statement of its own if `outer` has no tables,
+/// or as a subquery of an expression in `outer` otherwise, sending its
+/// rows to `destination`. Returns the names of the result columns.
+fn compile_select(builder: &mut ProgramBuilder, select: &ast::Select, outer: Scope, destination: Destination) -> Result<Vec<String>, CompileError> {
+    compile_with(builder, select, outer, |builder, scope| {
+        if select.compound.is_empty() {
+            compile_simple_select(builder, select, scope, destination)
+        } else {
+            compile_compound(builder, select, scope, destination, None)
+        }
+    })
+}
+
+/// Emits code that fills an ephemeral table with the rows of each table
+/// in the `WITH` clause of `select`, in turn, and then the code emitted
+/// by `body`, which is given the scope where those tables are defined
+/// inside `outer`.
+fn compile_with<T>(
+    builder: &mut ProgramBuilder,
+    select: &ast::Select,
+    outer: Scope,
+    body: impl FnOnce(&mut ProgramBuilder, Scope) -> Result<T, CompileError>,
+) -> Result<T, CompileError> {
+    let mut ctes = Vec::new();
+    for cte in &select.with {
+        let cursor = builder.alloc_cursor();
+        // Each table can read the ones before it.
+        let scope = Scope { ctes: &ctes, ..outer.nested(&[]) };
+        let kind = format!("MATERIALIZE {}", cte.name);
+        let schema = emit_once_if_uncorrelated(builder, scope, &kind, |builder| {
+            builder.emit(Opcode::OpenEphemeral, cursor, 0, 0, P4::None, "");
+            compile_cte(builder, cte, scope, cursor)
+        })?;
+        ctes.push(CteTable { schema, cursor });
+    }
+    body(builder, Scope { ctes: &ctes, ..outer.nested(&[]) })
+}
+
+/// Emits code that adds the rows of `cte`, whose select is in `scope`, to
+/// the ephemeral table at `cursor`, and returns the schema of the table.
+fn compile_cte(builder: &mut ProgramBuilder, cte: &ast::Cte, scope: Scope, cursor: usize) -> Result<TableSchema, CompileError> {
+    let select = &cte.select;
+    let destination = Destination::Table { cursor, set: None };
+    let names = compile_with(builder, select, scope, |builder, scope| {
+        if !select.compound.is_empty() {
+            return compile_compound(builder, select, scope, destination, Some(cte));
+        }
+        if select.from.iter().any(|join| join.table.eq_ignore_ascii_case(&cte.name)) {
+            return Err(CompileError::CircularReference(cte.name.clone()));
+        }
+        compile_simple_select(builder, select, scope, destination)
+    })?;
+    cte_schema(cte, names)
+}
+
+/// Returns the schema of the table `cte` for a select whose result
+/// columns are named `names`.
+///
+/// # Errors
+///
+/// Returns an error if `cte` names a different number of columns.
+fn cte_schema(cte: &ast::Cte, names: Vec<String>) -> Result<TableSchema, CompileError> {
+    let names = if cte.columns.is_empty() {
+        names
+    } else if cte.columns.len() == names.len() {
+        cte.columns.clone()
+    } else {
+        return Err(CompileError::CteColumns { name: cte.name.clone(), values: names.len(), columns: cte.columns.len() });
+    };
+    let columns = names.into_iter()
+        .map(|name| ast::ColumnDef { name, type_name: None, primary_key: false, not_null: false })
+        .collect();
+    Ok(TableSchema { name: cte.name.clone(), root_page: 0, columns, row_count: None })
+}
+
+/// Emits code that sends the rows of the compound `select`, in `scope`,
+/// to `destination`, and returns the names of its result columns.
+///
+/// The rows of each select are added to a queue, and then taken from
+/// the queue in turn and sent on. If `select` defines the table `cte`,
+/// the selects that read `cte` are run again for each row taken, with
+/// the table holding just that row, and add the rows they find to the
+/// queue. Rows a `UNION` leaves out are found in an ephemeral index of
+/// the rows added.
+fn compile_compound(
+    builder: &mut ProgramBuilder,
+    select: &ast::Select,
+    scope: Scope,
+    destination: Destination,
+    cte: Option<&ast::Cte>,
+) -> Result<Vec<String>, CompileError> {
+    if !select.order_by.is_empty() {
+        return Err(CompileError::Unsupported("ORDER BY clauses on compound SELECTs"));
+    }
+    // The first select's LIMIT applies to the rows of all of them.
+    let first = ast::Select { with: Vec::new(), compound: Vec::new(), limit: None, ..select.clone() };
+    let selects: Vec<(ast::CompoundOp, &ast::Select)> = std::iter::once((ast::CompoundOp::UnionAll, &first))
+        .chain(select.compound.iter().map(|(op, select)| (*op, select)))
+        .collect();
+    // A UNION leaves out rows equal to any row of the selects before it,
+    // so each select up to the last UNION leaves out repeated rows.
+    let last_union = selects.iter().rposition(|(op, _)| *op == ast::CompoundOp::Union);
+    let distinct = |i: usize| last_union.is_some_and(|last| i <= last);
+    let reads_cte = |select: &ast::Select| {
+        cte.is_some_and(|cte| select.from.iter().any(|join| join.table.eq_ignore_ascii_case(&cte.name)))
+    };
+
+    let queue = builder.alloc_cursor();
+    builder.emit(Opcode::OpenEphemeral, queue, 0, 0, P4::None, "");
+    let set = last_union.map(|_| {
+        let set = builder.alloc_cursor();
+        builder.emit(Opcode::OpenEphemeral, set, 0, 1, P4::None, "");
+        set
+    });
+    let mut names: Option<Vec<String>> = None;
+    for (i, (op, select)) in selects.iter().enumerate().filter(|(_, (_, select))| !reads_cte(select)) {
+        let destination = Destination::Table { cursor: queue, set: set.filter(|_| distinct(i)) };
+        let select_names = compile_simple_select(builder, select, scope, destination)?;
+        match names.as_ref().map(Vec::len) {
+            None => names = Some(select_names),
+            Some(width) if width != select_names.len() => return Err(CompileError::CompoundColumns(*op)),
+            Some(_) => {},
+        }
+    }
+    let Some(names) = names else {
+        let name = cte.map_or("", |cte| &cte.name);
+        return Err(CompileError::CircularReference(name.to_string()));
+    };
+    destination.check_width(names.len())?;
+
+    let mut end_jumps = Vec::new();
+    let (limit, offset) = match &select.limit {
+        Some(limit) => compile_limit(builder, limit, scope, &mut end_jumps)?,
+        None => (None, None),
+    };
+    let width = names.len();
+    let position = builder.alloc_register();
+    let one = builder.alloc_register();
+    let row = builder.alloc_registers(width);
+    builder.emit(Opcode::Integer, 0, position, 0, P4::Integer(1), "");
+    builder.emit(Opcode::Integer, 0, one, 0, P4::Integer(1), "");
+    let loop_start = builder.current_addr();
+    end_jumps.push(builder.emit(Opcode::SeekRowid, queue, 0, position, P4::None, ""));
+    for i in 0..width {
+        builder.emit(Opcode::Column, queue, i, row + i, P4::None, "");
+    }
+    builder.emit(Opcode::Add, position, one, position, P4::None, "");
+    let mut next_jumps = Vec::new();
+    if let Some(offset) = offset {
+        next_jumps.push(builder.emit(Opcode::IfPos, offset, 0, 1, P4::None, ""));
+    }
+    destination.emit(builder, row, width, &mut next_jumps, &mut end_jumps);
+    if let (Some(limit), false) = (limit, destination.first_row_only()) {
+        end_jumps.push(builder.emit(Opcode::DecrJumpZero, limit, 0, 0, P4::None, ""));
+    }
+    next_jumps.into_iter().for_each(|addr| builder.patch_jump(addr));
+
+    if let Some(cte) = cte.filter(|_| selects.iter().any(|(_, select)| reads_cte(select))) {
+        // The selects that read the table see only the row just taken.
+        let current = builder.alloc_cursor();
+        let record = builder.alloc_register();
+        let key = builder.alloc_register();
+        builder.emit(Opcode::OpenEphemeral, current, 0, 0, P4::None, "");
+        builder.emit(Opcode::MakeRecord, row, width, record, P4::None, "");
+        builder.emit(Opcode::Integer, 0, key, 0, P4::Integer(1), "");
+        builder.emit(Opcode::Insert, current, record, key, P4::None, &cte.name);
+        let table = [CteTable { schema: cte_schema(cte, names.clone())?, cursor: current }];
+        let recursive_scope = Scope { ctes: &table, ..scope.nested(&[]) };
+        for (i, (op, select)) in selects.iter().enumerate().filter(|(_, (_, select))| reads_cte(select)) {
+            let destination = Destination::Table { cursor: queue, set: set.filter(|_| distinct(i)) };
+            let select_names = compile_simple_select(builder, select, recursive_scope, destination)?;
+            if select_names.len() != width {
+                return Err(CompileError::CompoundColumns(*op));
+            }
+        }
+    }
+    builder.emit(Opcode::Goto, 0, loop_start, 0, P4::None, "");
+    end_jumps.into_iter().for_each(|addr| builder.patch_jump(addr));
+    Ok(names)
+}
+
+/// Compiles a select without `WITH` or `UNION`, as for [`compile_select`].
+fn compile_simple_select(builder: &mut ProgramBuilder, select: &ast::Select, outer: Scope, destination: Destination) -> Result<Vec<String>, CompileError> {
+    let schema = outer.schema;
+    let mut tables = Vec::new();
+    // Where each table's rows are read from, if it is defined by `WITH`.
+    let mut ephemeral = Vec::new();
+    for join in &select.from {
+        let (table, cursor) = match outer.cte(&join.table) {
+            Some(cte) => (&cte.schema, Some(cte.cursor)),
+            None => (schema.table(&join.table).ok_or_else(|| CompileError::NoSuchTable(join.table.clone()))?, None),
+        };
+        tables.push(planner::TableRef { name: join.alias.as_deref().unwrap_or(&table.name), schema: table });
+        ephemeral.push(cursor);
+    }
+    // Result columns and their aliases, with `*` expanded.
+    let mut columns: Vec<(ast::Expr, Option<&str>)> = Vec::new();
+    for column in &select.columns {
+        let expanded = match column {
+            ast::ResultColumn::All if tables.is_empty() => return Err(CompileError::NoTablesSpecified),
+            ast::ResultColumn::All => tables.as_slice(),
+            ast::ResultColumn::TableAll(name) => {
+                match tables.iter().position(|table| table.name.eq_ignore_ascii_case(name)) {
+                    Some(i) => &tables[i..=i],
+                    None => return Err(CompileError::NoSuchTable(name.clone())),
+                }
+            },
+            ast::ResultColumn::Expr { expr, alias } => {
+                columns.push((expr.clone(), alias.as_deref()));
+                continue;
+            },
+        };
+        for table in expanded {
+            columns.extend(table.schema.columns.iter().map(|column| {
+                (ast::Expr::Column { table: Some(String::from(table.name)), name: column.name.clone() }, None)
+            }));
+        }
+    }
+    destination.check_width(columns.len())?;
+    let order_by = select.order_by
+        .iter()
+        .enumerate()
+        .map(|(i, term)| resolve_ordering_term(&term.expr, i, &columns))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut aggregates = Vec::new();
+    for expr in columns.iter().map(|(expr, _)| expr).chain(&select.having).chain(&order_by) {
+        collect_aggregates(expr, &mut aggregates)?;
+    }
+
+    let mut end_jumps = Vec::new();
+    let (limit, offset) = match &select.limit {
+        Some(limit) => compile_limit(builder, limit, outer, &mut end_jumps)?,
+        None => (None, None),
+    };
+    let sorter = if order_by.is_empty() {
+        None
+    } else {
+        let sorter = builder.alloc_cursor();
+        let order = select.order_by.iter().map(|term| if term.descending { '-' } else { '+' }).collect();
+        builder.emit(Opcode::SorterOpen, sorter, order_by.len(), 0, P4::Text(order), "");
+        Some(sorter)
+    };
+    let output = Output { columns: &columns, order_by: &order_by, sorter, limit, offset, destination };
+    // The WHERE clause and the ON conditions of inner joins hold for
+    // every row output, so any table can be looked up with them. A table
+    // on the right of a LEFT JOIN is only looked up with its own ON
+    // condition, since its row is NULL where that finds nothing.
+    let ons = select.from.iter().filter(|join| join.kind == ast::JoinKind::Inner).filter_map(|join| join.on.as_ref());
+    let inner_conditions: Vec<&ast::Expr> = select.where_clause.iter().chain(ons).collect();
+    let mut loops = Vec::new();
+    for (i, join) in select.from.iter().enumerate() {
+        let exprs = columns.iter().map(|(expr, _)| expr)
+            .chain(&select.where_clause)
+            .chain(select.from.iter().filter_map(|join| join.on.as_ref()))
+            .chain(&select.group_by)
+            .chain(&select.having)
+            .chain(&order_by);
+        let used_columns = planner::used_columns(&tables, i, exprs);
+        let conditions = match join.kind {
+            ast::JoinKind::Inner => inner_conditions.clone(),
+            ast::JoinKind::Left => join.on.iter().collect(),
+        };
+        let indexes = match ephemeral[i] {
+            Some(_) => Vec::new(),
+            None => schema.indexes_of(&tables[i].schema.name),
+        };
+        let mut scan = TableLoop::open(builder, &tables, i, &indexes, ephemeral[i], &conditions, &used_columns);
+        scan.on = join.on.as_ref();
+        scan.left_join = join.kind == ast::JoinKind::Left;
+        loops.push(scan);
+    }
+    if aggregates.is_empty() && select.group_by.is_empty() && select.having.is_none() {
+        let sources = sources(&loops);
+        emit_scan(builder, &loops, outer, select.where_clause.as_ref(), |builder, next_jumps| {
+            output.emit(builder, outer.nested(&sources), next_jumps, &mut end_jumps)
+        })?;
+    } else {
+        compile_aggregate(builder, select, &output, &loops, outer, &aggregates, &mut end_jumps)?;
+    }
+    if let Some(sorter) = sorter {
+        builder.note(String::from("USE TEMP B-TREE FOR ORDER BY"));
+        end_jumps.push(builder.emit(Opcode::SorterSort, sorter, 0, 0, P4::None, ""));
+        let loop_start = builder.current_addr();
+        let registers = builder.alloc_registers(columns.len());
+        let mut next_jumps = Vec::new();
+        output.emit_row(builder, registers, &mut next_jumps, &mut end_jumps, |builder| {
+            for i in 0..columns.len() {
+                builder.emit(Opcode::Column, sorter, order_by.len() + i, registers + i, P4::None, "");
+            }
+            Ok(())
+        })?;
+        next_jumps.into_iter().for_each(|addr| builder.patch_jump(addr));
+        builder.emit(Opcode::SorterNext, sorter, loop_start, 0, P4::None, "");
+    }
+    end_jumps.into_iter().for_each(|addr| builder.patch_jump(addr));
+    let names = columns.iter().enumerate().map(|(i, (expr, alias))| match (alias, expr) {
+        (Some(alias), _) => alias.to_string(),
+        (None, ast::Expr::Column { name, .. }) => name.clone(),
+        (None, _) => format!("column{}", i + 1),
+    });
+    Ok(names.collect())
+}
+
+/// A loop over the rows of a table found by a [`planner::Plan`], with
+/// the cursors it reads them from.
+struct TableLoop<'a> {
+    plan: planner::Plan<'a>,
+    /// Cursor on the table, unless the plan reads a covering index
+    /// instead.
+    cursor: Option<usize>,
+    /// Cursor on the index the plan reads, if any.
+    index_cursor: Option<usize>,
+    /// Condition checked for each row before the loops inside this one
+    /// run, which is the `ON` condition of the table's join.
+    on: Option<&'a ast::Expr>,
+    /// Whether the loop inside runs once with a row of NULLs when no row
+    /// meets the `ON` condition, for the right side of a `LEFT JOIN`.
+    left_join: bool,
+}
+
+impl<'a> TableLoop<'a> {
+    /// Plans how to find the rows of the table at `position` in
+    /// `tables` where `conditions` hold, using `indexes`, for a statement
+    /// that reads `used_columns` of it, and emits code that opens the
+    /// cursors the plan needs. A table defined by `WITH` is read through
+    /// a new cursor on its `ephemeral` table.
+    fn open(
+        builder: &mut ProgramBuilder,
+        tables: &[planner::TableRef<'a>],
+        position: usize,
+        indexes: &[&'a IndexSchema],
+        ephemeral: Option<usize>,
+        conditions: &[&'a ast::Expr],
+        used_columns: &[usize],
+    ) -> Self {
+        let table = tables[position].schema;
+        let plan = planner::plan(tables, position, indexes, conditions, used_columns);
+        builder.note(plan.to_string());
+        let (needs_table, index) = match &plan.access {
+            planner::Access::Index { index, covering, .. } => (!covering, Some(*index)),
+            _ => (true, None),
+        };
+        let cursor = needs_table.then(|| {
+            let cursor = builder.alloc_cursor();
+            match ephemeral {
+                Some(ephemeral) => builder.emit(Opcode::OpenDup, cursor, ephemeral, 0, P4::None, &table.name),
+                None => builder.emit(Opcode::OpenRead, cursor, table.root_page, 0, P4::None, &table.name),
+            };
+            cursor
+        });
+        let index_cursor = index.map(|index| {
+            let cursor = builder.alloc_cursor();
+            builder.emit(Opcode::OpenRead, cursor, index.root_page, 0, P4::None, &index.name);
+            cursor
+        });
+        Self { plan, cursor, index_cursor, on: None, left_join: false }
+    }
+
+    /// Where the current row of the loop is read from.
+    fn source(&self) -> Source<'_> {
+        let row = match (&self.plan.access, self.cursor, self.index_cursor) {
+            (planner::Access::Index { columns, covering: true, .. }, _, Some(cursor)) => RowSource::Index { cursor, columns },
+            (_, Some(cursor), _) => RowSource::Cursor(cursor),
+            _ => unreachable!("a loop reads from its table unless an index covers it"),
+        };
+        Source { table: self.plan.table, row }
+    }
+}
+
+/// Returns where the current row of each of `loops` is read from.
+fn sources<'a>(loops: &'a [TableLoop]) -> Vec<Source<'a>> {
+    loops.iter().map(TableLoop::source).collect()
+}
+
+/// Emits nested loops over the rows found by `loops`, the first one
+/// outermost, or a single pass if there are none, that run the code
+/// emitted by `body` for each combination of rows where the `ON`
+/// condition of each loop and `condition` hold. The loops are in the
+/// scope `outer`, and `body` is given a vector to add the addresses of
+/// jumps to the next row to.
+fn emit_scan(
+    builder: &mut ProgramBuilder,
+    loops: &[TableLoop],
+    outer: Scope,
+    condition: Option<&ast::Expr>,
+    body: impl FnOnce(&mut ProgramBuilder, &mut Vec<usize>) -> Result<(), CompileError>,
+) -> Result<(), CompileError> {
+    emit_nested_loop(builder, loops, &sources(loops), 0, outer, condition, body)
+}
+
+/// Emits the loop at `depth` in `loops` and the loops inside it for
+/// [`emit_scan`], where `sources` are where the row of each loop is
+/// read from.
+///
+/// The loop of a `LEFT JOIN` counts the rows that meet its `ON`
+/// condition. If there are none once it ends, its cursors are set to a
+/// row of NULLs and the code inside the loop runs once more, after
+/// which the row count makes the loop end.
+fn emit_nested_loop(
+    builder: &mut ProgramBuilder,
+    loops: &[TableLoop],
+    sources: &[Source],
+    depth: usize,
+    outer: Scope,
+    condition: Option<&ast::Expr>,
+    body: impl FnOnce(&mut ProgramBuilder, &mut Vec<usize>) -> Result<(), CompileError>,
+) -> Result<(), CompileError> {
+    let mut next_jumps = Vec::new();
+    let Some(scan) = loops.get(depth) else {
+        if let Some(condition) = condition {
+            let register = builder.alloc_register();
+            compile_expr(builder, condition, outer.nested(sources), register)?;
+            next_jumps.push(builder.emit(Opcode::IfNot, register, 0, 0, P4::None, ""));
+        }
+        body(builder, &mut next_jumps)?;
+        next_jumps.into_iter().for_each(|addr| builder.patch_jump(addr));
+        return Ok(());
+    };
+    let matches = scan.left_join.then(|| {
+        let matches = builder.alloc_register();
+        builder.emit(Opcode::Integer, 0, matches, 0, P4::Integer(0), "");
+        matches
+    });
+    let (loop_cursor, end_jumps, loop_start) = emit_loop_start(builder, scan, outer.nested(&sources[..depth]), &mut next_jumps)?;
+    if let Some(on) = scan.on {
+        let register = builder.alloc_register();
+        compile_expr(builder, on, outer.nested(&sources[..=depth]), register)?;
+        next_jumps.push(builder.emit(Opcode::IfNot, register, 0, 0, P4::None, ""));
+    }
+    let inner_start = builder.current_addr();
+    if let Some(matches) = matches {
+        builder.emit(Opcode::Integer, 0, matches, 0, P4::Integer(1), "");
+    }
+    emit_nested_loop(builder, loops, sources, depth + 1, outer, condition, body)?;
+    next_jumps.into_iter().for_each(|addr| builder.patch_jump(addr));
+    if let Some(loop_cursor) = loop_cursor {
+        builder.emit(Opcode::Next, loop_cursor, loop_start, 0, P4::None, "");
+    }
+    end_jumps.into_iter().for_each(|addr| builder.patch_jump(addr));
+    if let Some(matches) = matches {
+        let matched = builder.emit(Opcode::IfPos, matches, 0, 0, P4::None, "");
+        for cursor in scan.cursor.iter().chain(&scan.index_cursor) {
+            builder.emit(Opcode::NullRow, *cursor, 0, 0, P4::None, "");
+        }
+        builder.emit(Opcode::Goto, 0, inner_start, 0, P4::None, "");
+        builder.patch_jump(matched);
+    }
+    Ok(())
+}
+
+/// Emits code that moves the cursors of `scan` to its first row, and
+/// at the start of each row, checks it is still in the range the plan
+/// reads and moves the table cursor to the row an index entry refers
+/// to. Values the plan looks up are computed in `outer`, the scope of
+/// the loops outside this one. Returns the cursor to advance to the
+/// next row, if there can be one, the addresses of jumps to the end of
+/// the loop, and the address each row starts at. Jumps that skip a row
+/// are added to `next_jumps`.
+fn emit_loop_start(builder: &mut ProgramBuilder, scan: &TableLoop, outer: Scope, next_jumps: &mut Vec<usize>) -> Result<(Option<usize>, Vec<usize>, usize), CompileError> {
+    match (&scan.plan.access, scan.cursor, scan.index_cursor) {
+        (planner::Access::FullScan, Some(cursor), _) => {
+            let rewind = builder.emit(Opcode::Rewind, cursor, 0, 0, P4::None, "");
+            Ok((Some(cursor), vec![rewind], builder.current_addr()))
+        },
+        (planner::Access::RowidEq(value), Some(cursor), _) => {
+            let rowid = builder.alloc_register();
+            compile_expr(builder, value, outer, rowid)?;
+            let seek = builder.emit(Opcode::SeekRowid, cursor, 0, rowid, P4::None, "");
+            Ok((None, vec![seek], builder.current_addr()))
+        },
+        (planner::Access::Index { equal, lower, upper, covering, .. }, cursor, Some(index_cursor)) => {
+            // The first key holds the values of the columns constrained
+            // to be equal and the lower bound, and the end key the same
+            // values with the upper bound.
+            let num_equal = equal.len();
+            let key = builder.alloc_registers(num_equal + 1);
+            for (i, value) in equal.iter().enumerate() {
+                compile_expr(builder, value, outer, key + i)?;
+            }
+            if let Some(lower) = lower {
+                compile_expr(builder, lower.value, outer, key + num_equal)?;
+            }
+            let end_key = match upper {
+                Some(upper) => {
+                    let end_key = builder.alloc_registers(num_equal + 1);
+                    if num_equal > 0 {
+                        builder.emit(Opcode::Copy, key, end_key, num_equal, P4::None, "");
+                    }
+                    compile_expr(builder, upper.value, outer, end_key + num_equal)?;
+                    Some((end_key, num_equal + 1))
+                },
+                None => (num_equal > 0).then_some((key, num_equal)),
+            };
+            let num_start = num_equal + usize::from(lower.is_some());
+            let start = if num_start > 0 {
+                builder.emit(Opcode::SeekGe, index_cursor, 0, key, P4::Integer(num_start as i64), "")
+            } else {
+                builder.emit(Opcode::Rewind, index_cursor, 0, 0, P4::None, "")
+            };
+            let loop_start = builder.current_addr();
+            let mut end_jumps = vec![start];
+            if let Some((end_key, count)) = end_key {
+                end_jumps.push(builder.emit(Opcode::IdxGt, index_cursor, 0, end_key, P4::Integer(count as i64), ""));
+            }
+            if let (false, Some(cursor)) = (covering, cursor) {
+                let rowid = builder.alloc_register();
+                builder.emit(Opcode::IdxRowid, index_cursor, rowid, 0, P4::None, "");
+                next_jumps.push(builder.emit(Opcode::NotExists, cursor, 0, rowid, P4::None, ""));
+            }
+            Ok((Some(index_cursor), end_jumps, loop_start))
+        },
+        _ => unreachable!("loops open the cursors their plans read"),
+    }
+}
+
+/// Emits code that computes `aggregates` over each group of the rows in
+/// `loops`, which are in the scope `outer`, with equal `GROUP BY`
+/// values, or over every row if there is no `GROUP BY`, and outputs a
+/// row for each group where the `HAVING` condition holds.
+///
+/// Rows are grouped by sorting them on the `GROUP BY` values. Columns
+/// used outside aggregate calls are read from a copy of the last row of
+/// the group.
+fn compile_aggregate(
+    builder: &mut ProgramBuilder,
+    select: &ast::Select,
+    output: &Output,
+    loops: &[TableLoop],
+    outer: Scope,
+    aggregates: &[ast::Expr],
+    end_jumps: &mut Vec<usize>,
+) -> Result<(), CompileError> {
+    let scan_sources = sources(loops);
+    let scan_scope = outer.nested(&scan_sources);
+    // The copy of the rows holds each table's row in turn.
+    let row_width: usize = loops.iter().map(|scan| scan.plan.table.schema.columns.len() + 1).sum();
+    let row = builder.alloc_registers(row_width);
+    let mut group_sources = Vec::new();
+    let mut offset = row;
+    for scan in loops {
+        group_sources.push(Source { table: scan.plan.table, row: RowSource::Registers(offset) });
+        offset += scan.plan.table.schema.columns.len() + 1;
+    }
+    let group_scope = outer.nested(&group_sources);
+    let results = builder.alloc_registers(aggregates.len());
+    let output_scope = Scope { aggregates: Some((aggregates, results)), ..group_scope };
+    for i in 0..aggregates.len() {
+        builder.emit(Opcode::Null, 0, results + i, 0, P4::None, "");
+    }
+    // Copies the scanned rows into `registers`.
+    let copy_row = |builder: &mut ProgramBuilder, mut registers: usize| {
+        for source in &scan_sources {
+            let table = source.table.schema;
+            for i in 0..table.columns.len() {
+                emit_column(builder, table, source.row, Some(i), registers + i);
+            }
+            emit_column(builder, table, source.row, None, registers + table.columns.len());
+            registers += table.columns.len() + 1;
+        }
+    };
+
+    if select.group_by.is_empty() {
+        emit_scan(builder, loops, outer, select.where_clause.as_ref(), |builder, _| {
+            copy_row(builder, row);
+            emit_agg_steps(builder, aggregates, group_scope, results)
+        })?;
+        return emit_group_output(builder, select.having.as_ref(), output, output_scope, end_jumps);
+    }
+
+    // Sorter records hold the GROUP BY values followed by the row.
+    let num_keys = select.group_by.len();
+    let groups = builder.alloc_cursor();
+    builder.emit(Opcode::SorterOpen, groups, num_keys, 0, P4::Text("+".repeat(num_keys)), "");
+    builder.note(String::from("USE TEMP B-TREE FOR GROUP BY"));
+    emit_scan(builder, loops, outer, select.where_clause.as_ref(), |builder, _| {
+        let registers = builder.alloc_registers(num_keys + row_width);
+        for (i, expr) in select.group_by.iter().enumerate() {
+            compile_expr(builder, expr, scan_scope, registers + i)?;
+        }
+        copy_row(builder, registers + num_keys);
+        let record = builder.alloc_register();
+        builder.emit(Opcode::MakeRecord, registers, num_keys + row_width, record, P4::None, "");
+        builder.emit(Opcode::SorterInsert, groups, record, 0, P4::None, "");
+        Ok(())
+    })?;
+
+    let keys = builder.alloc_registers(num_keys);
+    let previous_keys = builder.alloc_registers(num_keys);
+    let is_first_row = builder.alloc_register();
+    let changed = builder.alloc_register();
+    let return_addr = builder.alloc_register();
+    builder.emit(Opcode::Integer, 0, is_first_row, 0, P4::Integer(1), "");
+    end_jumps.push(builder.emit(Opcode::SorterSort, groups, 0, 0, P4::None, ""));
+    let loop_start = builder.current_addr();
+    for i in 0..num_keys {
+        builder.emit(Opcode::Column, groups, i, keys + i, P4::None, "");
+    }
+    let mut same_group_jumps = vec![builder.emit(Opcode::If, is_first_row, 0, 0, P4::None, "")];
+    let mut changed_jumps = Vec::new();
+    for i in 0..num_keys {
+        builder.emit(Opcode::IsNot, keys + i, previous_keys + i, changed, P4::None, "");
+        changed_jumps.push(builder.emit(Opcode::If, changed, 0, 0, P4::None, ""));
+    }
+    same_group_jumps.push(builder.emit(Opcode::Goto, 0, 0, 0, P4::None, ""));
+    changed_jumps.into_iter().for_each(|addr| builder.patch_jump(addr));
+    // The row starts a new group, so output the last one first.
+    let mut output_calls = vec![builder.emit(Opcode::Gosub, return_addr, 0, 0, P4::None, "output group")];
+    for i in 0..aggregates.len() {
+        builder.emit(Opcode::Null, 0, results + i, 0, P4::None, "");
+    }
+    same_group_jumps.into_iter().for_each(|addr| builder.patch_jump(addr));
+    builder.emit(Opcode::Integer, 0, is_first_row, 0, P4::Integer(0), "");
+    builder.emit(Opcode::Copy, keys, previous_keys, num_keys, P4::None, "");
+    for i in 0..row_width {
+        builder.emit(Opcode::Column, groups, num_keys + i, row + i, P4::None, "");
+    }
+    emit_agg_steps(builder, aggregates, group_scope, results)?;
+    builder.emit(Opcode::SorterNext, groups, loop_start, 0, P4::None, "");
+    output_calls.push(builder.emit(Opcode::Gosub, return_addr, 0, 0, P4::None, "output final group"));
+    let done = builder.emit(Opcode::Goto, 0, 0, 0, P4::None, "");
+
+    output_calls.into_iter().for_each(|addr| builder.patch_jump(addr));
+    emit_group_output(builder, select.having.as_ref(), output, output_scope, end_jumps)?;
+    builder.emit(Opcode::Return, return_addr, 0, 0, P4::None, "");
+    builder.patch_jump(done);
+    Ok(())
+}
+
+/// Emits code that adds the row in `scope` to each of `aggregates`,
+/// accumulated in the registers starting at `results`.
+fn emit_agg_steps(builder: &mut ProgramBuilder, aggregates: &[ast::Expr], scope: Scope, results: usize) -> Result<(), CompileError> {
+    for (i, call) in aggregates.iter().enumerate() {
+        let ast::Expr::Function { name, args } = call else {
+            unreachable!("aggregates are only collected from function calls");
+        };
+        let function = aggregate_function(name, args)?;
+        let registers = builder.alloc_registers(args.len());
+        for (j, arg) in args.iter().enumerate() {
+            compile_expr(builder, arg, scope, registers + j)?;
+        }
+        builder.emit(Opcode::AggStep, registers, args.len(), results + i, P4::Aggregate(function), "");
+    }
+    Ok(())
+}
+
+/// Emits code that finishes the aggregates in `scope` and outputs the
+/// group's row if `having` holds.
+fn emit_group_output(
+    builder: &mut ProgramBuilder,
+    having: Option<&ast::Expr>,
+    output: &Output,
+    scope: Scope,
+    end_jumps: &mut Vec<usize>,
+) -> Result<(), CompileError> {
+    if let Some((aggregates, results)) = scope.aggregates {
+        for (i, call) in aggregates.iter().enumerate() {
+            if let ast::Expr::Function { name, args } = call {
+                let function = aggregate_function(name, args)?;
+                builder.emit(Opcode::AggFinal, results + i, args.len(), 0, P4::Aggregate(function), "");
+            }
+        }
+    }
+    let mut skip_jumps = Vec::new();
+    if let Some(having) = having {
+        let register = builder.alloc_register();
+        compile_expr(builder, having, scope, register)?;
+        skip_jumps.push(builder.emit(Opcode::IfNot, register, 0, 0, P4::None, ""));
+    }
+    output.emit(builder, scope, &mut skip_jumps, end_jumps)?;
+    skip_jumps.into_iter().for_each(|addr| builder.patch_jump(addr));
+    Ok(())
+}
+
+/// Where the rows of a `SELECT` go once computed.
+struct Output<'a> {
+    columns: &'a [(ast::Expr, Option<&'a str>)],
+    order_by: &'a [ast::Expr],
+    /// Cursor of the sorter rows are added to when there is an
+    /// `ORDER BY`, before being output in order.
+    sorter: Option<usize>,
+    limit: Option<usize>,
+    offset: Option<usize>,
+    destination: Destination,
+}
+
+/// What a `SELECT` does with the rows it outputs.
+#[derive(Clone, Copy)]
+enum Destination {
+    /// Return each row as a result row of the statement.
+    ResultRow,
+    /// Store the only column of the first row in the register, for a
+    /// subquery used as a value.
+    Value(usize),
+    /// Store 1 in the register at the first row, for `EXISTS`.
+    Exists(usize),
+    /// Add the only column of each row to the ephemeral index at the
+    /// cursor, once, for `IN`.
+    Set(usize),
+    /// Add each row to the ephemeral table at `cursor`, for a table
+    /// defined by `WITH` or the selects of a compound select. With a
+    /// `set`, rows already in that ephemeral index are left out and the
+    /// others are added to it, for `UNION`.
+    Table { cursor: usize, set: Option<usize> },
+}
+
+impl Destination {
+    /// Returns whether only the first row is needed.
+    fn first_row_only(self) -> bool {
+        matches!(self, Destination::Value(_) | Destination::Exists(_))
+    }
+
+    /// Checks that rows of `width` columns can be sent here.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the destination takes one column and `width`
+    /// is not 1.
+    fn check_width(self, width: usize) -> Result<(), CompileError> {
+        match self {
+            Destination::Value(_) | Destination::Set(_) if width != 1 => Err(CompileError::SubqueryColumns(width)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Emits code that sends the `width` columns in the registers from
+    /// `registers` here. The addresses of jumps to the next row and to
+    /// the end are added to `next_jumps` and `end_jumps`.
+    fn emit(self, builder: &mut ProgramBuilder, registers: usize, width: usize, next_jumps: &mut Vec<usize>, end_jumps: &mut Vec<usize>) {
+        match self {
+            Destination::ResultRow => {
+                builder.emit(Opcode::ResultRow, registers, width, 0, P4::None, "");
+            },
+            Destination::Value(register) => {
+                builder.emit(Opcode::Copy, registers, register, 1, P4::None, "");
+                end_jumps.push(builder.emit(Opcode::Goto, 0, 0, 0, P4::None, ""));
+            },
+            Destination::Exists(register) => {
+                builder.emit(Opcode::Integer, 0, register, 0, P4::Integer(1), "");
+                end_jumps.push(builder.emit(Opcode::Goto, 0, 0, 0, P4::None, ""));
+            },
+            Destination::Set(cursor) => {
+                let present = emit_set_insert(builder, cursor, registers, width);
+                builder.patch_jump(present);
+            },
+            Destination::Table { cursor, set } => {
+                if let Some(set) = set {
+                    next_jumps.push(emit_set_insert(builder, set, registers, width));
+                }
+                let record = builder.alloc_register();
+                let key = builder.alloc_register();
+                builder.emit(Opcode::MakeRecord, registers, width, record, P4::None, "");
+                builder.emit(Opcode::NewRowid, cursor, key, 0, P4::None, "");
+                builder.emit(Opcode::Insert, cursor, record, key, P4::None, "");
+            },
+        }
+    }
+}
+
+impl Output<'_> {
+    /// Emits code that computes the result columns in `scope` and either
+    /// adds them to the sorter or outputs them as a result row. The
+    /// addresses of jumps to the next row and to the end are added to
+    /// `next_jumps` and `end_jumps`.
+    fn emit(&self, builder: &mut ProgramBuilder, scope: Scope, next_jumps: &mut Vec<usize>, end_jumps: &mut Vec<usize>) -> Result<(), CompileError> {
+        match self.sorter {
+            Some(sorter) => {
+                // Sorter records hold the sort keys followed by the
+                // result columns.
+                let exprs: Vec<&ast::Expr> = self.order_by.iter().chain(self.columns.iter().map(|(expr, _)| expr)).collect();
+                let registers = builder.alloc_registers(exprs.len());
+                for (i, expr) in exprs.iter().enumerate() {
+                    compile_expr(builder, expr, scope, registers + i)?;
+                }
+                let record = builder.alloc_register();
+                builder.emit(Opcode::MakeRecord, registers, exprs.len(), record, P4::None, "");
+                builder.emit(Opcode::SorterInsert, sorter, record, 0, P4::None, "");
+                Ok(())
+            },
+            None => {
+                let registers = builder.alloc_registers(self.columns.len());
+                self.emit_row(builder, registers, next_jumps, end_jumps, |builder| {
+                    for (i, (expr, _)) in self.columns.iter().enumerate() {
+                        compile_expr(builder, expr, scope, registers + i)?;
+                    }
+                    Ok(())
+                })
+            },
+        }
+    }
+
+    /// Emits code that sends the result columns in the registers from
+    /// `registers` to the destination once `compute` has filled them
+    /// in, skipping the row while the `OFFSET` register is positive and
+    /// jumping to the end once the `LIMIT` register reaches 0, or after
+    /// the first row if only that one is needed. The addresses of jumps
+    /// to the next row and to the end are added to `next_jumps` and
+    /// `end_jumps`.
+    fn emit_row(
+        &self,
+        builder: &mut ProgramBuilder,
+        registers: usize,
+        next_jumps: &mut Vec<usize>,
+        end_jumps: &mut Vec<usize>,
+        compute: impl FnOnce(&mut ProgramBuilder) -> Result<(), CompileError>,
+    ) -> Result<(), CompileError> {
+        if let Some(offset) = self.offset {
+            next_jumps.push(builder.emit(Opcode::IfPos, offset, 0, 1, P4::None, ""));
+        }
+        compute(builder)?;
+        self.destination.emit(builder, registers, self.columns.len(), next_jumps, end_jumps);
+        if let (Some(limit), false) = (self.limit, self.destination.first_row_only()) {
+            end_jumps.push(builder.emit(Opcode::DecrJumpZero, limit, 0, 0, P4::None, ""));
+        }
+        Ok(())
+    }
+}
+
+/// Returns the expression an `ORDER BY` term sorts on, which is the
+/// result column it names if it is a column number or an alias.
+fn resolve_ordering_term(expr: &ast::Expr, index: usize, columns: &[(ast::Expr, Option<&str>)]) -> Result<ast::Expr, CompileError> {
+    match expr {
+        ast::Expr::Literal(ast::Literal::Integer(number)) => {
+            match usize::try_from(*number).ok().and_then(|number| columns.get(number.wrapping_sub(1))) {
+                Some((expr, _)) => Ok(expr.clone()),
+                None => Err(CompileError::OrderByOutOfRange { term: index + 1, columns: columns.len() }),
+            }
+        },
+        ast::Expr::Column { table: None, name } => {
+            let aliased = columns.iter().find(|(_, alias)| alias.is_some_and(|alias| alias.eq_ignore_ascii_case(name)));
+            Ok(aliased.map_or(expr, |(expr, _)| expr).clone())
+        },
+        _ => Ok(expr.clone()),
+    }
+}
+
+/// Emits code that stores the count and offset of a `LIMIT` clause,
+/// computed in `scope`, in registers and returns them, adding a jump to
+/// `end_jumps` that is taken when the count is 0.
+fn compile_limit(builder: &mut ProgramBuilder, limit: &ast::Limit, scope: Scope, end_jumps: &mut Vec<usize>) -> Result<(Option<usize>, Option<usize>), CompileError> {
+    let count = builder.alloc_register();
+    compile_expr(builder, &limit.count, scope, count)?;
+    builder.emit(Opcode::MustBeInt, count, 0, 0, P4::None, "");
+    end_jumps.push(builder.emit(Opcode::IfNot, count, 0, 0, P4::None, ""));
+    let offset = match &limit.offset {
+        Some(expr) => {
+            let offset = builder.alloc_register();
+            compile_expr(builder, expr, scope, offset)?;
+            builder.emit(Opcode::MustBeInt, offset, 0, 0, P4::None, "");
+            Some(offset)
+        },
+        None => None,
+    };
+    Ok((Some(count), offset))
+}
+
+fn compile_insert(builder: &mut ProgramBuilder, insert: &ast::Insert, schema: &Schema) -> Result<(), CompileError> {
+    let table = schema.table(&insert.table).ok_or_else(|| CompileError::NoSuchTable(insert.table.clone()))?;
+    if table.root_page == schema::SCHEMA_ROOT_PAGE {
+        return Err(CompileError::ReadOnlyTable(table.name.clone()));
+    }
+    let num_columns = table.columns.len();
+    // Position in the statement's value lists of each table column.
+    let mut positions = vec![None; num_columns];
+    let num_values = if insert.columns.is_empty() {
+        positions = (0..num_columns).map(Some).collect();
+        num_columns
+    } else {
+        for (i, name) in insert.columns.iter().enumerate() {
+            let index = table.column_index(name).ok_or_else(|| CompileError::NoSuchColumn(name.clone()))?;
+            positions[index] = Some(i);
+        }
+        insert.columns.len()
+    };
+    let rowid_alias = table.rowid_alias();
+    let cursor = builder.alloc_cursor();
+    builder.emit(Opcode::OpenWrite, cursor, table.root_page, 0, P4::None, &table.name);
+    let indexes = open_indexes(builder, schema, table);
+    let registers = builder.alloc_registers(num_columns);
+    let key = builder.alloc_register();
+    let record = builder.alloc_register();
+    let scope = Scope::new(schema);
+    for values in &insert.rows {
+        if values.len() != num_values {
+            return Err(CompileError::ValueCountMismatch { values: values.len(), columns: num_values });
+        }
+        // The row id comes from the INTEGER PRIMARY KEY column if it is
+        // given and not NULL, and is one past the largest id otherwise.
+        match rowid_alias.and_then(|index| positions[index]) {
+            Some(position) => {
+                compile_expr(builder, &values[position], scope, key)?;
+                let not_null = builder.emit(Opcode::NotNull, key, 0, 0, P4::None, "");
+                builder.emit(Opcode::NewRowid, cursor, key, 0, P4::None, "");
+                builder.patch_jump(not_null);
+                builder.emit(Opcode::MustBeInt, key, 0, 0, P4::None, "");
+            },
+            None => {
+                builder.emit(Opcode::NewRowid, cursor, key, 0, P4::None, "");
+            },
+        }
+        for (i, position) in positions.iter().enumerate() {
+            match position {
+                Some(position) if rowid_alias != Some(i) => {
+                    compile_expr(builder, &values[*position], scope, registers + i)?;
+                },
+                _ => {
+                    builder.emit(Opcode::Null, 0, registers + i, 0, P4::None, "");
+                },
+            }
+        }
+        for (i, column) in table.columns.iter().enumerate() {
+            if column.not_null && rowid_alias != Some(i) {
+                let name = format!("{}.{}", table.name, column.name);
+                builder.emit(Opcode::HaltIfNull, registers + i, 0, 0, P4::Text(name), "");
+            }
+        }
+        let affinities = table.affinities().iter().map(|affinity| affinity.code()).collect();
+        builder.emit(Opcode::MakeRecord, registers, num_columns, record, P4::Text(affinities), "");
+        // Every index is checked before this row is written, so a failed
+        // constraint leaves the row out of both the table and its
+        // indexes. Rows written before it are undone with the rest of
+        // the statement.
+        let mut entries = Vec::new();
+        for (index, index_cursor) in &indexes {
+            let entry = emit_index_record(builder, table, index, RowSource::Registers(registers), key);
+            if index.unique {
+                emit_no_conflict(builder, table, index, *index_cursor, entry);
+            }
+            entries.push(entry);
+        }
+        builder.emit(Opcode::Insert, cursor, record, key, P4::Text(rowid_name(table)), &table.name);
+        for ((index, index_cursor), entry) in indexes.iter().zip(entries) {
+            builder.emit(Opcode::IdxInsert, *index_cursor, entry, 0, P4::None, &index.name);
+        }
+    }
+    Ok(())
+}
+
+/// Returns the name of the row id of `table` in constraint errors, which
+/// is that of its `INTEGER PRIMARY KEY` column if it has one.
+fn rowid_name(table: &TableSchema) -> String {
+    let column = table.rowid_alias().map_or("rowid", |index| &table.columns[index].name);
+    format!("{}.{}", table.name, column)
+}
+
+/// Emits code that first collects the row ids of the rows to change in
+/// an ephemeral table, and then rewrites each of those rows. Collecting
+/// the ids first means a row moved to a larger id is not visited again,
+/// and that the rows can be found through an index that changes.
+fn compile_update(builder: &mut ProgramBuilder, update: &ast::Update, schema: &Schema) -> Result<(), CompileError> {
+    let table = schema.table(&update.table).ok_or_else(|| CompileError::NoSuchTable(update.table.clone()))?;
+    if table.root_page == schema::SCHEMA_ROOT_PAGE {
+        return Err(CompileError::ReadOnlyTable(table.name.clone()));
+    }
+    let rowid_alias = table.rowid_alias();
+    // New value of each table column, and of the row id, in the order
+    // of the table's columns. Later assignments to a column win.
+    let mut values = vec![None; table.columns.len()];
+    let mut new_rowid = None;
+    for (name, expr) in &update.assignments {
+        match table.column_index(name) {
+            Some(index) if rowid_alias == Some(index) => new_rowid = Some(expr),
+            Some(index) => values[index] = Some(expr),
+            None if schema::ROWID_NAMES.iter().any(|rowid| rowid.eq_ignore_ascii_case(name)) => new_rowid = Some(expr),
+            None => return Err(CompileError::NoSuchColumn(name.clone())),
+        }
+    }
+    builder.changes_verb = Some("updated");
+    let cursor = builder.alloc_cursor();
+    let rowids = builder.alloc_cursor();
+    builder.emit(Opcode::OpenWrite, cursor, table.root_page, 0, P4::None, &table.name);
+    builder.emit(Opcode::OpenEphemeral, rowids, 0, 0, P4::None, "");
+    let indexes = open_indexes(builder, schema, table);
+    emit_collect_rowids(builder, schema, table, update.where_clause.as_ref(), rowids)?;
+
+    let key = builder.alloc_register();
+    let rewind = builder.emit(Opcode::Rewind, rowids, 0, 0, P4::None, "");
+    let loop_start = builder.current_addr();
+    builder.emit(Opcode::Rowid, rowids, key, 0, P4::None, "");
+    let not_exists = builder.emit(Opcode::NotExists, cursor, 0, key, P4::None, "");
+    let row = [Source::cursor(table, cursor)];
+    let root = Scope::new(schema);
+    let scope = root.nested(&row);
+    let num_columns = table.columns.len();
+    let registers = builder.alloc_registers(num_columns);
+    let new_key = builder.alloc_register();
+    let record = builder.alloc_register();
+    for (i, value) in values.iter().enumerate() {
+        match value {
+            Some(expr) => compile_expr(builder, expr, scope, registers + i)?,
+            None if rowid_alias == Some(i) => {
+                builder.emit(Opcode::Null, 0, registers + i, 0, P4::None, "");
+            },
+            None => {
+                let comment = format!("{}.{}", table.name, table.columns[i].name);
+                builder.emit(Opcode::Column, cursor, i, registers + i, P4::None, &comment);
+            },
+        }
+    }
+    match new_rowid {
+        Some(expr) => {
+            compile_expr(builder, expr, scope, new_key)?;
+            builder.emit(Opcode::MustBeInt, new_key, 0, 0, P4::None, "");
+        },
+        None => {
+            builder.emit(Opcode::Rowid, cursor, new_key, 0, P4::None, "");
+        },
+    }
+    for (i, column) in table.columns.iter().enumerate() {
+        if column.not_null && rowid_alias != Some(i) && values[i].is_some() {
+            let name = format!("{}.{}", table.name, column.name);
+            builder.emit(Opcode::HaltIfNull, registers + i, 0, 0, P4::Text(name), "");
+        }
+    }
+    let affinities = table.affinities().iter().map(|affinity| affinity.code()).collect();
+    builder.emit(Opcode::MakeRecord, registers, num_columns, record, P4::Text(affinities), "");
+    // The old and new entry of each index are built while the cursor is
+    // still on the old row. Conflicts are looked for under the old row
+    // id, so that the row's own entry does not count as one.
+    let mut entries = Vec::new();
+    for (index, index_cursor) in &indexes {
+        let old_entry = emit_index_record(builder, table, index, RowSource::Cursor(cursor), key);
+        let new_entry = emit_index_record(builder, table, index, RowSource::Registers(registers), new_key);
+        if index.unique {
+            let probe = emit_index_record(builder, table, index, RowSource::Registers(registers), key);
+            emit_no_conflict(builder, table, index, *index_cursor, probe);
+        }
+        entries.push((old_entry, new_entry));
+    }
+    builder.emit(Opcode::Update, cursor, record, new_key, P4::Text(rowid_name(table)), &table.name);
+    for ((index, index_cursor), (old_entry, new_entry)) in indexes.iter().zip(entries) {
+        builder.emit(Opcode::IdxDelete, *index_cursor, old_entry, 0, P4::None, &index.name);
+        builder.emit(Opcode::IdxInsert, *index_cursor, new_entry, 0, P4::None, &index.name);
+    }
+    builder.patch_jump(not_exists);
+    builder.emit(Opcode::Next, rowids, loop_start, 0, P4::None, "");
+    builder.patch_jump(rewind);
+    Ok(())
+}
+
+fn compile_delete(builder: &mut ProgramBuilder, delete: &ast::Delete, schema: &Schema) -> Result<(), CompileError> {
+    let table = schema.table(&delete.table).ok_or_else(|| CompileError::NoSuchTable(delete.table.clone()))?;
+    if table.root_page == schema::SCHEMA_ROOT_PAGE {
+        return Err(CompileError::ReadOnlyTable(table.name.clone()));
+    }
+    builder.changes_verb = Some("deleted");
+    let cursor = builder.alloc_cursor();
+    builder.emit(Opcode::OpenWrite, cursor, table.root_page, 0, P4::None, &table.name);
+    let indexes = open_indexes(builder, schema, table);
+    let rowids = builder.alloc_cursor();
+    builder.emit(Opcode::OpenEphemeral, rowids, 0, 0, P4::None, "");
+    emit_collect_rowids(builder, schema, table, delete.where_clause.as_ref(), rowids)?;
+
+    let key = builder.alloc_register();
+    let rewind = builder.emit(Opcode::Rewind, rowids, 0, 0, P4::None, "");
+    let loop_start = builder.current_addr();
+    builder.emit(Opcode::Rowid, rowids, key, 0, P4::None, "");
+    let not_exists = builder.emit(Opcode::NotExists, cursor, 0, key, P4::None, "");
+    for (index, index_cursor) in &indexes {
+        let entry = emit_index_record(builder, table, index, RowSource::Cursor(cursor), key);
+        builder.emit(Opcode::IdxDelete, *index_cursor, entry, 0, P4::None, &index.name);
+    }
+    builder.emit(Opcode::Delete, cursor, 0, 0, P4::None, &table.name);
+    builder.patch_jump(not_exists);
+    builder.emit(Opcode::Next, rowids, loop_start, 0, P4::None, "");
+    builder.patch_jump(rewind);
+    Ok(())
+}
+
+/// Emits code that adds the id of each row of `table` where `condition`
+/// holds to the ephemeral table at cursor `rowids`, so that the rows can
+/// be changed afterwards without disturbing the loop that finds them.
+fn emit_collect_rowids(builder: &mut ProgramBuilder, schema: &Schema, table: &TableSchema, condition: Option<&ast::Expr>, rowids: usize) -> Result<(), CompileError> {
+    let key = builder.alloc_register();
+    let empty_record = builder.alloc_register();
+    builder.emit(Opcode::MakeRecord, 0, 0, empty_record, P4::None, "");
+    let tables = [planner::TableRef::new(table)];
+    let used_columns = planner::used_columns(&tables, 0, condition);
+    let indexes = schema.indexes_of(&table.name);
+    let loops = [TableLoop::open(builder, &tables, 0, &indexes, None, condition.as_slice(), &used_columns)];
+    let source = loops[0].source();
+    emit_scan(builder, &loops, Scope::new(schema), condition, |builder, _| {
+        emit_column(builder, table, source.row, None, key);
+        builder.emit(Opcode::Insert, rowids, empty_record, key, P4::None, "");
+        Ok(())
+    })
+}
+
+/// Emits code that creates the table's B-tree, records the table in the
+/// schema catalog and reloads the schema.
+fn compile_create_table(builder: &mut ProgramBuilder, create: &ast::CreateTable, schema: &Schema) -> Result<(), CompileError> {
+    if schema.table(&create.name).is_some() {
+        if create.if_not_exists {
+            return Ok(());
+        }
+        return Err(CompileError::TableExists(create.name.clone()));
+    }
+    if create.name.to_ascii_lowercase().starts_with("sqlite_") {
+        return Err(CompileError::ReservedName(create.name.clone()));
+    }
+    for (i, column) in create.columns.iter().enumerate() {
+        if create.columns[..i].iter().any(|other| other.name.eq_ignore_ascii_case(&column.name)) {
+            return Err(CompileError::DuplicateColumn(column.name.clone()));
+        }
+    }
+    let table = TableSchema { name: create.name.clone(), root_page: 0, columns: create.columns.clone(), row_count: None };
+    let primary_keys = create.columns.iter().filter(|column| column.primary_key).count();
+    if primary_keys > 1 {
+        return Err(CompileError::MultiplePrimaryKeys(create.name.clone()));
+    }
+    if primary_keys == 1 && table.rowid_alias().is_none() {
+        return Err(CompileError::Unsupported("PRIMARY KEY constraints on non-INTEGER columns"));
+    }
+    emit_catalog_entry(builder, "table", &create.name, &create.name, table.sql());
+    builder.emit(Opcode::ParseSchema, 0, 0, 0, P4::None, "");
+    Ok(())
+}
+
+/// Emits code that creates the B-tree of a table or index, as `kind`
+/// says, and records it in the schema catalog under `name`, for the
+/// table `table_name`, with the statement `sql`. Returns the register
+/// holding the root page of the new B-tree.
+fn emit_catalog_entry(builder: &mut ProgramBuilder, kind: &str, name: &str, table_name: &str, sql: String) -> usize {
+    // Registers holding the catalog columns:
+    // | type | name | tbl_name | rootpage | sql |
+    let registers = builder.alloc_registers(5);
+    let record = builder.alloc_register();
+    let key = builder.alloc_register();
+    builder.emit(Opcode::CreateBtree, 0, registers + 3, usize::from(kind == "index"), P4::None, name);
+    let catalog = builder.alloc_cursor();
+    builder.emit(Opcode::OpenWrite, catalog, schema::SCHEMA_ROOT_PAGE, 0, P4::None, schema::SCHEMA_TABLE_NAME);
+    builder.emit(Opcode::NewRowid, catalog, key, 0, P4::None, "");
+    builder.emit(Opcode::String, 0, registers, 0, P4::Text(String::from(kind)), "");
+    builder.emit(Opcode::String, 0, registers + 1, 0, P4::Text(String::from(name)), "");
+    builder.emit(Opcode::String, 0, registers + 2, 0, P4::Text(String::from(table_name)), "");
+    builder.emit(Opcode::String, 0, registers + 4, 0, P4::Text(sql), "");
+    builder.emit(Opcode::MakeRecord, registers, 5, record, P4::None, "");
+    builder.emit(Opcode::Insert, catalog, record, key, P4::None, schema::SCHEMA_TABLE_NAME);
+    registers + 3
+}
+
+/// Emits code that builds the index, records it in the schema catalog
+/// and fills it from the rows already in the table, then reloads the
+/// schema.
+fn compile_create_index(builder: &mut ProgramBuilder, create: &ast::CreateIndex, schema: &Schema) -> Result<(), CompileError> {
+    if schema.index(&create.name).is_some() {
+        if create.if_not_exists {
+            return Ok(());
+        }
+        return Err(CompileError::IndexExists(create.name.clone()));
+    }
+    if create.name.to_ascii_lowercase().starts_with("sqlite_") {
+        return Err(CompileError::ReservedName(create.name.clone()));
+    }
+    let table = schema.table(&create.table).ok_or_else(|| CompileError::NoSuchTable(create.table.clone()))?;
+    if table.root_page == schema::SCHEMA_ROOT_PAGE {
+        return Err(CompileError::ReadOnlyTable(table.name.clone()));
+    }
+    if let Some(column) = create.columns.iter().find(|column| table.column_index(column).is_none()) {
+        return Err(CompileError::NoSuchColumn(column.clone()));
+    }
+    let index = IndexSchema {
+        name: create.name.clone(),
+        table: table.name.clone(),
+        root_page: 0,
+        columns: create.columns.clone(),
+        unique: create.unique,
+        rows_per_key: Vec::new(),
+    };
+    // A conflict found while filling the new B-tree undoes the whole
+    // statement, which gives its pages back.
+    let root_page = emit_catalog_entry(builder, "index", &create.name, &table.name, index.sql());
+
+    let key = builder.alloc_register();
+    let cursor = builder.alloc_cursor();
+    let index_cursor = builder.alloc_cursor();
+    builder.emit(Opcode::OpenRead, cursor, table.root_page, 0, P4::None, &table.name);
+    builder.emit(Opcode::OpenWrite, index_cursor, root_page, 1, P4::None, &create.name);
+    let rewind = builder.emit(Opcode::Rewind, cursor, 0, 0, P4::None, "");
+    let loop_start = builder.current_addr();
+    builder.emit(Opcode::Rowid, cursor, key, 0, P4::None, "");
+    let entry = emit_index_record(builder, table, &index, RowSource::Cursor(cursor), key);
+    if create.unique {
+        emit_no_conflict(builder, table, &index, index_cursor, entry);
+    }
+    builder.emit(Opcode::IdxInsert, index_cursor, entry, 0, P4::None, &create.name);
+    builder.emit(Opcode::Next, cursor, loop_start, 0, P4::None, "");
+    builder.patch_jump(rewind);
+    builder.emit(Opcode::ParseSchema, 0, 0, 0, P4::None, "");
+    Ok(())
+}
+
+/// Emits code that counts the rows of each table being analysed and the
+/// distinct prefixes of each of its indexes, replacing the table's rows
+/// in the statistics table, which is created first if need be. The
+/// schema is then reloaded so the planner sees the new statistics.
+fn compile_analyze(builder: &mut ProgramBuilder, name: Option<&str>, schema: &Schema) -> Result<(), CompileError> {
+    let tables: Vec<&TableSchema> = match name {
+        Some(name) => vec![schema.table(name).ok_or_else(|| CompileError::NoSuchTable(String::from(name)))?],
+        None => schema.tables().iter().collect(),
+    };
+    let tables: Vec<&TableSchema> = tables.into_iter().filter(|table| !table.name.to_ascii_lowercase().starts_with("sqlite_")).collect();
+    let stat = builder.alloc_cursor();
+    match schema.table(schema::STAT_TABLE_NAME) {
+        Some(table) => {
+            builder.emit(Opcode::OpenWrite, stat, table.root_page, 0, P4::None, schema::STAT_TABLE_NAME);
+        },
+        None => {
+            let columns = schema::STAT_COLUMNS
+                .iter()
+                .map(|name| ast::ColumnDef { name: String::from(*name), type_name: None, primary_key: false, not_null: false })
+                .collect();
+            let table = TableSchema { name: String::from(schema::STAT_TABLE_NAME), root_page: 0, columns, row_count: None };
+            let root_page = emit_catalog_entry(builder, "table", &table.name, &table.name, table.sql());
+            builder.emit(Opcode::OpenWrite, stat, root_page, 1, P4::None, &table.name);
+        },
+    }
+
+    // Statistics rows being written:
+    // | tbl | idx | stat |
+    let row = builder.alloc_registers(3);
+    let record = builder.alloc_register();
+    let key = builder.alloc_register();
+    let one = builder.alloc_register();
+    let space = builder.alloc_register();
+    let is_other = builder.alloc_register();
+    builder.emit(Opcode::Integer, 0, one, 0, P4::Integer(1), "");
+    builder.emit(Opcode::String, 0, space, 0, P4::Text(String::from(" ")), "");
+
+    // Remove the old statistics of the tables being analysed.
+    let rewind = builder.emit(Opcode::Rewind, stat, 0, 0, P4::None, "");
+    let loop_start = builder.current_addr();
+    let mut skips = Vec::new();
+    if name.is_some() {
+        builder.emit(Opcode::Column, stat, 0, key, P4::None, "tbl");
+        for table in &tables {
+            builder.emit(Opcode::String, 0, row, 0, P4::Text(table.name.clone()), "");
+            builder.emit(Opcode::Ne, key, row, is_other, P4::None, "");
+            skips.push(builder.emit(Opcode::If, is_other, 0, 0, P4::None, ""));
+        }
+    }
+    builder.emit(Opcode::Delete, stat, 0, 0, P4::None, schema::STAT_TABLE_NAME);
+    for skip in skips {
+        builder.patch_jump(skip);
+    }
+    builder.emit(Opcode::Next, stat, loop_start, 0, P4::None, "");
+    builder.patch_jump(rewind);
+
+    for table in tables {
+        let count = builder.alloc_register();
+        let cursor = builder.alloc_cursor();
+        builder.emit(Opcode::Integer, 0, count, 0, P4::Integer(0), "");
+        builder.emit(Opcode::OpenRead, cursor, table.root_page, 0, P4::None, &table.name);
+        let rewind = builder.emit(Opcode::Rewind, cursor, 0, 0, P4::None, "");
+        let loop_start = builder.current_addr();
+        builder.emit(Opcode::Add, count, one, count, P4::None, "");
+        builder.emit(Opcode::Next, cursor, loop_start, 0, P4::None, "");
+        builder.patch_jump(rewind);
+        builder.emit(Opcode::String, 0, row, 0, P4::Text(table.name.clone()), "");
+        builder.emit(Opcode::Null, 0, row + 1, 0, P4::None, "");
+        builder.emit(Opcode::String, 0, row + 2, 0, P4::Text(String::new()), "");
+        builder.emit(Opcode::Concat, row + 2, count, row + 2, P4::None, "");
+        emit_stat_row(builder, stat, row, record, key);
+
+        for index in schema.indexes_of(&table.name) {
+            let count = emit_index_stat(builder, index, row, one, space);
+            let empty = builder.emit(Opcode::IfNot, count, 0, 0, P4::None, "");
+            emit_stat_row(builder, stat, row, record, key);
+            builder.patch_jump(empty);
+        }
+    }
+    builder.emit(Opcode::ParseSchema, 0, 0, 0, P4::None, "");
+    Ok(())
+}
+
+/// Emits code that reads every entry of `index` and stores its
+/// statistics row in the three registers starting at `row`, where `one`
+/// holds 1 and `space` a space. Returns the register holding the number
+/// of entries, which leaves the row incomplete if it is 0.
+fn emit_index_stat(builder: &mut ProgramBuilder, index: &IndexSchema, row: usize, one: usize, space: usize) -> usize {
+    let num_columns = index.columns.len();
+    let count = builder.alloc_register();
+    // Number of distinct values of each prefix of the columns, and the
+    // values of the current and previous entries.
+    let distinct = builder.alloc_registers(num_columns);
+    let current = builder.alloc_registers(num_columns);
+    let previous = builder.alloc_registers(num_columns);
+    let is_changed = builder.alloc_register();
+    builder.emit(Opcode::Integer, 0, count, 0, P4::Integer(0), "");
+    for i in 0..num_columns {
+        builder.emit(Opcode::Integer, 0, distinct + i, 0, P4::Integer(0), "");
+    }
+    let cursor = builder.alloc_cursor();
+    builder.emit(Opcode::OpenRead, cursor, index.root_page, 0, P4::None, &index.name);
+    let rewind = builder.emit(Opcode::Rewind, cursor, 0, 0, P4::None, "");
+    let loop_start = builder.current_addr();
+    for i in 0..num_columns {
+        builder.emit(Opcode::Column, cursor, i, current + i, P4::None, &index.columns[i]);
+    }
+    // Every prefix of the first entry is new. Otherwise the prefixes from
+    // the first column that differs from the previous entry are.
+    let mut changed_jumps = vec![builder.emit(Opcode::IfNot, count, 0, 0, P4::None, "")];
+    for i in 0..num_columns {
+        builder.emit(Opcode::IsNot, previous + i, current + i, is_changed, P4::None, "");
+        changed_jumps.push(builder.emit(Opcode::If, is_changed, 0, 0, P4::None, ""));
+    }
+    let unchanged = builder.emit(Opcode::Goto, 0, 0, 0, P4::None, "");
+    builder.patch_jump(changed_jumps[0]);
+    for i in 0..num_columns {
+        builder.patch_jump(changed_jumps[i + 1]);
+        builder.emit(Opcode::Add, distinct + i, one, distinct + i, P4::None, "");
+    }
+    builder.emit(Opcode::Copy, current, previous, num_columns, P4::None, "");
+    builder.patch_jump(unchanged);
+    builder.emit(Opcode::Add, count, one, count, P4::None, "");
+    builder.emit(Opcode::Next, cursor, loop_start, 0, P4::None, "");
+    builder.patch_jump(rewind);
+
+    // stat = count || ' ' || ceil(count / distinct) for each prefix
+    builder.emit(Opcode::String, 0, row + 1, 0, P4::Text(index.name.clone()), "");
+    builder.emit(Opcode::String, 0, row + 2, 0, P4::Text(String::new()), "");
+    builder.emit(Opcode::Concat, row + 2, count, row + 2, P4::None, "");
+    let empty = builder.emit(Opcode::IfNot, count, 0, 0, P4::None, "");
+    let average = builder.alloc_register();
+    for i in 0..num_columns {
+        builder.emit(Opcode::Add, count, distinct + i, average, P4::None, "");
+        builder.emit(Opcode::Subtract, average, one, average, P4::None, "");
+        builder.emit(Opcode::Divide, average, distinct + i, average, P4::None, "");
+        builder.emit(Opcode::Concat, row + 2, space, row + 2, P4::None, "");
+        builder.emit(Opcode::Concat, row + 2, average, row + 2, P4::None, "");
+    }
+    builder.patch_jump(empty);
+    count
+}
+
+/// Emits code that adds the statistics row in the three registers
+/// starting at `row` to the statistics table at cursor `stat`.
+fn emit_stat_row(builder: &mut ProgramBuilder, stat: usize, row: usize, record: usize, key: usize) {
+    builder.emit(Opcode::NewRowid, stat, key, 0, P4::None, "");
+    builder.emit(Opcode::MakeRecord, row, 3, record, P4::None, "");
+    builder.emit(Opcode::Insert, stat, record, key, P4::None, schema::STAT_TABLE_NAME);
+}
+
+/// Emits code that opens a cursor for writing on each index of `table`,
+/// and returns the indexes with their cursors.
+fn open_indexes<'a>(builder: &mut ProgramBuilder, schema: &'a Schema, table: &TableSchema) -> Vec<(&'a IndexSchema, usize)> {
+    schema.indexes_of(&table.name)
+        .into_iter()
+        .map(|index| {
+            let cursor = builder.alloc_cursor();
+            builder.emit(Opcode::OpenWrite, cursor, index.root_page, 0, P4::None, &index.name);
+            (index, cursor)
+        })
+        .collect()
+}
+
+/// Emits code that builds the entry of `index` for a row of `table`,
+/// whose columns are read from `row` and whose row id is in register
+/// `rowid`, and returns the register holding the entry.
+fn emit_index_record(builder: &mut ProgramBuilder, table: &TableSchema, index: &IndexSchema, row: RowSource, rowid: usize) -> usize {
+    let positions = index.column_positions(table);
+    let registers = builder.alloc_registers(positions.len() + 1);
+    for (i, position) in positions.iter().enumerate() {
+        if table.rowid_alias() == Some(*position) {
+            let comment = format!("{}.{}", table.name, table.columns[*position].name);
+            builder.emit(Opcode::Copy, rowid, registers + i, 1, P4::None, &comment);
+        } else {
+            emit_column(builder, table, row, Some(*position), registers + i);
+        }
+    }
+    builder.emit(Opcode::Copy, rowid, registers + positions.len(), 1, P4::None, "");
+    let affinities = table.affinities();
+    let codes = positions.iter().map(|position| affinities[*position].code()).collect();
+    let entry = builder.alloc_register();
+    builder.emit(Opcode::MakeRecord, registers, positions.len() + 1, entry, P4::Text(codes), &index.name);
+    entry
+}
+
+/// Emits a check that no other row has the values of the index entry
+/// in register `entry` in the unique `index`.
+fn emit_no_conflict(builder: &mut ProgramBuilder, table: &TableSchema, index: &IndexSchema, cursor: usize, entry: usize) {
+    let columns: Vec<String> = index.column_positions(table)
+        .iter()
+        .map(|position| format!("{}.{}", table.name, table.columns[*position].name))
+        .collect();
+    builder.emit(Opcode::NoConflict, cursor, entry, columns.len(), P4::Text(columns.join(", ")), &index.name);
+}
+
+/// Emits code that stores the value of `expr` in register `target`,
+/// reading columns and aggregate results from where `scope` says.
+fn compile_expr(builder: &mut ProgramBuilder, expr: &ast::Expr, scope: Scope, target: usize) -> Result<(), CompileError> {
+    match expr {
+        ast::Expr::Literal(ast::Literal::Null) => {
+            builder.emit(Opcode::Null, 0, target, 0, P4::None, "");
+        },
+        ast::Expr::Literal(ast::Literal::Integer(value)) => {
+            builder.emit(Opcode::Integer, 0, target, 0, P4::Integer(*value), "");
+        },
+        ast::Expr::Literal(ast::Literal::Float(value)) => {
+            builder.emit(Opcode::Real, 0, target, 0, P4::Real(*value), "");
+        },
+        ast::Expr::Literal(ast::Literal::String(value)) => {
+            builder.emit(Opcode::String, 0, target, 0, P4::Text(value.clone()), "");
+        },
+        ast::Expr::Literal(ast::Literal::Blob(value)) => {
+            builder.emit(Opcode::Blob, 0, target, 0, P4::Blob(value.clone()), "");
+        },
+        ast::Expr::Column { table: qualifier, name } => {
+            let full_name = match qualifier {
+                Some(qualifier) => format!("{qualifier}.{name}"),
+                None => name.clone(),
+            };
+            // A name refers to a column of the innermost scope that has
+            // one by that name.
+            let mut current = &scope;
+            loop {
+                let tables: Vec<planner::TableRef> = current.sources.iter().map(|source| source.table).collect();
+                match planner::find_column(&tables, qualifier.as_deref(), name)[..] {
+                    [] => match current.outer {
+                        Some(outer) => current = outer,
+                        None => return Err(CompileError::NoSuchColumn(full_name)),
+                    },
+                    // Index of the column, or `None` for the row id.
+                    [(i, index)] => {
+                        let source = current.sources[i];
+                        emit_column(builder, source.table.schema, source.row, index, target);
+                        let depth = current.depth();
+                        builder.shallowest_reference = Some(builder.shallowest_reference.map_or(depth, |shallowest| shallowest.min(depth)));
+                        break;
+                    },
+                    _ => return Err(CompileError::AmbiguousColumn(full_name)),
+                }
+            }
+        },
+        ast::Expr::Function { name, args } => {
+            aggregate_function(name, args)?;
+            let result = scope.aggregates.and_then(|(calls, results)| {
+                calls.iter().position(|call| call == expr).map(|i| results + i)
+            });
+            let Some(result) = result else {
+                return Err(CompileError::MisuseOfAggregate(name.clone()));
+            };
+            builder.emit(Opcode::Copy, result, target, 1, P4::None, "");
+        },
+        ast::Expr::Unary { op: ast::UnaryOp::Plus, operand } => compile_expr(builder, operand, scope, target)?,
+        ast::Expr::Unary { op: ast::UnaryOp::Not, operand } => {
+            compile_expr(builder, operand, scope, target)?;
+            builder.emit(Opcode::Not, target, target, 0, P4::None, "");
+        },
+        ast::Expr::Binary { op, left, right } => {
+            let opcode = match op {
+                ast::BinaryOp::Eq => Opcode::Eq,
+                ast::BinaryOp::NotEq => Opcode::Ne,
+                ast::BinaryOp::Lt => Opcode::Lt,
+                ast::BinaryOp::LtEq => Opcode::Le,
+                ast::BinaryOp::Gt => Opcode::Gt,
+                ast::BinaryOp::GtEq => Opcode::Ge,
+                ast::BinaryOp::Is => Opcode::Is,
+                ast::BinaryOp::IsNot => Opcode::IsNot,
+                ast::BinaryOp::And => Opcode::And,
+                ast::BinaryOp::Or => Opcode::Or,
+                ast::BinaryOp::Add => Opcode::Add,
+                ast::BinaryOp::Subtract => Opcode::Subtract,
+                ast::BinaryOp::Multiply => Opcode::Multiply,
+                ast::BinaryOp::Divide => Opcode::Divide,
+                ast::BinaryOp::Remainder => Opcode::Remainder,
+                ast::BinaryOp::Concat => Opcode::Concat,
+            };
+            let operands = builder.alloc_registers(2);
+            compile_expr(builder, left, scope, operands)?;
+            compile_expr(builder, right, scope, operands + 1)?;
+            builder.emit(opcode, operands, operands + 1, target, P4::None, "");
+        },
+        ast::Expr::Unary { op: ast::UnaryOp::Negate, operand } => {
+            let operands = builder.alloc_registers(2);
+            builder.emit(Opcode::Integer, 0, operands, 0, P4::Integer(0), "");
+            compile_expr(builder, operand, scope, operands + 1)?;
+            builder.emit(Opcode::Subtract, operands, operands + 1, target, P4::None, "");
+        },
+        ast::Expr::InList { expr, list, negated } => {
+            // The result is 1 if a value equals `expr`, and otherwise
+            // NULL if a comparison was NULL and 0 if none was.
+            let operands = builder.alloc_registers(2);
+            let equal = builder.alloc_register();
+            compile_expr(builder, expr, scope, operands)?;
+            builder.emit(Opcode::Integer, 0, target, 0, P4::Integer(0), "");
+            let mut found_jumps = Vec::new();
+            for item in list {
+                compile_expr(builder, item, scope, operands + 1)?;
+                builder.emit(Opcode::Eq, operands, operands + 1, equal, P4::None, "");
+                found_jumps.push(builder.emit(Opcode::If, equal, 0, 0, P4::None, ""));
+                let not_null = builder.emit(Opcode::NotNull, equal, 0, 0, P4::None, "");
+                builder.emit(Opcode::Null, 0, target, 0, P4::None, "");
+                builder.patch_jump(not_null);
+            }
+            let done = builder.emit(Opcode::Goto, 0, 0, 0, P4::None, "");
+            found_jumps.into_iter().for_each(|addr| builder.patch_jump(addr));
+            builder.emit(Opcode::Integer, 0, target, 0, P4::Integer(1), "");
+            builder.patch_jump(done);
+            if *negated {
+                builder.emit(Opcode::Not, target, target, 0, P4::None, "");
+            }
+        },
+        ast::Expr::InSelect { expr, select, negated } => {
+            // As for a list, the result is NULL rather than 0 when
+            // `expr` is NULL or the set has a NULL, unless it is empty.
+            let set = builder.alloc_cursor();
+            compile_subquery(builder, select, scope, Destination::Set(set))?;
+            let value = builder.alloc_register();
+            compile_expr(builder, expr, scope, value)?;
+            builder.emit(Opcode::Integer, 0, target, 0, P4::Integer(0), "");
+            let mut done_jumps = vec![builder.emit(Opcode::Rewind, set, 0, 0, P4::None, "")];
+            let not_null = builder.emit(Opcode::NotNull, value, 0, 0, P4::None, "");
+            builder.emit(Opcode::Null, 0, target, 0, P4::None, "");
+            done_jumps.push(builder.emit(Opcode::Goto, 0, 0, 0, P4::None, ""));
+            builder.patch_jump(not_null);
+            let missing = emit_set_lookup(builder, set, value, 1);
+            builder.emit(Opcode::Integer, 0, target, 0, P4::Integer(1), "");
+            done_jumps.push(builder.emit(Opcode::Goto, 0, 0, 0, P4::None, ""));
+            missing.into_iter().for_each(|addr| builder.patch_jump(addr));
+            // NULL sorts first, so the set has one if its first value is.
+            let first = builder.alloc_register();
+            done_jumps.push(builder.emit(Opcode::Rewind, set, 0, 0, P4::None, ""));
+            builder.emit(Opcode::Column, set, 0, first, P4::None, "");
+            done_jumps.push(builder.emit(Opcode::NotNull, first, 0, 0, P4::None, ""));
+            builder.emit(Opcode::Null, 0, target, 0, P4::None, "");
+            done_jumps.into_iter().for_each(|addr| builder.patch_jump(addr));
+            if *negated {
+                builder.emit(Opcode::Not, target, target, 0, P4::None, "");
+            }
+        },
+        ast::Expr::Exists(select) => {
+            let result = builder.alloc_register();
+            compile_subquery(builder, select, scope, Destination::Exists(result))?;
+            builder.emit(Opcode::Copy, result, target, 1, P4::None, "");
+        },
+        ast::Expr::Subquery(select) => {
+            let result = builder.alloc_register();
+            compile_subquery(builder, select, scope, Destination::Value(result))?;
+            builder.emit(Opcode::Copy, result, target, 1, P4::None, "");
+        },
+    }
+    Ok(())
+}
+
+/// Emits code that runs `select` as a subquery of an expression in
+/// `scope`, sending its rows to `destination`, which it first empties.
+///
+/// A subquery that does not refer to the tables of the statements
+/// around it gives the same rows each time, so it only runs the first
+/// time it is reached, and its destination is kept for later.
+fn compile_subquery(builder: &mut ProgramBuilder, select: &ast::Select, scope: Scope, destination: Destination) -> Result<(), CompileError> {
+    let kind = if matches!(destination, Destination::Set(_)) { "LIST SUBQUERY" } else { "SCALAR SUBQUERY" };
+    emit_once_if_uncorrelated(builder, scope, kind, |builder| {
+        match destination {
+            Destination::Value(register) => {
+                builder.emit(Opcode::Null, 0, register, 0, P4::None, "");
+            },
+            Destination::Exists(register) => {
+                builder.emit(Opcode::Integer, 0, register, 0, P4::Integer(0), "");
+            },
+            Destination::Set(cursor) => {
+                builder.emit(Opcode::OpenEphemeral, cursor, 0, 1, P4::None, "");
+            },
+            Destination::ResultRow | Destination::Table { .. } => unreachable!("subqueries only compute values"),
+        }
+        compile_select(builder, select, scope, destination)
+    })?;
+    Ok(())
+}
+
+/// Emits the code emitted by `body`, which reads from inside `scope`,
+/// so that it runs only the first time unless it reads columns of
+/// `scope` or the scopes around it. Notes `kind` in the query plan,
+/// marked `CORRELATED` if the code runs every time.
+fn emit_once_if_uncorrelated<T>(
+    builder: &mut ProgramBuilder,
+    scope: Scope,
+    kind: &str,
+    body: impl FnOnce(&mut ProgramBuilder) -> Result<T, CompileError>,
+) -> Result<T, CompileError> {
+    let note = builder.plan.len();
+    let once = builder.emit(Opcode::Once, 0, 0, 0, P4::None, "");
+    let shallowest_outside = builder.shallowest_reference.take();
+    let result = body(builder)?;
+    let shallowest_inside = builder.shallowest_reference;
+    let correlated = shallowest_inside.is_some_and(|depth| depth <= scope.depth());
+    builder.shallowest_reference = shallowest_outside.into_iter().chain(shallowest_inside).min();
+    if correlated {
+        builder.instructions[once].opcode = Opcode::Noop;
+    } else {
+        builder.patch_jump(once);
+    }
+    let correlated = if correlated { "CORRELATED " } else { "" };
+    builder.plan.insert(note, format!("{correlated}{kind}"));
+    Ok(result)
+}
+
+/// Emits code that adds the `count` registers from `values` to the
+/// ephemeral index at `cursor` unless it already holds them, returning
+/// the address of the jump taken when it does.
+fn emit_set_insert(builder: &mut ProgramBuilder, cursor: usize, values: usize, count: usize) -> usize {
+    let missing = emit_set_lookup(builder, cursor, values, count);
+    let present = builder.emit(Opcode::Goto, 0, 0, 0, P4::None, "");
+    missing.into_iter().for_each(|addr| builder.patch_jump(addr));
+    let record = builder.alloc_register();
+    builder.emit(Opcode::MakeRecord, values, count, record, P4::None, "");
+    builder.emit(Opcode::IdxInsert, cursor, record, 0, P4::None, "");
+    present
+}
+
+/// Emits code that moves the ephemeral index at `cursor` to the entry
+/// equal to the `count` registers from `values`, returning the addresses
+/// of the jumps taken when there is none.
+fn emit_set_lookup(builder: &mut ProgramBuilder, cursor: usize, values: usize, count: usize) -> Vec<usize> {
+    vec![
+        builder.emit(Opcode::SeekGe, cursor, 0, values, P4::Integer(count as i64), ""),
+        builder.emit(Opcode::IdxGt, cursor, 0, values, P4::Integer(count as i64), ""),
+    ]
+}
+
+/// Emits code that stores column `index` of the current row of `table`
+/// in register `target`, or its row id if `index` is `None`, reading
+/// the row from `row`.
+fn emit_column(builder: &mut ProgramBuilder, table: &TableSchema, row: RowSource, index: Option<usize>, target: usize) {
+    let comment = match index {
+        Some(index) => format!("{}.{}", table.name, table.columns[index].name),
+        None => format!("{}.rowid", table.name),
+    };
+    match (row, index) {
+        (RowSource::Cursor(cursor), Some(index)) => {
+            builder.emit(Opcode::Column, cursor, index, target, P4::None, &comment);
+        },
+        (RowSource::Cursor(cursor), None) => {
+            builder.emit(Opcode::Rowid, cursor, target, 0, P4::None, &comment);
+        },
+        (RowSource::Index { cursor, columns }, Some(index)) => {
+            // A covering index has every column that is used, so the
+            // others are never read.
+            match columns.iter().position(|column| *column == index) {
+                Some(position) => builder.emit(Opcode::Column, cursor, position, target, P4::None, &comment),
+                None => builder.emit(Opcode::Null, 0, target, 0, P4::None, &comment),
+            };
+        },
+        (RowSource::Index { cursor, .. }, None) => {
+            builder.emit(Opcode::IdxRowid, cursor, target, 0, P4::None, &comment);
+        },
+        (RowSource::Registers(first), index) => {
+            let register = first + index.unwrap_or(table.columns.len());
+            builder.emit(Opcode::Copy, register, target, 1, P4::None, &comment);
+        },
+    }
+}
+
+/// Returns the aggregate function called by `name` with `args`.
+fn aggregate_function(name: &str, args: &[ast::Expr]) -> Result<aggregate::Function, CompileError> {
+    let function = aggregate::Function::from_name(name).ok_or_else(|| CompileError::NoSuchFunction(name.to_string()))?;
+    if !function.accepts(args.len()) {
+        return Err(CompileError::WrongArgumentCount(name.to_string()));
+    }
+    Ok(function)
+}
+
+/// Adds the aggregate calls in `expr` that are not already in
+/// `aggregates` to it.
+fn collect_aggregates(expr: &ast::Expr, aggregates: &mut Vec<ast::Expr>) -> Result<(), CompileError> {
+    match expr {
+        ast::Expr::Function { name, args } => {
+            aggregate_function(name, args)?;
+            let mut nested = Vec::new();
+            for arg in args {
+                collect_aggregates(arg, &mut nested)?;
+            }
+            if !nested.is_empty() {
+                return Err(CompileError::MisuseOfAggregate(name.clone()));
+            }
+            if !aggregates.contains(expr) {
+                aggregates.push(expr.clone());
+            }
+        },
+        ast::Expr::Unary { operand, .. } => collect_aggregates(operand, aggregates)?,
+        ast::Expr::Binary { left, right, .. } => {
+            collect_aggregates(left, aggregates)?;
+            collect_aggregates(right, aggregates)?;
+        },
+        ast::Expr::InList { expr, list, .. } => {
+            collect_aggregates(expr, aggregates)?;
+            for item in list {
+                collect_aggregates(item, aggregates)?;
+            }
+        },
+        ast::Expr::InSelect { expr, .. } => collect_aggregates(expr, aggregates)?,
+        // Aggregate calls in a subquery are computed by the subquery.
+        ast::Expr::Literal(_) | ast::Expr::Column { .. } | ast::Expr::Exists(_) | ast::Expr::Subquery(_) => {},
+    }
+    Ok(())
+}
+
+/// What the names and aggregate calls in an expression refer to.
+#[derive(Clone, Copy)]
+struct Scope<'a> {
+    /// The schema that table names in subqueries refer to.
+    schema: &'a Schema,
+    /// The tables whose columns are in scope, and where the current row
+    /// of each is read from.
+    sources: &'a [Source<'a>],
+    /// Aggregate calls, once computed, and the first of the consecutive
+    /// registers holding their results.
+    aggregates: Option<(&'a [ast::Expr], usize)>,
+    /// Scope of the statement around a subquery, whose columns the
+    /// subquery can also refer to.
+    outer: Option<&'a Scope<'a>>,
+    /// Tables defined by `WITH`, which the statement and the ones inside
+    /// it can read.
+    ctes: &'a [CteTable],
+}
+
+impl<'a> Scope<'a> {
+    /// Scope without any tables, around a statement.
+    fn new(schema: &'a Schema) -> Self {
+        Self { schema, sources: &[], aggregates: None, outer: None, ctes: &[] }
+    }
+
+    /// Scope of the current rows of `sources`, inside this scope.
+    fn nested(&'a self, sources: &'a [Source<'a>]) -> Self {
+        Self { schema: self.schema, sources, aggregates: None, outer: Some(self), ctes: &[] }
+    }
+
+    /// Number of scopes this one is inside.
+    fn depth(&self) -> usize {
+        self.outer.map_or(0, |outer| outer.depth() + 1)
+    }
+
+    /// Returns the table defined by `WITH` named `name` in this scope or
+    /// the nearest one around it.
+    fn cte(&self, name: &str) -> Option<&'a CteTable> {
+        match self.ctes.iter().find(|cte| cte.schema.name.eq_ignore_ascii_case(name)) {
+            Some(cte) => Some(cte),
+            None => self.outer.and_then(|outer| outer.cte(name)),
+        }
+    }
+}
+
+/// A table defined by `WITH`, whose rows are in an ephemeral table.
+struct CteTable {
+    schema: TableSchema,
+    cursor: usize,
+}
+
+/// A table in scope and where its current row is read from.
+#[derive(Clone, Copy)]
+struct Source<'a> {
+    table: planner::TableRef<'a>,
+    row: RowSource<'a>,
+}
+
+impl<'a> Source<'a> {
+    /// The row at `cursor` in `table`.
+    fn cursor(table: &'a TableSchema, cursor: usize) -> Self {
+        Self { table: planner::TableRef::new(table), row: RowSource::Cursor(cursor) }
+    }
+}
+
+/// Where the current row of a table is read from.
+#[derive(Clone, Copy)]
+enum RowSource<'a> {
+    Cursor(usize),
+    /// The entry at an index cursor that holds every column read, where
+    /// `columns` are the positions in the table of the indexed columns.
+    Index { cursor: usize, columns: &'a [usize] },
+    /// A copy of the row in the registers starting at the given one:
+    /// each column in turn followed by the row id.
+    Registers(usize),
+}
+
+/// Accumulates instructions and allocates registers and cursors for a
+/// [`Program`].
+#[derive(Default)]
+struct ProgramBuilder {
+    instructions: Vec<Instruction>,
+    num_registers: usize,
+    num_cursors: usize,
+    changes_verb: Option<&'static str>,
+    /// Steps of the query plan, as listed by `EXPLAIN QUERY PLAN`.
+    plan: Vec<String>,
+    /// Depth of the shallowest scope with a column referred to since
+    /// this was last cleared, which tells whether a subquery refers to
+    /// the statements around it.
+    shallowest_reference: Option<usize>,
+}
+
+impl ProgramBuilder {
+    /// Appends an instruction and returns its address.
+    fn emit(&mut self, opcode: Opcode, p1: usize, p2: usize, p3: usize, p4: P4, comment: &str) -> usize {
+        self.instructions.push(Instruction { opcode, p1, p2, p3, p4, comment: String::from(comment) });
+        self.instructions.len() - 1
+    }
+
+    /// Records a step of the query plan.
+    fn note(&mut self, detail: String) {
+        self.plan.push(detail);
+    }
+
+    /// Address of the next instruction to be emitted.
+    fn current_addr(&self) -> usize {
+        self.instructions.len()
+    }
+
+    /// Points the jump target `p2` of the instruction at `addr` to the
+    /// next instruction to be emitted.
+    fn patch_jump(&mut self, addr: usize) {
+        self.instructions[addr].p2 = self.current_addr();
+    }
+
+    fn alloc_register(&mut self) -> usize {
+        self.alloc_registers(1)
+    }
+
+    /// Reserves `count` consecutive registers and returns the first.
+    fn alloc_registers(&mut self, count: usize) -> usize {
+        self.num_registers += count;
+        self.num_registers - count
+    }
+
+    fn alloc_cursor(&mut self) -> usize {
+        self.num_cursors += 1;
+        self.num_cursors - 1
+    }
+
+    fn build(self) -> Program {
+        Program {
+            instructions: self.instructions,
+            num_registers: self.num_registers,
+            num_cursors: self.num_cursors,
+            explain: false,
+            changes_verb: self.changes_verb,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+    use crate::{cursor, pager, parser, table, value::Value, vm};
+
+    fn run(sql: &str, database: &database::Database) -> Result<Vec<Vec<Value>>, String> {
+        let statement = parser::parse(sql).map_err(|e| e.to_string())?;
+        let program = compile(&statement, database).map_err(|e| e.to_string())?;
+        let mut vm = vm::Vm::new(program, database);
+        let mut rows = Vec::new();
+        while let vm::StepResult::Row(row) = vm.step().map_err(|e| e.to_string())? {
+            rows.push(row);
+        }
+        Ok(rows)
+    }
+
+    fn text(value: &str) -> Value {
+        Value::Text(String::from(value))
+    }
+
+    /// Returns an in-memory database holding an empty `users` table.
+    fn test_database() -> database::Database {
+        let database = database::Database::open(pager::Pager::in_memory(4096, 16).unwrap()).unwrap();
+        run("create table users (id integer primary key, username text not null, email text)", &database).unwrap();
+        database
+    }
+
+    #[test]
+    fn inserted_rows_are_selected_in_id_order() {
+        let database = test_database();
+        run("insert into users values (2, 'bob', 'b@x'), (1, 'alice', 'a@x')", &database).unwrap();
+        assert_eq!(
+            vec![vec![text("a@x"), Value::Integer(1)], vec![text("b@x"), Value::Integer(2)]],
+            run("select email, id from users", &database).unwrap()
+        );
+        assert_eq!(
+            Err(String::from("UNIQUE constraint failed: users.id")),
+            run("insert into users values (1, 'carol', 'c@x')", &database)
+        );
+        assert_eq!(
+            Err(String::from("UNIQUE constraint failed: users.id")),
+            run("insert into users values (9, 'dave', 'd@x'), (1, 'erin', 'e@x')", &database)
+        );
+        assert_eq!(2, run("select id from users", &database).unwrap().len());
+    }
+
+    #[test]
+    fn insert_with_column_list_fills_columns_by_name() {
+        let database = test_database();
+        run("insert into users (email, username, id) values ('e', 'u', 7)", &database).unwrap();
+        run("insert into users (username) values ('v')", &database).unwrap();
+        assert_eq!(
+            vec![
+                vec![Value::Integer(7), text("u"), text("e")],
+                vec![Value::Integer(8), text("v"), Value::Null],
+            ],
+            run("select * from users", &database).unwrap()
+        );
+        assert_eq!(
+            Err(String::from("NOT NULL constraint failed: users.username")),
+            run("insert into users (id, email) values (9, 'e')", &database)
+        );
+    }
+
+    #[test]
+    fn tables_without_integer_primary_key_number_rows_in_insert_order() {
+        let database = test_database();
+        run("create table notes (body, author varchar(20))", &database).unwrap();
+        run("insert into notes values ('first', 'a'), ('second', NULL)", &database).unwrap();
+        assert_eq!(
+            vec![
+                vec![Value::Integer(1), text("first"), text("a")],
+                vec![Value::Integer(2), text("second"), Value::Null],
+            ],
+            run("select rowid, * from notes", &database).unwrap()
+        );
+    }
+
+    #[test]
+    fn inserted_values_are_converted_to_column_affinity() {
+        let database = test_database();
+        run("create table t (a integer, b real, c blob, d text, e)", &database).unwrap();
+        run("insert into t values (1, 2.5, x'00ff', NULL, 'x'), (' 2 ', 3, '4', 5, 6.0)", &database).unwrap();
+        assert_eq!(
+            vec![
+                vec![Value::Integer(1), Value::Real(2.5), Value::Blob(vec![0x00, 0xff]), Value::Null, text("x")],
+                vec![Value::Integer(2), Value::Real(3.0), text("4"), text("5"), Value::Real(6.0)],
+            ],
+            run("select * from t", &database).unwrap()
+        );
+    }
+
+    #[test]
+    fn rows_larger_than_a_page_are_stored_in_overflow_pages() {
+        let database = test_database();
+        let long = "x".repeat(20_000);
+        run(&format!("insert into users values (1, '{long}', x'{}')", "ab".repeat(5000)), &database).unwrap();
+        run("insert into users values (2, 'short', NULL)", &database).unwrap();
+        assert_eq!(
+            vec![
+                vec![Value::Integer(1), text(&long), Value::Blob(vec![0xab; 5000])],
+                vec![Value::Integer(2), text("short"), Value::Null],
+            ],
+            run("select * from users", &database).unwrap()
+        );
+    }
+
+    #[test]
+    fn delete_removes_rows_matching_where_clause() {
+        let database = test_database();
+        for id in 1..=300 {
+            run(&format!("insert into users values ({id}, 'user{id}', NULL)"), &database).unwrap();
+        }
+        let statement = parser::parse("delete from users where id > 100 and not id >= 250 or username = 'user7'").unwrap();
+        let program = compile(&statement, &database).unwrap();
+        assert_eq!(Some("deleted"), program.changes_verb);
+        let mut vm = vm::Vm::new(program, &database);
+        while let vm::StepResult::Row(_) = vm.step().unwrap() {}
+        assert_eq!(150, vm.changes());
+        let ids: Vec<Vec<Value>> = (1..=300)
+            .filter(|id| !(*id > 100 && *id < 250 || *id == 7))
+            .map(|id| vec![Value::Integer(id)])
+            .collect();
+        assert_eq!(ids, run("select id from users", &database).unwrap());
+        // NULL conditions do not match.
+        run("delete from users where email = NULL", &database).unwrap();
+        assert_eq!(ids.len(), run("select id from users", &database).unwrap().len());
+        run("delete from users", &database).unwrap();
+        assert!(run("select * from users", &database).unwrap().is_empty());
+        assert!(run("delete from sqlite_schema", &database).is_err());
+    }
+
+    #[test]
+    fn update_rewrites_matching_rows() {
+        let database = test_database();
+        for id in 1..=100 {
+            run(&format!("insert into users values ({id}, 'user{id}', NULL)"), &database).unwrap();
+        }
+        let statement = parser::parse("update users set email = username || '@x', username = 'u' || (id * 2) where id % 10 = 0").unwrap();
+        let program = compile(&statement, &database).unwrap();
+        assert_eq!(Some("updated"), program.changes_verb);
+        let mut vm = vm::Vm::new(program, &database);
+        while let vm::StepResult::Row(_) = vm.step().unwrap() {}
+        assert_eq!(10, vm.changes());
+        assert_eq!(
+            vec![
+                vec![Value::Integer(9), text("user9"), Value::Null],
+                vec![Value::Integer(10), text("u20"), text("user10@x")],
+            ],
+            run("select * from users", &database).unwrap()[8..10].to_vec()
+        );
+        // Values grow into overflow pages and shrink back.
+        let long = "y".repeat(10_000);
+        run(&format!("update users set email = '{long}' where id <= 50"), &database).unwrap();
+        assert_eq!(vec![text(&long)], run("select email from users", &database).unwrap()[49]);
+        run("update users set email = NULL", &database).unwrap();
+        assert!(run("select email from users", &database).unwrap().iter().all(|row| row[0] == Value::Null));
+        assert_eq!(
+            Err(String::from("NOT NULL constraint failed: users.username")),
+            run("update users set username = NULL where id = 1", &database)
+        );
+        assert_eq!(Err(String::from("no such column: age")), run("update users set age = 1", &database));
+        assert!(run("update sqlite_schema set name = 'x'", &database).is_err());
+    }
+
+    #[test]
+    fn update_of_primary_key_moves_rows() {
+        let database = test_database();
+        for id in 1..=50 {
+            run(&format!("insert into users values ({id}, 'user{id}', NULL)"), &database).unwrap();
+        }
+        // Each row moves once, even though it moves past rows the scan
+        // has not reached yet.
+        run("update users set id = id + 100", &database).unwrap();
+        let ids: Vec<Vec<Value>> = (1..=50).map(|id| vec![Value::Integer(id + 100), text(&format!("user{id}"))]).collect();
+        assert_eq!(ids, run("select id, username from users", &database).unwrap());
+        assert_eq!(
+            Err(String::from("UNIQUE constraint failed: users.id")),
+            run("update users set id = 102 where id = 101", &database)
+        );
+        assert_eq!(Err(String::from("datatype mismatch")), run("update users set id = 'a' where id = 101", &database));
+        // Rows moved before a conflict is found are moved back.
+        run("delete from users where id in (102, 103)", &database).unwrap();
+        assert_eq!(
+            Err(String::from("UNIQUE constraint failed: users.id")),
+            run("update users set id = id + 2 where id < 110", &database)
+        );
+        let ids: Vec<Vec<Value>> = [101, 104, 105, 106, 107, 108, 109].into_iter().map(|id| vec![Value::Integer(id)]).collect();
+        assert_eq!(ids, run("select id from users where id < 110", &database).unwrap());
+        run("update users set rowid = -rowid where username = 'user1'", &database).unwrap();
+        assert_eq!(vec![Value::Integer(-101), text("user1")], run("select id, username from users", &database).unwrap()[0]);
+    }
+
+    #[test]
+    fn select_filters_sorts_and_limits_rows() {
+        let database = test_database();
+        run("insert into users values (1, 'carol', 'c@x'), (2, 'alice', NULL), (3, 'bob', 'b@x'), (4, 'alice', 'a@x')", &database).unwrap();
+        let ids = |sql: &str| -> Vec<i64> {
+            run(sql, &database).unwrap().iter().map(|row| match row[0] {
+                Value::Integer(id) => id,
+                _ => panic!("expected id"),
+            }).collect()
+        };
+        assert_eq!(vec![3, 4], ids("select id from users where email is not null and id > 2"));
+        assert_eq!(vec![2], ids("select id from users where email is null"));
+        assert_eq!(vec![4, 2, 3, 1], ids("select id from users order by username, email desc"));
+        assert_eq!(vec![1, 3, 2], ids("select id, username name from users order by name desc limit 3"));
+        assert_eq!(vec![3, 4], ids("select id from users order by 1 limit 2 offset 2"));
+        assert_eq!(vec![2, 3], ids("select id from users limit 1, 2"));
+        assert!(ids("select id from users limit 0").is_empty());
+        assert_eq!(vec![1, 2, 3, 4], ids("select id from users limit -1"));
+        assert_eq!(
+            vec![vec![Value::Integer(6), text("ab"), Value::Integer(1), Value::Integer(0)]],
+            run("select 1 + 2 * 5 / 2, 'a' || 'b', null is null, 1 is not 1 where 1", &database).unwrap()
+        );
+        assert_eq!(
+            Err(String::from("2nd ORDER BY term out of range - should be between 1 and 1")),
+            run("select id from users order by 1, 2", &database)
+        );
+    }
+
+    #[test]
+    fn aggregates_are_computed_per_group() {
+        let database = test_database();
+        run("insert into users values (1, 'carol', 'c@x'), (2, 'alice', NULL), (3, 'bob', 'b@x'), (4, 'alice', 'a@x')", &database).unwrap();
+        assert_eq!(
+            vec![vec![Value::Integer(4), Value::Integer(3), Value::Integer(10), Value::Real(2.5), Value::Integer(1), text("c@x")]],
+            run("select count(*), count(email), sum(id), avg(id), min(id), max(email) from users", &database).unwrap()
+        );
+        assert_eq!(
+            vec![
+                vec![text("alice"), Value::Integer(2), text("2;4")],
+                vec![text("bob"), Value::Integer(1), text("3")],
+                vec![text("carol"), Value::Integer(1), text("1")],
+            ],
+            run("select username, count(*), group_concat(id, ';') from users group by username", &database).unwrap()
+        );
+        assert_eq!(
+            vec![vec![text("bob"), Value::Integer(3)], vec![text("alice"), Value::Integer(4)]],
+            run("select username, max(id) m from users where id > 1 group by 1 + 1, username having count(*) < 2 or max(id) = 4 order by m limit 2", &database).unwrap()
+        );
+        assert_eq!(
+            vec![vec![Value::Integer(0), Value::Null]],
+            run("select count(*), sum(id) from users where id > 10", &database).unwrap()
+        );
+        assert!(run("select count(*) from users where id > 10 group by username", &database).unwrap().is_empty());
+    }
+
+    #[test]
+    fn misused_functions_return_errors() {
+        let database = test_database();
+        let error = |sql| run(sql, &database).unwrap_err();
+        assert_eq!("no such function: nope", error("select nope(id) from users"));
+        assert_eq!("wrong number of arguments to function min()", error("select min(id, 1) from users"));
+        assert_eq!("misuse of aggregate function count()", error("select id from users where count(*) > 1"));
+        assert_eq!("misuse of aggregate function sum()", error("select sum(max(id)) from users"));
+        assert_eq!("misuse of aggregate function max()", error("select id from users group by max(id)"));
+    }
+
+    #[test]
+    fn select_program_loops_over_table() {
+        let database = test_database();
+        let statement = parser::parse("select username from users").unwrap();
+        let opcodes: Vec<Opcode> = compile(&statement, &database).unwrap()
+            .instructions
+            .iter()
+            .map(|instruction| instruction.opcode)
+            .collect();
+        assert_eq!(
+            vec![
+                Opcode::Init,
+                Opcode::OpenRead,
+                Opcode::Rewind,
+                Opcode::Column,
+                Opcode::ResultRow,
+                Opcode::Next,
+                Opcode::Halt,
+            ],
+            opcodes
+        );
+    }
+
+    #[test]
+    fn unknown_tables_and_columns_return_errors() {
+        let database = test_database();
+        assert_eq!(Err(String::from("no such table: nope")), run("select * from nope", &database));
+        assert_eq!(Err(String::from("no such column: age")), run("select age from users", &database));
+        assert_eq!(Err(String::from("no such column: t.id")), run("select t.id from users", &database));
+    }
+
+    #[test]
+    fn creating_existing_or_reserved_tables_returns_errors() {
+        let database = test_database();
+        assert_eq!(Err(String::from("table users already exists")), run("create table users (a)", &database));
+        assert_eq!(Ok(Vec::new()), run("create table if not exists users (a)", &database));
+        assert_eq!(
+            Err(String::from("object name reserved for internal use: sqlite_x")),
+            run("create table sqlite_x (a)", &database)
+        );
+        assert_eq!(Err(String::from("duplicate column name: A")), run("create table t (a, A)", &database));
+    }
+
+    #[test]
+    fn indexes_find_rows_and_follow_changes() {
+        let database = test_database();
+        for id in 1..=200 {
+            run(&format!("insert into users values ({id}, 'user{}', 'e{id}')", id % 20), &database).unwrap();
+        }
+        run("create index by_name on users (username, email)", &database).unwrap();
+        let statement = parser::parse("select id from users where 'user3' = username and id > 100").unwrap();
+        let program = compile(&statement, &database).unwrap();
+        assert!(program.instructions.iter().any(|instruction| instruction.opcode == Opcode::SeekGe));
+        let ids = |sql: &str| -> Vec<Value> {
+            run(sql, &database).unwrap().into_iter().map(|row| row[0].clone()).collect()
+        };
+        // Rows come in index order, by email within the name.
+        assert_eq!(
+            [103, 123, 143, 163, 183].map(Value::Integer).to_vec(),
+            ids("select id from users where 'user3' = username and id > 100")
+        );
+        assert_eq!(vec![Value::Integer(10)], ids("select count(*) from users where username = 'user7'"));
+
+        run("update users set username = 'moved' where id % 20 = 7 and id < 100", &database).unwrap();
+        run("delete from users where id = 187", &database).unwrap();
+        run("insert into users values (500, 'user7', 'new')", &database).unwrap();
+        assert_eq!(
+            [107, 127, 147, 167, 500].map(Value::Integer).to_vec(),
+            ids("select id from users where username = 'user7'")
+        );
+        assert_eq!(vec![Value::Integer(47)], ids("select id from users where username = 'moved' and email = 'e47'"));
+        run("delete from users", &database).unwrap();
+        assert!(ids("select id from users where username = 'moved'").is_empty());
+    }
+
+    #[test]
+    fn unique_indexes_reject_duplicate_values() {
+        let database = test_database();
+        run("insert into users values (1, 'a', 'a@x'), (2, 'b', NULL), (3, 'c', NULL), (4, 'd', 'a@x')", &database).unwrap();
+        let pages = |database: &database::Database| {
+            let mut pager = database.pager().borrow_mut();
+            (pager.num_pages(), pager.free_page_count().unwrap())
+        };
+        let before = pages(&database);
+        assert_eq!(
+            Err(String::from("UNIQUE constraint failed: users.email")),
+            run("create unique index by_email on users (email)", &database)
+        );
+        // The index's B-tree is dropped with the rest of the statement.
+        assert_eq!(before, pages(&database));
+        run("delete from users where id = 4", &database).unwrap();
+        run("create unique index by_email on users (email)", &database).unwrap();
+        assert_eq!(
+            Err(String::from("UNIQUE constraint failed: users.email")),
+            run("insert into users values (5, 'e', 'a@x')", &database)
+        );
+        // A row that fails undoes the rows of the statement before it.
+        assert_eq!(
+            Err(String::from("UNIQUE constraint failed: users.email")),
+            run("insert into users values (6, 'f', 'f@x'), (7, 'g', 'a@x')", &database)
+        );
+        assert!(run("select id from users where email = 'f@x'", &database).unwrap().is_empty());
+        assert!(run("select email from users where id = 6", &database).unwrap().is_empty());
+        assert_eq!(
+            Err(String::from("UNIQUE constraint failed: users.email")),
+            run("update users set email = 'a@x' where id = 2", &database)
+        );
+        // NULLs never conflict, and rows keep their own values when
+        // they move to a new id.
+        run("insert into users values (5, 'e', NULL)", &database).unwrap();
+        run("update users set id = id + 10, email = email", &database).unwrap();
+        assert_eq!(
+            vec![vec![Value::Integer(11), text("a")]],
+            run("select id, username from users where email = 'a@x'", &database).unwrap()
+        );
+        assert_eq!(3, run("select id from users where email is null", &database).unwrap().len());
+    }
+
+    #[test]
+    fn creating_invalid_indexes_returns_errors() {
+        let database = test_database();
+        run("create index i on users (email)", &database).unwrap();
+        let error = |sql| run(sql, &database).unwrap_err();
+        assert_eq!("index i already exists", error("create index i on users (username)"));
+        assert_eq!(Ok(Vec::new()), run("create index if not exists i on users (username)", &database));
+        assert_eq!("no such table: nope", error("create index j on nope (a)"));
+        assert_eq!("no such column: age", error("create index j on users (age)"));
+        assert_eq!("table sqlite_schema may not be modified", error("create index j on sqlite_schema (name)"));
+        assert_eq!("object name reserved for internal use: sqlite_j", error("create index sqlite_j on users (email)"));
+        assert_eq!(
+            vec![vec![text("index"), text("i"), text("users"), text("CREATE INDEX i ON users (email)")]],
+            run("select type, name, tbl_name, sql from sqlite_schema where type = 'index'", &database).unwrap()
+        );
+    }
+
+    #[test]
+    fn query_plan_shows_how_each_table_is_read() {
+        let database = test_database();
+        let plan = |sql: &str| -> Vec<Value> {
+            run(&format!("explain query plan {sql}"), &database).unwrap().into_iter().map(|row| row[0].clone()).collect()
+        };
+        assert_eq!(vec![text("SCAN users")], plan("select * from users where email = 'a@x'"));
+        run("create index by_name on users (username, email)", &database).unwrap();
+        assert_eq!(vec![text("SCAN users USING COVERING INDEX by_name")], plan("select * from users where email = 'a@x'"));
+        assert_eq!(
+            vec![text("SEARCH users USING INTEGER PRIMARY KEY (rowid=?)")],
+            plan("select * from users where id = 3 and username = 'a'")
+        );
+        assert_eq!(
+            vec![text("SEARCH users USING COVERING INDEX by_name (username=? AND email>?)")],
+            plan("delete from users where username = 'a' and email > 'b'")
+        );
+        assert_eq!(
+            vec![text("SCAN users USING COVERING INDEX by_name"), text("USE TEMP B-TREE FOR GROUP BY")],
+            plan("select email, count(*) from users group by email")
+        );
+        assert_eq!(
+            vec![text("SEARCH users USING COVERING INDEX by_name (username=?)"), text("USE TEMP B-TREE FOR ORDER BY")],
+            plan("select id from users where username = 'a' order by email desc")
+        );
+        assert!(plan("insert into users values (1, 'a', 'b')").is_empty());
+    }
+
+    #[test]
+    fn planned_lookups_find_the_same_rows_as_scans() {
+        let database = test_database();
+        for id in 1..=100 {
+            run(&format!("insert into users values ({id}, 'user{}', 'e{}')", id % 10, id % 7), &database).unwrap();
+        }
+        let queries = [
+            "select id from users where id = 42",
+            "select id from users where id = '42'",
+            "select id from users where id = 4.5",
+            "select id from users where id = 'x'",
+            "select id from users where username = 'user3' and email >= 'e2' and email < 'e5'",
+            "select id, email from users where username > 'user7'",
+            "select email, count(*) from users where username <= 'user1' group by email",
+            "select count(*) from users where username = 'user3' and email = 3",
+        ];
+        // Rows found through the index come in index order instead of
+        // id order.
+        let sorted_rows = |sql: &str| {
+            let mut rows = run(sql, &database).unwrap();
+            rows.sort_by(|a, b| a[0].compare(&b[0]).unwrap());
+            rows
+        };
+        let expected: Vec<_> = queries.iter().map(|sql| sorted_rows(sql)).collect();
+        run("create index by_name on users (username, email)", &database).unwrap();
+        for (sql, expected) in queries.iter().zip(expected) {
+            assert_eq!(expected, sorted_rows(sql), "{sql}");
+        }
+
+        run("update users set email = 'moved' where username = 'user3' and email < 'e3'", &database).unwrap();
+        run("delete from users where username = 'user4'", &database).unwrap();
+        assert_eq!(vec![vec![Value::Integer(4)]], run("select count(*) from users where email = 'moved'", &database).unwrap());
+        assert_eq!(vec![vec![Value::Integer(4)]], run("select count(*) from users where username = 'user3' and email = 'moved'", &database).unwrap());
+        assert!(run("select id from users where username = 'user4'", &database).unwrap().is_empty());
+        assert_eq!(vec![vec![Value::Integer(90)]], run("select count(*) from users", &database).unwrap());
+    }
+
+    #[test]
+    fn analyze_stores_statistics_that_guide_the_planner() {
+        let database = test_database();
+        // Almost every user shares one name, while emails are unique.
+        for id in 1..=300 {
+            let name = if id % 100 == 0 { "admin" } else { "user" };
+            run(&format!("insert into users values ({id}, '{name}', 'e{id}')"), &database).unwrap();
+        }
+        run("create index by_name on users (username)", &database).unwrap();
+        run("create index by_email on users (email)", &database).unwrap();
+        let plan = |sql: &str| -> Value {
+            run(&format!("explain query plan {sql}"), &database).unwrap().remove(0).remove(0)
+        };
+        let by_name = "select * from users where username = 'user' and id > 5";
+        assert_eq!(text("SEARCH users USING INDEX by_name (username=?)"), plan(by_name));
+
+        run("analyze", &database).unwrap();
+        assert_eq!(
+            vec![
+                vec![text("users"), Value::Null, text("300")],
+                vec![text("users"), text("by_name"), text("300 150")],
+                vec![text("users"), text("by_email"), text("300 1")],
+            ],
+            run("select * from sqlite_stat1", &database).unwrap()
+        );
+        assert_eq!(text("SCAN users"), plan(by_name));
+        assert_eq!(
+            text("SEARCH users USING INDEX by_email (email=?)"),
+            plan("select * from users where username = 'user' and email = 'e7'")
+        );
+        assert_eq!(292, run(by_name, &database).unwrap().len());
+
+        // Analysing one table replaces only its own statistics.
+        run("create table empty (a)", &database).unwrap();
+        run("create index by_a on empty (a)", &database).unwrap();
+        run("delete from users where id > 10", &database).unwrap();
+        run("analyze empty", &database).unwrap();
+        run("analyze users", &database).unwrap();
+        assert_eq!(
+            vec![
+                vec![text("empty"), Value::Null, text("0")],
+                vec![text("users"), Value::Null, text("10")],
+                vec![text("users"), text("by_name"), text("10 10")],
+                vec![text("users"), text("by_email"), text("10 1")],
+            ],
+            run("select * from sqlite_stat1", &database).unwrap()
+        );
+        let schema = database.schema().borrow();
+        assert_eq!(Some(10), schema.table("users").unwrap().row_count);
+        assert_eq!(vec![1], schema.index("by_email").unwrap().rows_per_key);
+        assert!(schema.index("by_a").unwrap().rows_per_key.is_empty());
+        drop(schema);
+        assert_eq!(Err(String::from("no such table: nope")), run("analyze nope", &database));
+        assert_eq!(
+            Err(String::from("table sqlite_stat1 already exists")),
+            run("create table sqlite_stat1 (a)", &database)
+        );
+    }
+
+    /// Returns a database with `users` and their `orders`, where one
+    /// order belongs to no user and one user has no orders.
+    fn orders_database() -> database::Database {
+        let database = test_database();
+        run("create table orders (id integer primary key, user_id integer, total)", &database).unwrap();
+        run("insert into users values (1, 'alice', 'a@x'), (2, 'bob', NULL), (3, 'carol', 'c@x')", &database).unwrap();
+        run("insert into orders values (10, 1, 5), (11, 1, 7), (12, 2, 3), (13, 9, 1)", &database).unwrap();
+        database
+    }
+
+    #[test]
+    fn joins_pair_rows_of_each_table() {
+        let database = orders_database();
+        let pairs = vec![
+            vec![text("alice"), Value::Integer(5)],
+            vec![text("alice"), Value::Integer(7)],
+            vec![text("bob"), Value::Integer(3)],
+        ];
+        for sql in [
+            "select username, total from users join orders on user_id = users.id",
+            "select u.username, o.total from users as u inner join orders o on o.user_id = u.id",
+            "select username, total from users, orders where orders.user_id = users.id",
+            "select username, total from users cross join orders where user_id = users.id",
+        ] {
+            assert_eq!(pairs, run(sql, &database).unwrap(), "{sql}");
+        }
+        run("create index orders_by_user on orders (user_id)", &database).unwrap();
+        assert_eq!(pairs, run("select username, total from users join orders on user_id = users.id", &database).unwrap());
+        assert_eq!(
+            vec![vec![Value::Integer(12), Value::Integer(2), text("bob"), Value::Null]],
+            run("select o.id, users.* from orders o, users where o.user_id = users.id and total < 5", &database).unwrap()
+        );
+        assert_eq!(
+            vec![vec![text("alice"), text("bob")], vec![text("bob"), text("carol")]],
+            run("select a.username, b.username from users a join users b on b.id = a.id + 1", &database).unwrap()
+        );
+        assert_eq!(vec![vec![Value::Integer(12)]], run("select count(*) from users, orders", &database).unwrap());
+
+        let error = |sql| run(sql, &database).unwrap_err();
+        assert_eq!("ambiguous column name: id", error("select id from users join orders on user_id = users.id"));
+        assert_eq!("no such column: users.total", error("select users.total from users, orders"));
+        assert_eq!("no such column: users.id", error("select 1 from users u join orders on user_id = users.id"));
+        assert_eq!("no such table: o", error("select o.* from users, orders"));
+        assert_eq!("no such table: nope", error("select 1 from users join nope"));
+    }
+
+    #[test]
+    fn left_joins_keep_rows_without_a_match() {
+        let database = orders_database();
+        let expected = vec![
+            vec![text("alice"), Value::Integer(10)],
+            vec![text("alice"), Value::Integer(11)],
+            vec![text("bob"), Value::Integer(12)],
+            vec![text("carol"), Value::Null],
+        ];
+        let sql = "select username, orders.id from users left join orders on user_id = users.id";
+        assert_eq!(expected, run(sql, &database).unwrap());
+        run("create index orders_by_user on orders (user_id)", &database).unwrap();
+        assert_eq!(expected, run(sql, &database).unwrap());
+        assert_eq!(
+            vec![
+                vec![text("alice"), Value::Integer(2), Value::Integer(12)],
+                vec![text("bob"), Value::Integer(1), Value::Integer(3)],
+                vec![text("carol"), Value::Integer(0), Value::Null],
+            ],
+            run("select username, count(o.id), sum(o.total) from users left outer join orders o on o.user_id = users.id group by username", &database).unwrap()
+        );
+        // WHERE applies after the join, while ON decides what matches.
+        assert_eq!(
+            vec![vec![text("carol")]],
+            run("select username from users left join orders on user_id = users.id where orders.id is null", &database).unwrap()
+        );
+        assert_eq!(
+            vec![vec![Value::Integer(1), Value::Integer(11)], vec![Value::Integer(2), Value::Null], vec![Value::Integer(3), Value::Null]],
+            run("select users.id, orders.id from users left join orders on user_id = users.id and total > 5", &database).unwrap()
+        );
+        // Orders looked up by id, and users found only in the index.
+        assert_eq!(
+            vec![vec![Value::Integer(13), Value::Null, Value::Null], vec![Value::Integer(12), Value::Integer(2), text("bob")]],
+            run("select o.id, u.id, u.username from orders o left join users u on u.id = o.user_id where o.id > 11 order by o.id desc", &database).unwrap()
+        );
+        run("create index users_by_name on users (username)", &database).unwrap();
+        assert_eq!(
+            vec![vec![Value::Integer(1), Value::Null], vec![Value::Integer(1), Value::Null], vec![Value::Integer(2), text("bob")], vec![Value::Integer(9), Value::Null]],
+            run("select user_id, u.username from orders left join users u on u.username = 'bob' and u.id = user_id", &database).unwrap()
+        );
+    }
+
+    #[test]
+    fn query_plan_lists_each_joined_table() {
+        let database = orders_database();
+        run("create index orders_by_user on orders (user_id, total)", &database).unwrap();
+        let plan = |sql: &str| -> Vec<Value> {
+            run(&format!("explain query plan {sql}"), &database).unwrap().into_iter().map(|row| row[0].clone()).collect()
+        };
+        assert_eq!(
+            vec![text("SCAN users AS u"), text("SEARCH orders AS o USING COVERING INDEX orders_by_user (user_id=?)")],
+            plan("select u.username, o.total from users u join orders o on o.user_id = u.id")
+        );
+        assert_eq!(
+            vec![text("SCAN orders USING COVERING INDEX orders_by_user"), text("SEARCH users USING INTEGER PRIMARY KEY (rowid=?)")],
+            plan("select username from orders left join users on users.id = user_id")
+        );
+        // A left joined table cannot be looked up with the WHERE clause.
+        assert_eq!(
+            vec![text("SCAN users"), text("SCAN orders USING COVERING INDEX orders_by_user")],
+            plan("select 1 from users left join orders on total > 2 where orders.id = users.id")
+        );
+    }
+
+    #[test]
+    fn subqueries_and_in_lists_filter_and_compute_values() {
+        let database = orders_database();
+        let ids = |sql| -> Vec<Value> { run(sql, &database).unwrap().into_iter().map(|row| row[0].clone()).collect() };
+        let integers = |values: &[i64]| -> Vec<Value> { values.iter().map(|value| Value::Integer(*value)).collect() };
+        assert_eq!(integers(&[1, 3]), ids("select id from users where id in (3, 1, NULL)"));
+        assert_eq!(integers(&[2]), ids("select id from users where id not in (1, 3)"));
+        assert!(ids("select id from users where id not in (1, NULL)").is_empty());
+        assert!(ids("select id from users where id in ()").is_empty());
+        assert_eq!(integers(&[1, 2]), ids("select id from users where id in (select user_id from orders)"));
+        assert_eq!(integers(&[3]), ids("select id from users where id not in (select user_id from orders)"));
+        assert!(ids("select id from users where id not in (select email from users)").is_empty());
+        assert_eq!(
+            vec![vec![Value::Integer(1), Value::Null, Value::Integer(0), Value::Null]],
+            run("select 2 in (select user_id from orders), NULL in (1), NULL in (select 1 where 0), 4 in (select email from users)", &database).unwrap()
+        );
+        // Scalar subqueries give the first row's value, or NULL.
+        assert_eq!(
+            vec![vec![Value::Integer(13), Value::Null, text("alice")]],
+            run("select (select max(id) from orders), (select total from orders where id > 99), (select username from users order by id)", &database).unwrap()
+        );
+        // Correlated subqueries read the row of the statement around them.
+        let totals = vec![
+            vec![text("alice"), Value::Integer(12)],
+            vec![text("bob"), Value::Integer(3)],
+            vec![text("carol"), Value::Null],
+        ];
+        let sql = "select username, (select sum(total) from orders where user_id = users.id) from users";
+        assert_eq!(totals, run(sql, &database).unwrap());
+        run("create index orders_by_user on orders (user_id)", &database).unwrap();
+        assert_eq!(totals, run(sql, &database).unwrap());
+        assert_eq!(integers(&[1, 2]), ids("select id from users u where exists (select * from orders where user_id = u.id)"));
+        assert_eq!(integers(&[3]), ids("select id from users where not exists (select 1 from orders o where o.user_id = users.id)"));
+        assert_eq!(
+            integers(&[11, 12]),
+            ids("select id from orders o where total = (select max(total) from orders where user_id = o.user_id) and user_id in (select id from users)")
+        );
+        assert_eq!(
+            integers(&[1]),
+            ids("select id from users where 1 < (select count(*) from orders where user_id = users.id and total > (select min(total) from orders))")
+        );
+
+        run("insert into orders values ((select max(id) from orders) + 1, (select id from users where username = 'carol'), 4)", &database).unwrap();
+        run("update users set email = 'buyer' where id in (select user_id from orders where total > 3)", &database).unwrap();
+        assert_eq!(
+            vec![text("buyer"), Value::Null, text("buyer")],
+            ids("select email from users order by id")
+        );
+        run("delete from orders where not exists (select 1 from users where id = orders.user_id)", &database).unwrap();
+        assert_eq!(integers(&[10, 11, 12, 14]), ids("select id from orders"));
+
+        let error = |sql| run(sql, &database).unwrap_err();
+        assert_eq!("sub-select returns 2 columns - expected 1", error("select (select id, total from orders)"));
+        assert_eq!("sub-select returns 3 columns - expected 1", error("select 1 where 1 in (select * from orders)"));
+        assert_eq!("no such column: nope", error("select id from users where exists (select 1 from orders where nope = 1)"));
+        assert_eq!("ambiguous column name: id", error("select 1 from users, orders where exists (select 1 where id > 0)"));
+        // A name refers to the innermost table with such a column.
+        assert_eq!(12, run("select 1 from users, orders where exists (select 1 from users u where u.id = id)", &database).unwrap().len());
+    }
+
+    #[test]
+    fn query_plan_shows_which_subqueries_are_correlated() {
+        let database = orders_database();
+        run("create index orders_by_user on orders (user_id)", &database).unwrap();
+        let plan = |sql: &str| -> Vec<Value> {
+            run(&format!("explain query plan {sql}"), &database).unwrap().into_iter().map(|row| row[0].clone()).collect()
+        };
+        assert_eq!(
+            vec![
+                text("SCAN users"),
+                text("LIST SUBQUERY"),
+                text("SCAN orders USING COVERING INDEX orders_by_user"),
+                text("CORRELATED SCALAR SUBQUERY"),
+                text("SEARCH orders USING INDEX orders_by_user (user_id=?)"),
+            ],
+            plan("select id from users where id in (select user_id from orders) and exists (select total from orders where user_id = users.id)")
+        );
+        // The inner subquery refers to the outer statement, which makes
+        // the subquery around it correlated too.
+        assert_eq!(
+            vec![
+                text("SCAN users"),
+                text("CORRELATED SCALAR SUBQUERY"),
+                text("SCAN orders"),
+                text("CORRELATED SCALAR SUBQUERY"),
+                text("SEARCH users AS u USING INTEGER PRIMARY KEY (rowid=?)"),
+            ],
+            plan("select (select count(*) from orders where total > (select u.id from users u where u.id = users.id)) from users")
+        );
+    }
+
+    #[test]
+    fn compound_selects_combine_the_rows_of_each_select() {
+        let database = orders_database();
+        let values = |sql| -> Vec<Value> { run(sql, &database).unwrap().into_iter().map(|row| row[0].clone()).collect() };
+        let integers = |values: &[i64]| -> Vec<Value> { values.iter().map(|value| Value::Integer(*value)).collect() };
+        assert_eq!(integers(&[1, 2, 3, 1, 1, 2, 9]), values("select id from users union all select user_id from orders"));
+        assert_eq!(integers(&[1, 2, 3, 9]), values("select id from users union select user_id from orders"));
+        // A UNION leaves out repeats of the rows before it, but not of
+        // the rows after it.
+        assert_eq!(integers(&[1, 2, 1]), values("select 1 union select 1 union select 2 union all select 1"));
+        assert_eq!(vec![Value::Null], values("select NULL union select NULL"));
+        assert_eq!(integers(&[2, 3]), values("select id from users union all select id from orders limit 2 offset 1"));
+        assert_eq!(integers(&[1]), values("select id from users where id in (select 3 union select 1) and id < 3"));
+
+        let error = |sql| run(sql, &database).unwrap_err();
+        assert_eq!(
+            "SELECTs to the left and right of UNION ALL do not have the same number of result columns",
+            error("select 1 union all select 1, 2")
+        );
+        assert_eq!("ORDER BY clauses on compound SELECTs are not supported", error("select 1 union select 2 order by 1"));
+    }
+
+    #[test]
+    fn common_table_expressions_walk_hierarchies() {
+        let database = test_database();
+        run("create table nodes (id integer primary key, parent_id integer, name text)", &database).unwrap();
+        for (id, parent_id, name) in [(1, "NULL", "root"), (2, "1", "a"), (3, "1", "b"), (4, "2", "c"), (5, "4", "d")] {
+            run(&format!("insert into nodes values ({id}, {parent_id}, '{name}')"), &database).unwrap();
+        }
+        let rows = |sql| run(sql, &database).unwrap();
+        assert_eq!(
+            vec![
+                vec![text("a"), Value::Integer(0)],
+                vec![text("c"), Value::Integer(1)],
+                vec![text("d"), Value::Integer(2)],
+            ],
+            rows("with recursive below(id, depth) as (
+                select id, 0 from nodes where name = 'a'
+                union all
+                select nodes.id, below.depth + 1 from nodes join below on nodes.parent_id = below.id
+            ) select name, depth from below join nodes on nodes.id = below.id")
+        );
+        // Walking up from a leaf to the root.
+        assert_eq!(
+            vec![vec![text("d")], vec![text("c")], vec![text("a")], vec![text("root")]],
+            rows("with recursive above(id) as (
+                select 5 union all select parent_id from nodes, above where nodes.id = above.id
+            ) select name from above, nodes where nodes.id = above.id")
+        );
+        // A UNION stops at rows already found, so cycles end.
+        assert_eq!(3, rows("with recursive cycle(x) as (select 1 union select x % 3 + 1 from cycle) select x from cycle").len());
+        assert_eq!(
+            vec![vec![Value::Integer(3)], vec![Value::Integer(4)]],
+            rows("with recursive counter(x) as (select 1 union all select x + 1 from counter limit 2 offset 2) select * from counter")
+        );
+        // Tables defined by WITH can be read more than once, and by the
+        // ones after them.
+        assert_eq!(
+            vec![vec![text("a"), text("b")]],
+            rows("with children as (select * from nodes where parent_id = 1), named as (select name, id from children)
+                select x.name, y.name from named x, named y where x.id < y.id")
+        );
+        assert_eq!(
+            vec![vec![text("root"), Value::Integer(2)]],
+            rows("select name, (with c as (select 1 from nodes n where n.parent_id = nodes.id) select count(*) from c) from nodes where id = 1")
+        );
+
+        let error = |sql| run(sql, &database).unwrap_err();
+        assert_eq!("circular reference: t", error("with t as (select * from t) select * from t"));
+        assert_eq!("circular reference: t", error("with recursive t(x) as (select x from t union select x from t) select * from t"));
+        assert_eq!("table t has 1 values for 2 columns", error("with t(a, b) as (select 1) select * from t"));
+        assert_eq!("no such table: t", error("with s as (select 1) select * from t"));
+    }
+
+    #[test]
+    fn query_plan_shows_where_common_table_expressions_are_materialized() {
+        let database = orders_database();
+        let plan = |sql: &str| -> Vec<Value> {
+            run(&format!("explain query plan {sql}"), &database).unwrap().into_iter().map(|row| row[0].clone()).collect()
+        };
+        assert_eq!(
+            vec![text("MATERIALIZE big"), text("SCAN orders"), text("SCAN users"), text("SCAN big")],
+            plan("with big as (select user_id from orders where total > 3) select username from users, big where id = user_id")
+        );
+        assert_eq!(
+            vec![
+                text("SCAN users"),
+                text("CORRELATED SCALAR SUBQUERY"),
+                text("CORRELATED MATERIALIZE mine"),
+                text("SCAN orders"),
+                text("SCAN mine"),
+            ],
+            plan("select (with mine as (select total from orders where user_id = users.id) select sum(total) from mine) from users")
+        );
+    }
+
+    #[test]
+    fn catalog_statements_with_keywords_as_column_names_still_load() {
+        let database = test_database();
+        run("create table orders (id integer primary key, total)", &database).unwrap();
+        // Keywords that were once plain names may be stored unquoted by
+        // older versions.
+        let mut cursor = cursor::Cursor::new(Rc::clone(database.pager()), schema::SCHEMA_ROOT_PAGE);
+        cursor.last().unwrap();
+        let mut row = table::Row::deserialise(&cursor.value().unwrap()).unwrap();
+        row.values[4] = text("CREATE TABLE orders (id integer PRIMARY KEY, asc, desc integer, offset, left integer, cross, inner, outer, with text, recursive)");
+        cursor.update(&row.serialise()).unwrap();
+        let schema = Schema::load(database.pager()).unwrap();
+        let columns: Vec<&str> = schema.table("orders").unwrap().columns.iter().map(|column| column.name.as_str()).collect();
+        assert_eq!(vec!["id", "asc", "desc", "offset", "left", "cross", "inner", "outer", "with", "recursive"], columns);
+        *database.schema().borrow_mut() = schema;
+        run("insert into orders values (1, 2, 3, 4, 5, 6, 7, 8, 'w', 10)", &database).unwrap();
+        run("insert into users values (1, 'alice', NULL)", &database).unwrap();
+        assert_eq!(
+            vec![vec![Value::Integer(2), Value::Integer(4)]],
+            run("select asc, offset from orders order by desc desc limit 1 offset 0", &database).unwrap()
+        );
+        assert_eq!(
+            vec![vec![Value::Integer(5), Value::Integer(1)]],
+            run("select orders.left, users.id from orders left join users on left = users.id + 4 where inner + outer > 0", &database).unwrap()
+        );
+        assert_eq!(
+            vec![vec![text("w"), Value::Integer(10)]],
+            run("with recursive recursive as (select with, recursive from orders) select (with), recursive.recursive from recursive", &database).unwrap()
+        );
+    }
+
+    #[test]
+    fn vacuum_shrinks_the_file_and_keeps_every_row() {
+        let path = std::env::temp_dir()
+            .join(format!("sqlite-rust-{}-vacuum.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut database = database::Database::open(pager::Pager::open(&path, 1024, 4).unwrap()).unwrap();
+        run("create table a (x integer primary key, y)", &database).unwrap();
+        run("create table b (z text)", &database).unwrap();
+        run("create index by_y on a (y)", &database).unwrap();
+        for i in 0..400 {
+            run(&format!("insert into a values ({i}, '{}')", "a".repeat(i % 50)), &database).unwrap();
+            run(&format!("insert into b values ('{}')", "b".repeat(i * 7 % 3000)), &database).unwrap();
+        }
+        run("delete from a where x % 3 <> 0", &database).unwrap();
+        run("delete from b where rowid > 100", &database).unwrap();
+        let rows = (run("select * from a", &database).unwrap(), run("select * from b", &database).unwrap());
+        database.close().unwrap();
+        let size_before = std::fs::metadata(&path).unwrap().len();
+
+        run("vacuum", &database).unwrap();
+        assert_eq!(0, database.pager().borrow_mut().free_page_count().unwrap());
+        assert_eq!(rows, (run("select * from a", &database).unwrap(), run("select * from b", &database).unwrap()));
+        run("insert into a values (1000, 'new')", &database).unwrap();
+        database.close().unwrap();
+        drop(database);
+        assert!(std::fs::metadata(&path).unwrap().len() < size_before / 2);
+
+        let database = database::Database::open(pager::Pager::open(&path, 1024, 4).unwrap()).unwrap();
+        assert_eq!(rows.0.len() + 1, run("select * from a", &database).unwrap().len());
+        assert_eq!(rows.1, run("select * from b", &database).unwrap());
+        assert_eq!(
+            vec![vec![Value::Integer(3)], vec![Value::Integer(153)], vec![Value::Integer(303)]],
+            run("select x from a where y = 'aaa'", &database).unwrap()
+        );
+        let mut scratch_path = path.clone().into_os_string();
+        scratch_path.push("-vacuum");
+        assert!(!std::path::Path::new(&scratch_path).exists());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn tables_are_read_back_from_schema_catalog_after_reopening() {
+        let path = std::env::temp_dir()
+            .join(format!("sqlite-rust-{}-schema-reopen.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut database = database::Database::open(pager::Pager::open(&path, 1024, 4).unwrap()).unwrap();
+        run("create table a (x integer primary key, y)", &database).unwrap();
+        run("create table b (z text)", &database).unwrap();
+        for i in 0..50 {
+            run(&format!("insert into a values ({i}, 'a{i}')"), &database).unwrap();
+            run(&format!("insert into b values ('b{i}')"), &database).unwrap();
+        }
+        database.close().unwrap();
+        drop(database);
+
+        let database = database::Database::open(pager::Pager::open(&path, 1024, 4).unwrap()).unwrap();
+        assert_eq!(
+            vec![
+                vec![text("table"), text("a"), Value::Integer(2), text("CREATE TABLE a (x integer PRIMARY KEY, y)")],
+                vec![text("table"), text("b"), Value::Integer(3), text("CREATE TABLE b (z text)")],
+            ],
+            run("select type, name, rootpage, sql from sqlite_schema", &database).unwrap()
+        );
+        assert_eq!(50, run("select y from a", &database).unwrap().len());
+        assert_eq!(vec![text("b49")], run("select * from b", &database).unwrap()[49]);
+        assert!(run("insert into sqlite_schema values ('a', 'b', 'c', 1, 'd')", &database).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,727 @@
+use std::{cell::RefCell, rc::Rc};
+use crate::{btree::{self, BTreeError, Key, Node}, pager};
+
+/// A position within the B-tree of a table or index.
+///
+/// A cursor records the path from the root to the current cell, so it
+/// can step from one leaf to the next without the leaves linking to
+/// each other. The leaf the cursor points into is pinned in the page
+/// cache for as long as the cursor is there.
+pub struct Cursor {
+    pager: Rc<RefCell<pager::Pager>>,
+    root_page: usize,
+    /// Page number and cell index of every node from the root down to
+    /// the current leaf. For internal nodes the index is that of the
+    /// child taken, with the number of cells standing for the right
+    /// child.
+    stack: Vec<(usize, usize)>,
+    end_of_table: bool,
+    /// Set after a delete, which leaves the cursor on the row after the
+    /// deleted one, so the next advance stays on that row.
+    skip_next: bool,
+    pinned_page: Option<usize>,
+}
+
+impl Cursor {
+    /// Returns a cursor over the tree rooted at `root_page`. The cursor
+    /// starts at the end of the table until it is moved with [`rewind`]
+    /// or [`seek`].
+    ///
+    /// [`rewind`]: Self::rewind
+    /// [`seek`]: Self::seek
+    pub fn new(pager: Rc<RefCell<pager::Pager>>, root_page: usize) -> Self {
+        Self {
+            pager,
+            root_page,
+            stack: Vec::new(),
+            end_of_table: true,
+            skip_next: false,
+            pinned_page: None,
+        }
+    }
+
+    /// Returns a cursor pointing at the row with the smallest key in the
+    /// tree rooted at `root_page`.
+    pub fn table_start(pager: Rc<RefCell<pager::Pager>>, root_page: usize) -> Result<Self, BTreeError> {
+        let mut cursor = Self::new(pager, root_page);
+        cursor.rewind()?;
+        Ok(cursor)
+    }
+
+    /// Returns a cursor pointing at the row with key `key` in the tree
+    /// rooted at `root_page`, or at the row with the next largest key if
+    /// there is no such row.
+    pub fn table_find(pager: Rc<RefCell<pager::Pager>>, root_page: usize, key: i64) -> Result<Self, BTreeError> {
+        let mut cursor = Self::new(pager, root_page);
+        cursor.seek(key)?;
+        Ok(cursor)
+    }
+
+    /// Returns another cursor over the same tree, starting at the end of
+    /// the table.
+    pub fn duplicate(&self) -> Self {
+        Self::new(Rc::clone(&self.pager), self.root_page)
+    }
+
+    /// Points the cursor at the row with the smallest key.
+    pub fn rewind(&mut self) -> Result<(), BTreeError> {
+        self.move_to(Target::First)
+    }
+
+    /// Points the cursor at the row with the largest key, or at the end
+    /// of the table if it is empty.
+    pub fn last(&mut self) -> Result<(), BTreeError> {
+        self.move_to(Target::Last)
+    }
+
+    /// Returns `true` once the cursor has moved past the last row.
+    pub fn end_of_table(&self) -> bool {
+        self.end_of_table
+    }
+
+    /// Returns the key of the row the cursor points at.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the cursor is at the end of the table or over an
+    /// index.
+    pub fn key(&self) -> Result<i64, BTreeError> {
+        match self.cell_key()? {
+            Key::Integer(key) => Ok(key),
+            Key::Record(_) => panic!("index entries have no integer key"),
+        }
+    }
+
+    /// Returns the key of the entry the cursor points at, whether it is
+    /// a row id or an index record.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the cursor is at the end of the table.
+    pub fn cell_key(&self) -> Result<Key, BTreeError> {
+        self.with_cell(|cell| cell.key)
+    }
+
+    /// Returns the payload of the row the cursor points at.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the cursor is at the end of the table.
+    pub fn value(&self) -> Result<Vec<u8>, BTreeError> {
+        let cell = self.with_cell(|cell| cell)?;
+        cell.read_payload(&mut self.pager.borrow_mut())
+    }
+
+    /// Returns the record the cursor points at: the payload of a table
+    /// row or the key of an index entry.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the cursor is at the end of the table.
+    pub fn record(&self) -> Result<Vec<u8>, BTreeError> {
+        let cell = self.with_cell(|cell| cell)?;
+        match cell.key {
+            Key::Integer(_) => cell.read_payload(&mut self.pager.borrow_mut()),
+            Key::Record(record) => Ok(record),
+        }
+    }
+
+    /// Moves the cursor to the next row in key order.
+    pub fn advance(&mut self) -> Result<(), BTreeError> {
+        if self.end_of_table {
+            return Ok(());
+        }
+        if self.skip_next {
+            self.skip_next = false;
+            return Ok(());
+        }
+        if let Some((_, index)) = self.stack.last_mut() {
+            *index += 1;
+        }
+        self.skip_exhausted_leaves()
+    }
+
+    /// Inserts `payload` under `key`, splitting nodes that overflow,
+    /// and leaves the cursor pointing at the new row. Payloads too large
+    /// for a cell continue in overflow pages.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Err`] if `key` is already in the tree.
+    pub fn insert(&mut self, key: i64, payload: &[u8]) -> Result<(), BTreeError> {
+        let key = Key::Integer(key);
+        self.insert_cell(key.clone(), |pager| btree::LeafCell::new(pager, key, payload))
+    }
+
+    /// Inserts the serialised `record` into an index tree, where the
+    /// whole entry is the key, and leaves the cursor pointing at it.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Err`] if the record is already in the index or is too
+    /// large to be an index key.
+    pub fn insert_record(&mut self, record: &[u8]) -> Result<(), BTreeError> {
+        let key = Key::Record(record.to_vec());
+        self.insert_cell(key.clone(), |pager| btree::LeafCell::new(pager, key, &[]))
+    }
+
+    /// Inserts the cell made by `make_cell` under `key`, which is only
+    /// called once `key` is known not to be in the tree.
+    fn insert_cell(
+        &mut self,
+        key: Key,
+        make_cell: impl FnOnce(&mut pager::Pager) -> Result<btree::LeafCell, BTreeError>,
+    ) -> Result<(), BTreeError> {
+        let mut pager = self.pager.borrow_mut();
+        let (page_num, path) = self.descend(&mut pager, Target::Key(&key))?;
+        let mut node = Node::read(&mut pager, page_num)?;
+        if let Node::Leaf { cells, .. } = &mut node {
+            let index = cells.partition_point(|cell| cell.key < key);
+            if cells.get(index).is_some_and(|cell| cell.key == key) {
+                return Err(BTreeError::DuplicateKey(key));
+            }
+            cells.insert(index, make_cell(&mut pager)?);
+        }
+        self.balance(&mut pager, page_num, node, path)?;
+        drop(pager);
+        self.seek_key(&key)
+    }
+
+    /// Replaces the payload of the row the cursor points at, leaving the
+    /// cursor on that row.
+    ///
+    /// The row is rewritten in its leaf when the leaf still fits in a
+    /// page and stays at least a quarter full, and is otherwise deleted
+    /// and inserted again so the tree is rebalanced.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the cursor is at the end of the table.
+    pub fn update(&mut self, payload: &[u8]) -> Result<(), BTreeError> {
+        let key = Key::Integer(self.key()?);
+        let mut pager = self.pager.borrow_mut();
+        let page_size = pager.page_size();
+        let (leaf, path) = self.descend(&mut pager, Target::Key(&key))?;
+        let mut node = Node::read(&mut pager, leaf)?;
+        let cell = btree::LeafCell::new(&mut pager, key.clone(), payload)?;
+        let Node::Leaf { cells, .. } = &mut node else {
+            return Err(BTreeError::CorruptNode(leaf));
+        };
+        let index = cells.partition_point(|cell| cell.key < key);
+        let old_cell = std::mem::replace(&mut cells[index], cell.clone());
+        if node.size() <= page_size && (path.is_empty() || node.size() >= page_size / 4) {
+            node.write(&mut pager, leaf)?;
+            return old_cell.free_overflow(&mut pager);
+        }
+        drop(pager);
+        // Deleting the old row frees its overflow pages.
+        self.delete()?;
+        self.insert_cell(key, |_| Ok(cell))
+    }
+
+    /// Removes the row the cursor points at and leaves the cursor on the
+    /// row after it, so that the next [`advance`] does not move.
+    ///
+    /// Nodes left less than a quarter full are merged with a sibling,
+    /// or take cells from it if the two do not fit in one page, and the
+    /// tree loses a level when the root is left with a single child.
+    /// Pages emptied this way, and the row's overflow pages, go to the
+    /// pager's free list.
+    ///
+    /// [`advance`]: Self::advance
+    ///
+    /// # Panics
+    ///
+    /// Panics if the cursor is at the end of the table.
+    pub fn delete(&mut self) -> Result<(), BTreeError> {
+        let key = self.cell_key()?;
+        let mut pager = self.pager.borrow_mut();
+        let (page_num, path) = self.descend(&mut pager, Target::Key(&key))?;
+        let mut node = Node::read(&mut pager, page_num)?;
+        if let Node::Leaf { cells, .. } = &mut node {
+            let index = cells.partition_point(|cell| cell.key < key);
+            cells.remove(index).free_overflow(&mut pager)?;
+        }
+        self.balance(&mut pager, page_num, node, path)?;
+        drop(pager);
+        self.seek_key(&key)?;
+        self.skip_next = true;
+        Ok(())
+    }
+
+    /// Writes `node` back to `page_num`, below the internal nodes in
+    /// `path`, after restoring the tree's balance.
+    ///
+    /// A node too large for a page is split, adding a cell to its
+    /// parent. A node other than the root left less than a quarter full
+    /// is merged with a sibling, or takes cells from it if the two do
+    /// not fit in one page, which changes its parent's cells. Either
+    /// way the parent is balanced in turn. The root stays on the same
+    /// page, gaining a level when it splits and losing one when it is
+    /// left with a single child.
+    fn balance(&self, pager: &mut pager::Pager, mut page_num: usize, mut node: Node, mut path: Vec<(usize, usize)>) -> Result<(), BTreeError> {
+        let page_size = pager.page_size();
+        loop {
+            if node.size() > page_size {
+                let (split_key, right) = node.split();
+                let right_page = pager.allocate_page()?;
+                right.write(pager, right_page)?;
+                match path.pop() {
+                    Some((parent, index)) => {
+                        node.write(pager, page_num)?;
+                        node = Node::read(pager, parent)?;
+                        node.insert_child(index, split_key, right_page);
+                        page_num = parent;
+                    },
+                    None => {
+                        // The left half moves to a new page under a
+                        // fresh internal root.
+                        let left_page = pager.allocate_page()?;
+                        node.write(pager, left_page)?;
+                        node = Node::Internal {
+                            kind: node.kind(),
+                            cells: vec![btree::InternalCell { child: left_page, key: split_key }],
+                            right_child: right_page,
+                        };
+                    },
+                }
+                continue;
+            }
+            let is_underfull = node.is_empty() || node.size() < page_size / 4;
+            let Some((parent_page, index)) = path.pop().filter(|_| is_underfull) else {
+                break;
+            };
+            let mut parent = Node::read(pager, parent_page)?;
+            // Pair the node with its left sibling, or its right sibling
+            // if it is the leftmost child.
+            let left_index = index.saturating_sub(1);
+            let (left_page, right_page) = (parent.child(left_index), parent.child(left_index + 1));
+            let (mut left, right) = if left_index == index {
+                (node, Node::read(pager, right_page)?)
+            } else {
+                (Node::read(pager, left_page)?, node)
+            };
+            let Node::Internal { cells, .. } = &parent else {
+                return Err(BTreeError::CorruptNode(parent_page));
+            };
+            left.merge(cells[left_index].key.clone(), right);
+            if left.size() <= page_size {
+                left.write(pager, left_page)?;
+                parent.remove_merged_child(left_index);
+                pager.free_page(right_page)?;
+            } else {
+                let (split_key, right) = left.split();
+                left.write(pager, left_page)?;
+                right.write(pager, right_page)?;
+                parent.set_separator(left_index, split_key);
+            }
+            node = parent;
+            page_num = parent_page;
+        }
+        if page_num == self.root_page {
+            // The root's only child moves up into it.
+            while let Node::Internal { cells, right_child, .. } = &node {
+                if !cells.is_empty() {
+                    break;
+                }
+                let child = *right_child;
+                node = Node::read(pager, child)?;
+                pager.free_page(child)?;
+            }
+        }
+        node.write(pager, page_num)
+    }
+
+    /// Walks from the root to the leaf that holds `target`, returning
+    /// the leaf's page number and the internal nodes visited on the way.
+    fn descend(&self, pager: &mut pager::Pager, target: Target) -> Result<(usize, Vec<(usize, usize)>), BTreeError> {
+        let mut path = Vec::new();
+        let mut page_num = self.root_page;
+        loop {
+            let node = Node::read(pager, page_num)?;
+            match &node {
+                Node::Leaf { .. } => return Ok((page_num, path)),
+                Node::Internal { cells, .. } => {
+                    let index = match target {
+                        Target::First => 0,
+                        Target::Key(key) => btree::child_index(cells, key),
+                        Target::Last => cells.len(),
+                    };
+                    path.push((page_num, index));
+                    page_num = node.child(index);
+                },
+            }
+        }
+    }
+
+    /// Points the cursor at the first row with a key of at least `key`.
+    pub fn seek(&mut self, key: i64) -> Result<(), BTreeError> {
+        self.seek_key(&Key::Integer(key))
+    }
+
+    /// Points the cursor at the first entry with a key of at least
+    /// `key`. In an index, a record of only the first few indexed
+    /// values finds the first entry starting with those values.
+    pub fn seek_key(&mut self, key: &Key) -> Result<(), BTreeError> {
+        self.move_to(Target::Key(key))
+    }
+
+    /// Points the cursor at `target`.
+    fn move_to(&mut self, target: Target) -> Result<(), BTreeError> {
+        let mut pager = self.pager.borrow_mut();
+        let (leaf, mut path) = self.descend(&mut pager, target)?;
+        let index = match Node::read(&mut pager, leaf)? {
+            Node::Leaf { cells, .. } => match target {
+                Target::First => 0,
+                Target::Key(key) => cells.partition_point(|cell| cell.key < *key),
+                // Only the root can be an empty leaf, and then the
+                // cursor ends up at the end of the table.
+                Target::Last => cells.len().saturating_sub(1),
+            },
+            Node::Internal { .. } => return Err(BTreeError::CorruptNode(leaf)),
+        };
+        drop(pager);
+        path.push((leaf, index));
+        self.stack = path;
+        self.end_of_table = false;
+        self.skip_next = false;
+        self.pin(leaf)?;
+        self.skip_exhausted_leaves()
+    }
+
+    /// Moves the cursor on to the next leaf while it points past the
+    /// last cell of its current leaf, setting `end_of_table` if there
+    /// are no leaves left.
+    fn skip_exhausted_leaves(&mut self) -> Result<(), BTreeError> {
+        loop {
+            let mut pager = self.pager.borrow_mut();
+            // Can unwrap here since the stack always ends in a leaf.
+            let (leaf, index) = *self.stack.last().unwrap();
+            if index < Node::read(&mut pager, leaf)?.len() {
+                return Ok(());
+            }
+            self.stack.pop();
+            // Climb until a node has a child to the right of the one
+            // the cursor came from.
+            loop {
+                let Some((page_num, index)) = self.stack.pop() else {
+                    drop(pager);
+                    self.end_of_table = true;
+                    self.stack.push((leaf, index));
+                    return Ok(());
+                };
+                let node = Node::read(&mut pager, page_num)?;
+                if index < node.len() {
+                    self.stack.push((page_num, index + 1));
+                    let mut child = node.child(index + 1);
+                    while let node @ Node::Internal { .. } = Node::read(&mut pager, child)? {
+                        self.stack.push((child, 0));
+                        child = node.child(0);
+                    }
+                    self.stack.push((child, 0));
+                    drop(pager);
+                    self.pin(child)?;
+                    break;
+                }
+            }
+        }
+    }
+
+    fn with_cell<T>(&self, f: impl FnOnce(btree::LeafCell) -> T) -> Result<T, BTreeError> {
+        assert!(!self.end_of_table, "cursor is at the end of the table");
+        let (leaf, index) = *self.stack.last().unwrap();
+        match Node::read(&mut self.pager.borrow_mut(), leaf)? {
+            Node::Leaf { mut cells, .. } => Ok(f(cells.swap_remove(index))),
+            Node::Internal { .. } => Err(BTreeError::CorruptNode(leaf)),
+        }
+    }
+
+    /// Pins `page_num` in the page cache, releasing the previously
+    /// pinned leaf.
+    fn pin(&mut self, page_num: usize) -> Result<(), BTreeError> {
+        let mut pager = self.pager.borrow_mut();
+        if let Some(old) = self.pinned_page.take() {
+            pager.unpin(old);
+        }
+        pager.pin(page_num)?;
+        self.pinned_page = Some(page_num);
+        Ok(())
+    }
+}
+
+/// Where in a tree to move a cursor to.
+#[derive(Clone, Copy)]
+enum Target<'a> {
+    First,
+    /// The first entry with a key of at least the given key.
+    Key(&'a Key),
+    Last,
+}
+
+impl Drop for Cursor {
+    fn drop(&mut self) {
+        if let (Some(page_num), Ok(mut pager)) = (self.pinned_page, self.pager.try_borrow_mut()) {
+            pager.unpin(page_num);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns a cursor over a new, empty table tree.
+    fn test_cursor() -> Cursor {
+        test_cursor_of_kind(btree::TreeKind::Table)
+    }
+
+    fn test_cursor_of_kind(kind: btree::TreeKind) -> Cursor {
+        let mut pager = pager::Pager::in_memory(pager::MIN_PAGE_SIZE, 16).unwrap();
+        let root_page = btree::create(&mut pager, kind).unwrap();
+        Cursor::new(Rc::new(RefCell::new(pager)), root_page)
+    }
+
+    /// Returns a new cursor over the same tree as `cursor`, pointing at
+    /// the first row with a key of at least `key`.
+    fn find(cursor: &Cursor, key: i64) -> Cursor {
+        Cursor::table_find(Rc::clone(&cursor.pager), cursor.root_page, key).unwrap()
+    }
+
+    #[test]
+    fn inserted_keys_are_found_after_splits() {
+        let mut cursor = test_cursor();
+        let keys: Vec<i64> = (0..500).map(|i| (i * 7919) % 500).collect();
+        for key in &keys {
+            cursor.insert(*key, format!("row {key}").as_bytes()).unwrap();
+        }
+        for key in &keys {
+            let found = find(&cursor, *key);
+            assert_eq!(*key, found.key().unwrap());
+            assert_eq!(format!("row {key}").as_bytes(), &found.value().unwrap()[..]);
+        }
+        assert!(find(&cursor, 500).end_of_table());
+    }
+
+    #[test]
+    fn advancing_from_table_start_visits_keys_in_ascending_order() {
+        let mut cursor = test_cursor();
+        cursor.rewind().unwrap();
+        assert!(cursor.end_of_table());
+        for key in (0..300).rev() {
+            cursor.insert(key, &[0; 20]).unwrap();
+        }
+        let mut cursor = Cursor::table_start(Rc::clone(&cursor.pager), cursor.root_page).unwrap();
+        let mut keys = Vec::new();
+        while !cursor.end_of_table() {
+            keys.push(cursor.key().unwrap());
+            cursor.advance().unwrap();
+        }
+        assert_eq!((0..300).collect::<Vec<i64>>(), keys);
+    }
+
+    #[test]
+    fn table_find_on_missing_key_points_at_next_key() {
+        let mut cursor = test_cursor();
+        for key in (0..200).step_by(10) {
+            cursor.insert(key, &[0; 40]).unwrap();
+        }
+        assert_eq!(50, find(&cursor, 41).key().unwrap());
+    }
+
+    #[test]
+    fn inserting_existing_key_returns_error() {
+        let mut cursor = test_cursor();
+        for key in 0..100 {
+            cursor.insert(key, &[0; 40]).unwrap();
+        }
+        assert!(matches!(cursor.insert(42, &[1; 40]), Err(BTreeError::DuplicateKey(Key::Integer(42)))));
+        assert_eq!(vec![0; 40], find(&cursor, 42).value().unwrap());
+    }
+
+    #[test]
+    fn payloads_larger_than_a_page_are_read_back_whole() {
+        let mut cursor = test_cursor();
+        let payload = |key: i64| -> Vec<u8> {
+            let len = (key as usize * 97) % (3 * pager::MIN_PAGE_SIZE);
+            (0..len).map(|i| (i as i64 + key) as u8).collect()
+        };
+        for key in (0..100).rev() {
+            cursor.insert(key, &payload(key)).unwrap();
+        }
+        cursor.rewind().unwrap();
+        for key in 0..100 {
+            assert_eq!(key, cursor.key().unwrap());
+            assert_eq!(payload(key), cursor.value().unwrap());
+            cursor.advance().unwrap();
+        }
+        assert!(cursor.end_of_table());
+    }
+
+    /// Returns the keys in the tree in cursor order, checking that every
+    /// node other than the root is at least a quarter full.
+    fn keys_checking_fill(cursor: &mut Cursor) -> Vec<i64> {
+        check_fill(cursor);
+        let mut keys = Vec::new();
+        cursor.rewind().unwrap();
+        while !cursor.end_of_table() {
+            keys.push(cursor.key().unwrap());
+            cursor.advance().unwrap();
+        }
+        keys
+    }
+
+    /// Checks that every node other than the root is at least a quarter
+    /// full.
+    fn check_fill(cursor: &Cursor) {
+        fn check(pager: &mut pager::Pager, page_num: usize, is_root: bool) {
+            let node = Node::read(pager, page_num).unwrap();
+            if !is_root {
+                assert!(!node.is_empty() && node.size() >= pager.page_size() / 4, "page {page_num} underfull");
+            }
+            if let Node::Internal { .. } = node {
+                for index in 0..=node.len() {
+                    check(pager, node.child(index), false);
+                }
+            }
+        }
+        check(&mut cursor.pager.borrow_mut(), cursor.root_page, true);
+    }
+
+    #[test]
+    fn deleted_keys_are_removed_and_nodes_rebalanced() {
+        let mut cursor = test_cursor();
+        for key in 0..600 {
+            cursor.insert(key, &[key as u8; 30]).unwrap();
+        }
+        let mut remaining: Vec<i64> = (0..600).collect();
+        for key in (0..600).map(|i| (i * 7919) % 600).filter(|key| key % 5 != 0) {
+            cursor.seek(key).unwrap();
+            cursor.delete().unwrap();
+            remaining.retain(|remaining| *remaining != key);
+        }
+        assert_eq!(remaining, keys_checking_fill(&mut cursor));
+        assert_eq!(vec![35; 30], find(&cursor, 35).value().unwrap());
+        for key in remaining {
+            cursor.seek(key).unwrap();
+            cursor.delete().unwrap();
+        }
+        assert!(keys_checking_fill(&mut cursor).is_empty());
+        assert!(Node::read(&mut cursor.pager.borrow_mut(), cursor.root_page).unwrap().is_empty());
+        // Every page other than the header and the root is free.
+        let mut pager = cursor.pager.borrow_mut();
+        assert_eq!(pager.num_pages() - 2, pager.free_page_count().unwrap());
+    }
+
+    #[test]
+    fn pages_freed_by_deletes_are_reused_by_inserts() {
+        let mut cursor = test_cursor();
+        let payload = [7; 3 * pager::MIN_PAGE_SIZE];
+        for key in 0..100 {
+            cursor.insert(key, &payload).unwrap();
+        }
+        let num_pages = cursor.pager.borrow().num_pages();
+        for key in 0..100 {
+            cursor.seek(key).unwrap();
+            cursor.update(&[]).unwrap();
+        }
+        let free_pages = cursor.pager.borrow_mut().free_page_count().unwrap();
+        assert!(free_pages > 300, "only {free_pages} pages freed");
+        for key in 0..100 {
+            cursor.seek(key).unwrap();
+            cursor.update(&payload).unwrap();
+        }
+        assert_eq!(num_pages, cursor.pager.borrow().num_pages());
+        assert_eq!(payload.to_vec(), find(&cursor, 99).value().unwrap());
+    }
+
+    #[test]
+    fn advance_after_delete_visits_the_following_row() {
+        let mut cursor = test_cursor();
+        for key in 0..200 {
+            cursor.insert(key, &[0; 30]).unwrap();
+        }
+        cursor.rewind().unwrap();
+        while !cursor.end_of_table() {
+            if cursor.key().unwrap() % 2 == 0 {
+                cursor.delete().unwrap();
+            }
+            cursor.advance().unwrap();
+        }
+        assert_eq!((0..200).filter(|key| key % 2 == 1).collect::<Vec<i64>>(), keys_checking_fill(&mut cursor));
+    }
+
+    #[test]
+    fn updated_payloads_replace_rows_of_any_size() {
+        let mut cursor = test_cursor();
+        for key in 0..200 {
+            cursor.insert(key, &[key as u8; 30]).unwrap();
+        }
+        let payload = |key: i64| vec![key as u8; (key as usize * 13) % (2 * pager::MIN_PAGE_SIZE)];
+        for key in (0..200).map(|i| (i * 7) % 200) {
+            cursor.seek(key).unwrap();
+            cursor.update(&payload(key)).unwrap();
+            assert_eq!(key, cursor.key().unwrap());
+        }
+        assert_eq!((0..200).collect::<Vec<i64>>(), keys_checking_fill(&mut cursor));
+        for key in 0..200 {
+            assert_eq!(payload(key), find(&cursor, key).value().unwrap());
+        }
+    }
+
+    #[test]
+    fn index_records_are_kept_in_value_order() {
+        use crate::{table::Row, value::Value};
+        let record = |values: Vec<Value>| Row { values }.serialise().into_vec();
+        // Entries of an index on a text column, followed by the row id.
+        let entry = |rowid: i64| record(vec![Value::Text(format!("name {}", rowid % 50)), Value::Integer(rowid)]);
+        let mut cursor = test_cursor_of_kind(btree::TreeKind::Index);
+        for rowid in (0..400).map(|i| (i * 7919) % 400) {
+            cursor.insert_record(&entry(rowid)).unwrap();
+        }
+        assert!(matches!(cursor.insert_record(&entry(7)), Err(BTreeError::DuplicateKey(_))));
+        // Entries for `rowids`, sorted by name and then row id.
+        let sorted_entries = |rowids: Vec<i64>| {
+            let mut rowids = rowids;
+            rowids.sort_by_key(|rowid| (format!("name {}", rowid % 50), *rowid));
+            rowids.into_iter().map(entry).collect::<Vec<_>>()
+        };
+        let entries = |cursor: &mut Cursor| {
+            let mut entries = Vec::new();
+            cursor.rewind().unwrap();
+            while !cursor.end_of_table() {
+                let Key::Record(record) = cursor.cell_key().unwrap() else {
+                    panic!("expected record key");
+                };
+                entries.push(record);
+                cursor.advance().unwrap();
+            }
+            entries
+        };
+        assert_eq!(sorted_entries((0..400).collect()), entries(&mut cursor));
+
+        // A record of just the indexed value finds its first entry.
+        cursor.seek_key(&Key::Record(record(vec![Value::Text(String::from("name 12"))]))).unwrap();
+        assert_eq!(Key::Record(entry(12)), cursor.cell_key().unwrap());
+
+        for rowid in (0..400).filter(|rowid| rowid % 3 != 0) {
+            cursor.seek_key(&Key::Record(entry(rowid))).unwrap();
+            cursor.delete().unwrap();
+        }
+        check_fill(&cursor);
+        assert_eq!(sorted_entries((0..400).step_by(3).collect()), entries(&mut cursor));
+    }
+
+    #[test]
+    fn last_points_at_largest_key() {
+        let mut cursor = test_cursor();
+        cursor.last().unwrap();
+        assert!(cursor.end_of_table());
+        for key in (0..300).map(|i| (i * 7) % 300) {
+            cursor.insert(key, &[0; 20]).unwrap();
+        }
+        cursor.last().unwrap();
+        assert_eq!(299, cursor.key().unwrap());
+        cursor.advance().unwrap();
+        assert!(cursor.end_of_table());
+    }
+}
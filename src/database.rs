@@ -0,0 +1,87 @@
+use std::{cell::RefCell, rc::Rc};
+use crate::{btree, cursor, pager, schema::{self, Schema}, table::{Row, TableError}, value::Value};
+
+/// An open database: the pages it is stored in and the schema of the
+/// tables in those pages.
+pub struct Database {
+    pager: Rc<RefCell<pager::Pager>>,
+    schema: Rc<RefCell<Schema>>,
+}
+
+impl Database {
+    /// Opens the database stored in the pages managed by `pager`,
+    /// creating an empty schema catalog if there is none yet.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Err`] if the schema catalog cannot be created or read.
+    pub fn open(mut pager: pager::Pager) -> Result<Self, TableError> {
+        if pager.num_pages() <= schema::SCHEMA_ROOT_PAGE {
+            btree::create(&mut pager, btree::TreeKind::Table)?;
+        }
+        let pager = Rc::new(RefCell::new(pager));
+        let schema = Schema::load(&pager)?;
+        Ok(Self { pager, schema: Rc::new(RefCell::new(schema)) })
+    }
+
+    pub fn pager(&self) -> &Rc<RefCell<pager::Pager>> {
+        &self.pager
+    }
+
+    /// The schema of the database, which statements that create tables
+    /// reload once they have updated the catalog.
+    pub fn schema(&self) -> &Rc<RefCell<Schema>> {
+        &self.schema
+    }
+
+    /// Writes all changes back to the database file.
+    pub fn close(&mut self) -> Result<(), TableError> {
+        Ok(self.pager.borrow_mut().flush()?)
+    }
+}
+
+/// Rebuilds the database stored in `pager` into a new, densely packed
+/// database with no free pages, and replaces the old database with it.
+///
+/// Every B-tree listed in the schema catalog is copied in turn, and the
+/// catalog is copied with the root page of each entry updated to that
+/// of its copy.
+///
+/// # Errors
+///
+/// Returns [`Err`] if the database cannot be read or the new database
+/// cannot be written. The old database is left in place if so.
+pub fn vacuum(pager: &Rc<RefCell<pager::Pager>>) -> Result<(), TableError> {
+    let mut target = pager.borrow().scratch()?;
+    let mut catalog = btree::TreeBuilder::new(target.allocate_page()?, btree::TreeKind::Table);
+    let mut cursor = cursor::Cursor::table_start(Rc::clone(pager), schema::SCHEMA_ROOT_PAGE)?;
+    while !cursor.end_of_table() {
+        let mut row = Row::deserialise(&cursor.value()?)?;
+        let Some(Value::Integer(root_page)) = row.values.get_mut(3) else {
+            return Err(TableError::CorruptSchema(String::from("missing root page")));
+        };
+        let old_root = usize::try_from(*root_page)
+            .map_err(|_| TableError::CorruptSchema(format!("invalid root page {root_page}")))?;
+        *root_page = copy_tree(pager, old_root, &mut target)? as i64;
+        catalog.push(&mut target, cursor.cell_key()?, &row.serialise())?;
+        cursor.advance()?;
+    }
+    catalog.finish(&mut target)?;
+    drop(cursor);
+    Ok(pager.borrow_mut().replace_with(target)?)
+}
+
+/// Copies the tree rooted at `root_page` in `pager` into `target`,
+/// returning the root page of the copy.
+fn copy_tree(pager: &Rc<RefCell<pager::Pager>>, root_page: usize, target: &mut pager::Pager) -> Result<usize, TableError> {
+    let new_root = target.allocate_page()?;
+    let kind = btree::Node::read(&mut pager.borrow_mut(), root_page)?.kind();
+    let mut builder = btree::TreeBuilder::new(new_root, kind);
+    let mut cursor = cursor::Cursor::table_start(Rc::clone(pager), root_page)?;
+    while !cursor.end_of_table() {
+        builder.push(target, cursor.cell_key()?, &cursor.value()?)?;
+        cursor.advance()?;
+    }
+    builder.finish(target)?;
+    Ok(new_root)
+}
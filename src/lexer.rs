@@ -0,0 +1,427 @@
+use std::fmt;
+
+/// Reserved words of the SQL dialect.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Keyword {
+    All,
+    Analyze,
+    And,
+    As,
+    Asc,
+    By,
+    Create,
+    Cross,
+    Delete,
+    Desc,
+    Exists,
+    Explain,
+    From,
+    Group,
+    Having,
+    If,
+    In,
+    Index,
+    Inner,
+    Insert,
+    Into,
+    Is,
+    Join,
+    Key,
+    Left,
+    Limit,
+    Not,
+    Null,
+    Offset,
+    On,
+    Or,
+    Order,
+    Outer,
+    Plan,
+    Primary,
+    Query,
+    Recursive,
+    Select,
+    Set,
+    Table,
+    Union,
+    Unique,
+    Update,
+    Vacuum,
+    Values,
+    Where,
+    With,
+}
+
+impl Keyword {
+    /// Returns the keyword spelt `word`, ignoring case, or `None` if
+    /// `word` is not a keyword.
+    pub fn from_word(word: &str) -> Option<Self> {
+        let keyword = match word.to_ascii_uppercase().as_str() {
+            "ALL" => Keyword::All,
+            "ANALYZE" => Keyword::Analyze,
+            "AND" => Keyword::And,
+            "AS" => Keyword::As,
+            "ASC" => Keyword::Asc,
+            "BY" => Keyword::By,
+            "CREATE" => Keyword::Create,
+            "CROSS" => Keyword::Cross,
+            "DELETE" => Keyword::Delete,
+            "DESC" => Keyword::Desc,
+            "EXISTS" => Keyword::Exists,
+            "EXPLAIN" => Keyword::Explain,
+            "FROM" => Keyword::From,
+            "GROUP" => Keyword::Group,
+            "HAVING" => Keyword::Having,
+            "IF" => Keyword::If,
+            "IN" => Keyword::In,
+            "INDEX" => Keyword::Index,
+            "INNER" => Keyword::Inner,
+            "INSERT" => Keyword::Insert,
+            "INTO" => Keyword::Into,
+            "IS" => Keyword::Is,
+            "JOIN" => Keyword::Join,
+            "KEY" => Keyword::Key,
+            "LEFT" => Keyword::Left,
+            "LIMIT" => Keyword::Limit,
+            "NOT" => Keyword::Not,
+            "NULL" => Keyword::Null,
+            "OFFSET" => Keyword::Offset,
+            "ON" => Keyword::On,
+            "OR" => Keyword::Or,
+            "ORDER" => Keyword::Order,
+            "OUTER" => Keyword::Outer,
+            "PLAN" => Keyword::Plan,
+            "PRIMARY" => Keyword::Primary,
+            "QUERY" => Keyword::Query,
+            "RECURSIVE" => Keyword::Recursive,
+            "SELECT" => Keyword::Select,
+            "SET" => Keyword::Set,
+            "TABLE" => Keyword::Table,
+            "UNION" => Keyword::Union,
+            "UNIQUE" => Keyword::Unique,
+            "UPDATE" => Keyword::Update,
+            "VACUUM" => Keyword::Vacuum,
+            "VALUES" => Keyword::Values,
+            "WHERE" => Keyword::Where,
+            "WITH" => Keyword::With,
+            _ => return None,
+        };
+        Some(keyword)
+    }
+
+    /// Returns whether the keyword may also be used as a table or
+    /// column name, where SQL would not otherwise allow a keyword.
+    pub fn is_fallback_identifier(self) -> bool {
+        matches!(
+            self,
+            Keyword::Analyze
+                | Keyword::Asc
+                | Keyword::Cross
+                | Keyword::Desc
+                | Keyword::Explain
+                | Keyword::If
+                | Keyword::Inner
+                | Keyword::Key
+                | Keyword::Left
+                | Keyword::Offset
+                | Keyword::Outer
+                | Keyword::Plan
+                | Keyword::Query
+                | Keyword::Recursive
+                | Keyword::Vacuum
+                | Keyword::With
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenKind {
+    Keyword(Keyword),
+    Identifier(String),
+    String(String),
+    Blob(Vec<u8>),
+    Integer(i64),
+    Float(f64),
+    LeftParen,
+    RightParen,
+    Comma,
+    Semicolon,
+    Dot,
+    Star,
+    Plus,
+    Minus,
+    Slash,
+    Percent,
+    Concat,
+    Eq,
+    NotEq,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+}
+
+/// A token together with the text it was read from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub text: String,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum LexError {
+    UnterminatedString,
+    UnterminatedComment,
+    UnrecognisedToken(String),
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LexError::UnterminatedString => write!(f, "unterminated string"),
+            LexError::UnterminatedComment => write!(f, "unterminated comment"),
+            LexError::UnrecognisedToken(text) => write!(f, "unrecognized token: \"{text}\""),
+        }
+    }
+}
+
+/// Splits `input` into tokens, skipping whitespace and comments.
+pub fn tokenise(input: &str) -> Result<Vec<Token>, LexError> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+    while pos < chars.len() {
+        let start = pos;
+        let c = chars[pos];
+        let next = chars.get(pos + 1).copied();
+        let kind = match c {
+            c if c.is_whitespace() => {
+                pos += 1;
+                continue;
+            },
+            '-' if next == Some('-') => {
+                while pos < chars.len() && chars[pos] != '\n' {
+                    pos += 1;
+                }
+                continue;
+            },
+            '/' if next == Some('*') => {
+                pos += 2;
+                loop {
+                    match (chars.get(pos), chars.get(pos + 1)) {
+                        (Some('*'), Some('/')) => break,
+                        (Some(_), _) => pos += 1,
+                        (None, _) => return Err(LexError::UnterminatedComment),
+                    }
+                }
+                pos += 2;
+                continue;
+            },
+            '\'' => {
+                let (value, end) = read_quoted(&chars, pos, '\'')
+                    .ok_or(LexError::UnterminatedString)?;
+                pos = end;
+                TokenKind::String(value)
+            },
+            'x' | 'X' if next == Some('\'') => {
+                let (hex, end) = read_quoted(&chars, pos + 1, '\'')
+                    .ok_or(LexError::UnterminatedString)?;
+                pos = end;
+                let blob = decode_hex(&hex)
+                    .ok_or_else(|| LexError::UnrecognisedToken(chars[start..pos].iter().collect()))?;
+                TokenKind::Blob(blob)
+            },
+            '"' | '`' | '[' => {
+                let close = if c == '[' { ']' } else { c };
+                let (value, end) = read_quoted(&chars, pos, close)
+                    .ok_or_else(|| LexError::UnrecognisedToken(chars[start..].iter().collect()))?;
+                pos = end;
+                TokenKind::Identifier(value)
+            },
+            c if c.is_ascii_digit() || (c == '.' && next.is_some_and(|n| n.is_ascii_digit())) => {
+                let (kind, end) = read_number(&chars, pos)?;
+                pos = end;
+                kind
+            },
+            c if c.is_alphabetic() || c == '_' => {
+                while pos < chars.len() && (chars[pos].is_alphanumeric() || chars[pos] == '_') {
+                    pos += 1;
+                }
+                let word: String = chars[start..pos].iter().collect();
+                match Keyword::from_word(&word) {
+                    Some(keyword) => TokenKind::Keyword(keyword),
+                    None => TokenKind::Identifier(word),
+                }
+            },
+            _ => {
+                let (kind, len) = match (c, next) {
+                    ('|', Some('|')) => (TokenKind::Concat, 2),
+                    ('=', Some('=')) => (TokenKind::Eq, 2),
+                    ('!', Some('=')) | ('<', Some('>')) => (TokenKind::NotEq, 2),
+                    ('<', Some('=')) => (TokenKind::LtEq, 2),
+                    ('>', Some('=')) => (TokenKind::GtEq, 2),
+                    ('(', _) => (TokenKind::LeftParen, 1),
+                    (')', _) => (TokenKind::RightParen, 1),
+                    (',', _) => (TokenKind::Comma, 1),
+                    (';', _) => (TokenKind::Semicolon, 1),
+                    ('.', _) => (TokenKind::Dot, 1),
+                    ('*', _) => (TokenKind::Star, 1),
+                    ('+', _) => (TokenKind::Plus, 1),
+                    ('-', _) => (TokenKind::Minus, 1),
+                    ('/', _) => (TokenKind::Slash, 1),
+                    ('%', _) => (TokenKind::Percent, 1),
+                    ('=', _) => (TokenKind::Eq, 1),
+                    ('<', _) => (TokenKind::Lt, 1),
+                    ('>', _) => (TokenKind::Gt, 1),
+                    _ => return Err(LexError::UnrecognisedToken(c.to_string())),
+                };
+                pos += len;
+                kind
+            },
+        };
+        tokens.push(Token { kind, text: chars[start..pos].iter().collect() });
+    }
+    Ok(tokens)
+}
+
+/// Reads a string starting with the opening quote at `start` and ending
+/// with `close`, where a doubled `close` stands for itself. Returns the
+/// unquoted string and the position after the closing quote, or `None`
+/// if the string is never closed.
+fn read_quoted(chars: &[char], start: usize, close: char) -> Option<(String, usize)> {
+    let mut value = String::new();
+    let mut pos = start + 1;
+    loop {
+        match *chars.get(pos)? {
+            c if c == close && chars.get(pos + 1) == Some(&close) => {
+                value.push(c);
+                pos += 2;
+            },
+            c if c == close => return Some((value, pos + 1)),
+            c => {
+                value.push(c);
+                pos += 1;
+            },
+        }
+    }
+}
+
+/// Decodes a string of pairs of hexadecimal digits, or returns `None`
+/// if `hex` is not such a string.
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+/// Reads an integer or floating point literal starting at `start` and
+/// returns it with the position after its last character.
+fn read_number(chars: &[char], start: usize) -> Result<(TokenKind, usize), LexError> {
+    let mut pos = start;
+    let mut is_float = false;
+    while pos < chars.len() && chars[pos].is_ascii_digit() {
+        pos += 1;
+    }
+    if chars.get(pos) == Some(&'.') {
+        is_float = true;
+        pos += 1;
+        while pos < chars.len() && chars[pos].is_ascii_digit() {
+            pos += 1;
+        }
+    }
+    if matches!(chars.get(pos), Some('e' | 'E')) {
+        let mut exponent_end = pos + 1;
+        if matches!(chars.get(exponent_end), Some('+' | '-')) {
+            exponent_end += 1;
+        }
+        if chars.get(exponent_end).is_some_and(|c| c.is_ascii_digit()) {
+            is_float = true;
+            pos = exponent_end;
+            while pos < chars.len() && chars[pos].is_ascii_digit() {
+                pos += 1;
+            }
+        }
+    }
+    let text: String = chars[start..pos].iter().collect();
+    if chars.get(pos).is_some_and(|c| c.is_alphanumeric() || *c == '_') {
+        return Err(LexError::UnrecognisedToken(text));
+    }
+    if !is_float {
+        if let Ok(value) = text.parse::<i64>() {
+            return Ok((TokenKind::Integer(value), pos));
+        }
+    }
+    // Integers too large for an i64 are read as floats, as SQLite does.
+    text.parse::<f64>()
+        .map(|value| (TokenKind::Float(value), pos))
+        .map_err(|_| LexError::UnrecognisedToken(text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(input: &str) -> Vec<TokenKind> {
+        tokenise(input).unwrap().into_iter().map(|token| token.kind).collect()
+    }
+
+    #[test]
+    fn keywords_are_case_insensitive_and_identifiers_are_not() {
+        assert_eq!(
+            vec![
+                TokenKind::Keyword(Keyword::Select),
+                TokenKind::Identifier(String::from("Name")),
+                TokenKind::Keyword(Keyword::From),
+                TokenKind::Identifier(String::from("select")),
+            ],
+            kinds("sElEcT Name FROM \"select\"")
+        );
+    }
+
+    #[test]
+    fn literals_and_operators_are_tokenised() {
+        assert_eq!(
+            vec![
+                TokenKind::LeftParen,
+                TokenKind::Integer(42),
+                TokenKind::Comma,
+                TokenKind::Float(2.5),
+                TokenKind::Comma,
+                TokenKind::String(String::from("it's")),
+                TokenKind::RightParen,
+                TokenKind::LtEq,
+                TokenKind::NotEq,
+                TokenKind::NotEq,
+                TokenKind::Concat,
+                TokenKind::Semicolon,
+            ],
+            kinds("(42, 2.5e0, 'it''s') <= != <> || ;")
+        );
+    }
+
+    #[test]
+    fn comments_and_whitespace_are_skipped() {
+        assert_eq!(
+            vec![TokenKind::Integer(1), TokenKind::Plus, TokenKind::Integer(2)],
+            kinds("  1 -- one\n + /* two\n */ 2")
+        );
+    }
+
+    #[test]
+    fn blob_literals_are_decoded() {
+        assert_eq!(
+            vec![TokenKind::Blob(vec![0x00, 0xff, 0x1a]), TokenKind::Blob(Vec::new()), TokenKind::Identifier(String::from("x"))],
+            kinds("x'00fF1a' X'' x")
+        );
+        assert_eq!(Err(LexError::UnrecognisedToken(String::from("x'abc'"))), tokenise("x'abc'"));
+    }
+
+    #[test]
+    fn unterminated_string_returns_error() {
+        assert_eq!(Err(LexError::UnterminatedString), tokenise("select 'abc"));
+    }
+}
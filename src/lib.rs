@@ -1,4 +1,17 @@
 pub mod user_io;
 pub mod commands;
 pub mod page;
-pub mod table;
\ No newline at end of file
+pub mod pager;
+pub mod btree;
+pub mod cursor;
+pub mod lexer;
+pub mod ast;
+pub mod parser;
+pub mod value;
+pub mod aggregate;
+pub mod vm;
+pub mod planner;
+pub mod compiler;
+pub mod table;
+pub mod schema;
+pub mod database;
\ No newline at end of file
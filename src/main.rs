@@ -1,32 +1,56 @@
-use std::{process, io};
+use std::{env, process, io};
 use sqlite::user_io::*;
 use sqlite::commands::*;
-use sqlite::table;
+use sqlite::{database, pager, table};
 
 const MAX_BUFFER_CAPACITY: usize = 4096; 
 const PAGE_SIZE: usize = 4096;
+const PAGE_CACHE_CAPACITY: usize = 256;
 
 fn main() {
     if let Err(e) = configure_env(io::stdout()) {
         eprintln!("{}", e);
         process::exit(ExitStatus::Failure as i32);
     };
+    let pager = match env::args().nth(1) {
+        Some(path) => pager::Pager::open(path, PAGE_SIZE, PAGE_CACHE_CAPACITY),
+        None => {
+            println!("Connected to a transient in-memory database.");
+            pager::Pager::in_memory(PAGE_SIZE, PAGE_CACHE_CAPACITY)
+        },
+    };
+    let mut database = match pager.map_err(table::TableError::from).and_then(database::Database::open) {
+        Ok(database) => database,
+        Err(e) => {
+            eprintln!("Error: unable to open database: {}", e);
+            process::exit(ExitStatus::Failure as i32);
+        },
+    };
     let mut input_buffer = InputBuffer::with_capacity(MAX_BUFFER_CAPACITY);
-    let mut table = table::Table::build(PAGE_SIZE).unwrap();
     loop {
-        if let Err(e) = prompt_user_input(io::stdin().lock(), io::stdout(), &mut input_buffer) {
-            eprintln!("{}", e);
-            process::exit(ExitStatus::Failure as i32);
+        match prompt_user_input(io::stdin().lock(), io::stdout(), &mut input_buffer) {
+            Ok(0) => {
+                if let Err(e) = close_database(&mut database) {
+                    eprintln!("{}", e);
+                    process::exit(ExitStatus::Failure as i32);
+                }
+                process::exit(ExitStatus::Success as i32);
+            },
+            Ok(_) => (),
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(ExitStatus::Failure as i32);
+            },
         };
         if input_buffer.buffer().is_empty() {
         continue
         }
         if input_buffer.buffer().get(0..1).unwrap() == "." {
-            if let Err(e) = handle_meta_command(input_buffer.buffer()) {
+            if let Err(e) = handle_meta_command(input_buffer.buffer(), &mut database) {
                 eprintln!("{}", e);
             };
         } else {
-            let statement = prepare_statement(input_buffer.buffer());
+            let statement = prepare_statement(input_buffer.buffer(), &database);
             let statement = match statement {
                 Ok(val) => val,
                 Err(msg) => {
@@ -37,7 +61,9 @@ fn main() {
                     continue;
                 }
             };
-            execute_statement(statement, &mut table);
+            if let Err(e) = execute_statement(statement, &mut database) {
+                eprintln!("{}", e);
+            }
         }
     }
-}
\ No newline at end of file
+}
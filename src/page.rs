@@ -1,4 +1,4 @@
-use std::{alloc::{self, GlobalAlloc}, ptr};
+use std::{alloc, ptr, slice};
 
 #[derive(Debug)]
 pub enum PageAllocationError{
@@ -21,7 +21,7 @@ impl Page {
     /// 
     /// # Errors
     /// 
-    /// Returns [`Err`] if `size` is 0 or the [`GlobalAlloc::alloc`] 
+    /// Returns [`Err`] if `size` is 0 or the [`GlobalAlloc::alloc`](alloc::GlobalAlloc::alloc) 
     /// method fails.
     /// 
     /// # Safety
@@ -33,15 +33,15 @@ impl Page {
         // from_size_align is required to avoid attempting a 0 size 
         // allocation in unsafe block
         let layout = alloc::Layout::from_size_align(size, 8)
-            .map_err(|e| PageAllocationError::LayoutError(e))?;
+            .map_err(PageAllocationError::LayoutError)?;
         let buffer;
         unsafe {
             // alloc_zeroed used for ease of debugging
-            buffer = alloc::alloc_zeroed(layout.clone());
+            buffer = alloc::alloc_zeroed(layout);
             if buffer.is_null() {
-                return Err(PageAllocationError::MemoryAllocationError);
+                Err(PageAllocationError::MemoryAllocationError)
             } else {
-                return Ok(Self { buffer, layout });
+                Ok(Self { buffer, layout })
             }           
         }
     }
@@ -70,7 +70,7 @@ impl Page {
         unsafe {
             ptr::copy_nonoverlapping(
                 src.as_ptr(), 
-                self.buffer.offset(loc as isize), src.len()
+                self.buffer.add(loc), src.len()
             )
         }
     }
@@ -92,12 +92,22 @@ impl Page {
         let mut output = vec![0; count];
         unsafe {
             ptr::copy_nonoverlapping(
-                self.buffer.offset(loc as isize), 
+                self.buffer.add(loc), 
                 output.as_mut_ptr(), count
             );
         }
         Some(output.into_boxed_slice())
     }
+
+    /// Returns the whole buffer as a byte slice.
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.buffer, self.size()) }
+    }
+
+    /// Returns the whole buffer as a mutable byte slice.
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.buffer, self.size()) }
+    }
 }
 
 impl Drop for Page {
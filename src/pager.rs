@@ -0,0 +1,627 @@
+use std::{collections::HashMap, fmt, fs, io::{self, Read, Seek, SeekFrom, Write}, path::{Path, PathBuf}};
+use crate::page;
+
+/// Bytes written at the start of every database file.
+const MAGIC: &[u8; 16] = b"SQLite rust v1\0\0";
+const PAGE_SIZE_OFFSET: usize = MAGIC.len();
+/// Offset in the header of the number of the first page in the free
+/// list, or 0 if the list is empty.
+const FREELIST_HEAD_OFFSET: usize = PAGE_SIZE_OFFSET + 4;
+/// Offset in the header of the number of pages in the free list.
+const FREELIST_COUNT_OFFSET: usize = FREELIST_HEAD_OFFSET + 4;
+/// Smallest page size accepted by [`Pager`], large enough to hold the
+/// database header.
+pub const MIN_PAGE_SIZE: usize = 512;
+
+#[derive(Debug)]
+pub enum PagerError {
+    IoError(io::Error),
+    AllocationError(page::PageAllocationError),
+    InvalidPageSize(usize),
+    InvalidCacheCapacity,
+    InvalidHeader,
+    PageOutOfBounds(usize),
+    /// Every page in the cache is pinned so none can be evicted.
+    CacheFull,
+}
+
+impl fmt::Display for PagerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PagerError::IoError(e) => write!(f, "{e}"),
+            PagerError::AllocationError(e) => write!(f, "unable to allocate page: {e:?}"),
+            PagerError::InvalidPageSize(size) => write!(f, "invalid page size {size}"),
+            PagerError::InvalidCacheCapacity => write!(f, "page cache capacity must be at least 1"),
+            PagerError::InvalidHeader => write!(f, "file is not a database"),
+            PagerError::PageOutOfBounds(page_num) => write!(f, "page {page_num} is out of bounds"),
+            PagerError::CacheFull => write!(f, "every page in the cache is pinned"),
+        }
+    }
+}
+
+impl From<io::Error> for PagerError {
+    fn from(e: io::Error) -> Self {
+        Self::IoError(e)
+    }
+}
+
+/// Where the pages of a database live between reads and writes.
+enum Storage {
+    File(fs::File),
+    Memory(Vec<u8>),
+}
+
+impl Storage {
+    fn len(&self) -> io::Result<u64> {
+        match self {
+            Storage::File(file) => Ok(file.metadata()?.len()),
+            Storage::Memory(bytes) => Ok(bytes.len() as u64),
+        }
+    }
+
+    /// Fills `buffer` with the bytes stored at `offset`. Bytes that lie
+    /// past the end of the storage are left untouched.
+    fn read_at(&mut self, offset: u64, buffer: &mut [u8]) -> io::Result<()> {
+        let available = self.len()?.saturating_sub(offset).min(buffer.len() as u64) as usize;
+        if available == 0 {
+            return Ok(());
+        }
+        match self {
+            Storage::File(file) => {
+                file.seek(SeekFrom::Start(offset))?;
+                file.read_exact(&mut buffer[..available])
+            },
+            Storage::Memory(bytes) => {
+                let offset = offset as usize;
+                buffer[..available].copy_from_slice(&bytes[offset..offset + available]);
+                Ok(())
+            },
+        }
+    }
+
+    fn write_at(&mut self, offset: u64, buffer: &[u8]) -> io::Result<()> {
+        match self {
+            Storage::File(file) => {
+                file.seek(SeekFrom::Start(offset))?;
+                file.write_all(buffer)
+            },
+            Storage::Memory(bytes) => {
+                let offset = offset as usize;
+                if bytes.len() < offset + buffer.len() {
+                    bytes.resize(offset + buffer.len(), 0);
+                }
+                bytes[offset..offset + buffer.len()].copy_from_slice(buffer);
+                Ok(())
+            },
+        }
+    }
+
+    /// Drops the bytes past `len`, if there are any.
+    fn truncate(&mut self, len: u64) -> io::Result<()> {
+        if self.len()? <= len {
+            return Ok(());
+        }
+        match self {
+            Storage::File(file) => file.set_len(len),
+            Storage::Memory(bytes) => {
+                bytes.truncate(len as usize);
+                Ok(())
+            },
+        }
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        match self {
+            Storage::File(file) => file.sync_all(),
+            Storage::Memory(_) => Ok(()),
+        }
+    }
+}
+
+/// A page that has been read into memory.
+struct Frame {
+    page: page::Page,
+    dirty: bool,
+    pin_count: usize,
+    /// Value of [`Pager::clock`] when the page was last accessed.
+    last_used: u64,
+}
+
+/// The contents of the database when a statement started, kept so that
+/// the statement's changes can be undone if it fails.
+struct Journal {
+    /// Contents of each page before the statement first changed it.
+    pages: HashMap<usize, Vec<u8>>,
+    num_pages: usize,
+}
+
+/// Reads fixed size pages from a database file on demand and writes
+/// modified pages back to it.
+///
+/// At most `cache_capacity` pages are held in memory at once. When the
+/// cache is full the least recently used page that is not pinned is
+/// written back, if modified, and evicted to make room.
+///
+/// Page 0 is reserved for the database header, which records the page
+/// size the file was created with and the list of free pages. Pages
+/// that are no longer used are added to the free list with
+/// [`free_page`], and reused by [`allocate_page`] before the file
+/// grows. Each free page holds the number of the next one in its first
+/// 4 bytes, with 0 ending the list.
+///
+/// Changes made after [`begin_statement`] can be undone with
+/// [`rollback_statement`], so that a statement that fails part way
+/// leaves the database as it found it.
+///
+/// [`free_page`]: Self::free_page
+/// [`allocate_page`]: Self::allocate_page
+/// [`begin_statement`]: Self::begin_statement
+/// [`rollback_statement`]: Self::rollback_statement
+pub struct Pager {
+    storage: Storage,
+    /// Path of the database file, or `None` for an in-memory database.
+    path: Option<PathBuf>,
+    page_size: usize,
+    num_pages: usize,
+    frames: HashMap<usize, Frame>,
+    cache_capacity: usize,
+    clock: u64,
+    /// Set while a statement runs.
+    journal: Option<Journal>,
+}
+
+impl Pager {
+    /// Opens the database file at `path`, creating it if it does not
+    /// exist, and returns a `Pager` for it that caches up to 
+    /// `cache_capacity` pages.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Err`] if the file cannot be opened, `page_size` is
+    /// smaller than [`MIN_PAGE_SIZE`], `cache_capacity` is 0, or the 
+    /// file is not a database written with the same `page_size`.
+    pub fn open(path: impl AsRef<Path>, page_size: usize, cache_capacity: usize) -> Result<Self, PagerError> {
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+        let mut pager = Self::with_storage(Storage::File(file), page_size, cache_capacity)?;
+        pager.path = Some(path.as_ref().to_path_buf());
+        Ok(pager)
+    }
+
+    /// Returns a `Pager` whose pages are never written to disk.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Err`] if `page_size` is smaller than [`MIN_PAGE_SIZE`]
+    /// or `cache_capacity` is 0.
+    pub fn in_memory(page_size: usize, cache_capacity: usize) -> Result<Self, PagerError> {
+        Self::with_storage(Storage::Memory(Vec::new()), page_size, cache_capacity)
+    }
+
+    fn with_storage(storage: Storage, page_size: usize, cache_capacity: usize) -> Result<Self, PagerError> {
+        if page_size < MIN_PAGE_SIZE {
+            return Err(PagerError::InvalidPageSize(page_size));
+        }
+        if cache_capacity == 0 {
+            return Err(PagerError::InvalidCacheCapacity);
+        }
+        let len = storage.len()?;
+        if len % page_size as u64 != 0 {
+            return Err(PagerError::InvalidHeader);
+        }
+        let mut pager = Self {
+            storage,
+            path: None,
+            page_size,
+            num_pages: (len / page_size as u64) as usize,
+            frames: HashMap::new(),
+            cache_capacity,
+            clock: 0,
+            journal: None,
+        };
+        if pager.num_pages == 0 {
+            let header = pager.allocate_page()?;
+            let page = pager.get_page_mut(header)?;
+            page.copy_from_slice(0, MAGIC);
+            page.copy_from_slice(PAGE_SIZE_OFFSET, &(page_size as u32).to_le_bytes());
+        } else {
+            let page = pager.get_page(0)?;
+            let magic = page.read_from_index(0, MAGIC.len()).unwrap();
+            let stored_size = page.read_from_index(PAGE_SIZE_OFFSET, 4).unwrap();
+            let stored_size = u32::from_le_bytes((*stored_size).try_into().unwrap());
+            if *magic != MAGIC[..] || stored_size as usize != page_size {
+                return Err(PagerError::InvalidHeader);
+            }
+        }
+        Ok(pager)
+    }
+
+    /// Returns an empty pager with the same page size and cache capacity
+    /// in which to build a replacement for this database, stored in a
+    /// file next to this pager's file, or in memory if this pager is.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Err`] if the file cannot be created.
+    pub fn scratch(&self) -> Result<Self, PagerError> {
+        let Some(path) = &self.path else {
+            return Self::in_memory(self.page_size, self.cache_capacity);
+        };
+        let mut scratch_path = path.clone().into_os_string();
+        scratch_path.push("-vacuum");
+        // Left behind if an earlier replacement failed part way.
+        match fs::remove_file(&scratch_path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+            _ => (),
+        }
+        Self::open(PathBuf::from(scratch_path), self.page_size, self.cache_capacity)
+    }
+
+    /// Replaces the database with the one built in `scratch`, a pager
+    /// returned by [`scratch`]. Its pages are written out and its file
+    /// renamed over this pager's file, so that the file holds either
+    /// the old or the new database should this fail part way.
+    ///
+    /// [`scratch`]: Self::scratch
+    pub fn replace_with(&mut self, mut scratch: Self) -> Result<(), PagerError> {
+        scratch.flush()?;
+        if let (Some(path), Some(scratch_path)) = (&self.path, &scratch.path) {
+            fs::rename(scratch_path, path)?;
+            scratch.path = Some(path.clone());
+        }
+        *self = scratch;
+        Ok(())
+    }
+
+    pub fn page_size(&self) -> usize {
+        self.page_size
+    }
+
+    /// Number of pages currently held in memory.
+    pub fn cached_pages(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Number of pages in the database, including pages that have been
+    /// allocated but not yet written to disk.
+    pub fn num_pages(&self) -> usize {
+        self.num_pages
+    }
+
+    /// Returns page number `page_num`, reading it from disk if it is not
+    /// already in memory.
+    pub fn get_page(&mut self, page_num: usize) -> Result<&page::Page, PagerError> {
+        Ok(&self.load(page_num)?.page)
+    }
+
+    /// Returns page number `page_num` for writing. The page is written
+    /// back to disk on the next [`flush`].
+    ///
+    /// [`flush`]: Self::flush
+    pub fn get_page_mut(&mut self, page_num: usize) -> Result<&mut page::Page, PagerError> {
+        let unsaved = self.journal.as_ref()
+            .is_some_and(|journal| page_num < journal.num_pages && !journal.pages.contains_key(&page_num));
+        if unsaved {
+            let original = self.get_page(page_num)?.as_slice().to_vec();
+            if let Some(journal) = &mut self.journal {
+                journal.pages.insert(page_num, original);
+            }
+        }
+        let frame = self.load(page_num)?;
+        frame.dirty = true;
+        Ok(&mut frame.page)
+    }
+
+    /// Keeps page number `page_num` in memory until a matching call to
+    /// [`unpin`]. Pins are counted, so a page pinned twice must be 
+    /// unpinned twice before it can be evicted.
+    ///
+    /// [`unpin`]: Self::unpin
+    pub fn pin(&mut self, page_num: usize) -> Result<(), PagerError> {
+        self.load(page_num)?.pin_count += 1;
+        Ok(())
+    }
+
+    /// Releases a pin taken with [`pin`]. Does nothing if the page is 
+    /// not pinned.
+    ///
+    /// [`pin`]: Self::pin
+    pub fn unpin(&mut self, page_num: usize) {
+        if let Some(frame) = self.frames.get_mut(&page_num) {
+            frame.pin_count = frame.pin_count.saturating_sub(1);
+        }
+    }
+
+    /// Number of pages in the free list.
+    pub fn free_page_count(&mut self) -> Result<usize, PagerError> {
+        self.read_header_field(FREELIST_COUNT_OFFSET)
+    }
+
+    /// Returns the number of a zeroed page for a new use, taking it from
+    /// the free list if there is one there and appending it to the end
+    /// of the database otherwise.
+    pub fn allocate_page(&mut self) -> Result<usize, PagerError> {
+        // Page 0 is allocated before there is a header to read.
+        let head = if self.num_pages == 0 { 0 } else { self.read_header_field(FREELIST_HEAD_OFFSET)? };
+        if head == 0 {
+            let page_num = self.num_pages;
+            self.num_pages += 1;
+            self.get_page_mut(page_num)?;
+            return Ok(page_num);
+        }
+        let page = self.get_page_mut(head)?;
+        let next = u32::from_le_bytes((*page.read_from_index(0, 4).unwrap()).try_into().unwrap());
+        page.as_mut_slice().fill(0);
+        let count = self.free_page_count()?;
+        self.write_header_field(FREELIST_HEAD_OFFSET, next as usize)?;
+        self.write_header_field(FREELIST_COUNT_OFFSET, count - 1)?;
+        Ok(head)
+    }
+
+    /// Adds page number `page_num` to the free list so that it can be
+    /// reused by a later [`allocate_page`].
+    ///
+    /// [`allocate_page`]: Self::allocate_page
+    ///
+    /// # Panics
+    ///
+    /// Panics if `page_num` is 0, which holds the header.
+    pub fn free_page(&mut self, page_num: usize) -> Result<(), PagerError> {
+        assert_ne!(0, page_num, "the header page cannot be freed");
+        let head = self.read_header_field(FREELIST_HEAD_OFFSET)?;
+        let count = self.free_page_count()?;
+        let page = self.get_page_mut(page_num)?;
+        page.as_mut_slice().fill(0);
+        page.copy_from_slice(0, &(head as u32).to_le_bytes());
+        self.write_header_field(FREELIST_HEAD_OFFSET, page_num)?;
+        self.write_header_field(FREELIST_COUNT_OFFSET, count + 1)
+    }
+
+    fn read_header_field(&mut self, offset: usize) -> Result<usize, PagerError> {
+        let bytes = self.get_page(0)?.read_from_index(offset, 4).unwrap();
+        Ok(u32::from_le_bytes((*bytes).try_into().unwrap()) as usize)
+    }
+
+    fn write_header_field(&mut self, offset: usize, value: usize) -> Result<(), PagerError> {
+        self.get_page_mut(0)?.copy_from_slice(offset, &(value as u32).to_le_bytes());
+        Ok(())
+    }
+
+    /// Starts recording the changes of a statement, so that they can be
+    /// undone by [`rollback_statement`]. Changes recorded for an earlier
+    /// statement can no longer be undone.
+    ///
+    /// [`rollback_statement`]: Self::rollback_statement
+    pub fn begin_statement(&mut self) {
+        self.journal = Some(Journal { pages: HashMap::new(), num_pages: self.num_pages });
+    }
+
+    /// Stops recording changes, keeping those made since
+    /// [`begin_statement`].
+    ///
+    /// [`begin_statement`]: Self::begin_statement
+    pub fn commit_statement(&mut self) {
+        self.journal = None;
+    }
+
+    /// Undoes every change made since [`begin_statement`], restoring
+    /// the pages changed and dropping those added. Does nothing if no
+    /// statement has begun.
+    ///
+    /// [`begin_statement`]: Self::begin_statement
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Err`] if the pages cannot be read or the file cannot
+    /// be shrunk.
+    pub fn rollback_statement(&mut self) -> Result<(), PagerError> {
+        let Some(journal) = self.journal.take() else {
+            return Ok(());
+        };
+        self.frames.retain(|page_num, _| *page_num < journal.num_pages);
+        self.storage.truncate((journal.num_pages * self.page_size) as u64)?;
+        self.num_pages = journal.num_pages;
+        for (page_num, original) in journal.pages {
+            self.get_page_mut(page_num)?.as_mut_slice().copy_from_slice(&original);
+        }
+        Ok(())
+    }
+
+    /// Writes every modified page back to disk.
+    pub fn flush(&mut self) -> Result<(), PagerError> {
+        let mut dirty: Vec<usize> = self.frames
+            .iter()
+            .filter(|(_, frame)| frame.dirty)
+            .map(|(page_num, _)| *page_num)
+            .collect();
+        dirty.sort_unstable();
+        for page_num in dirty {
+            self.write_back(page_num)?;
+        }
+        self.storage.sync()?;
+        Ok(())
+    }
+
+    fn write_back(&mut self, page_num: usize) -> Result<(), PagerError> {
+        // Can unwrap here since only cached pages are written back.
+        let frame = self.frames.get_mut(&page_num).unwrap();
+        self.storage.write_at(
+            (page_num * self.page_size) as u64,
+            frame.page.as_slice()
+        )?;
+        frame.dirty = false;
+        Ok(())
+    }
+
+    /// Removes the least recently used unpinned page from the cache,
+    /// writing it back first if it has been modified.
+    fn evict(&mut self) -> Result<(), PagerError> {
+        let page_num = self.frames
+            .iter()
+            .filter(|(_, frame)| frame.pin_count == 0)
+            .min_by_key(|(_, frame)| frame.last_used)
+            .map(|(page_num, _)| *page_num)
+            .ok_or(PagerError::CacheFull)?;
+        if self.frames[&page_num].dirty {
+            self.write_back(page_num)?;
+        }
+        self.frames.remove(&page_num);
+        Ok(())
+    }
+
+    fn load(&mut self, page_num: usize) -> Result<&mut Frame, PagerError> {
+        if page_num >= self.num_pages {
+            return Err(PagerError::PageOutOfBounds(page_num));
+        }
+        self.clock += 1;
+        if !self.frames.contains_key(&page_num) {
+            if self.frames.len() >= self.cache_capacity {
+                self.evict()?;
+            }
+            let mut page = unsafe {
+                page::Page::alloc_zeroed(self.page_size)
+                    .map_err(PagerError::AllocationError)?
+            };
+            self.storage.read_at(
+                (page_num * self.page_size) as u64,
+                page.as_mut_slice()
+            )?;
+            self.frames.insert(
+                page_num, 
+                Frame { page, dirty: false, pin_count: 0, last_used: 0 }
+            );
+        }
+        // Can unwrap here since the page was inserted above if it was
+        // not already cached.
+        let frame = self.frames.get_mut(&page_num).unwrap();
+        frame.last_used = self.clock;
+        Ok(frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, process};
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        env::temp_dir().join(format!("sqlite-rust-{}-{}.db", process::id(), name))
+    }
+
+    #[test]
+    fn flushed_pages_are_read_back_after_reopening() {
+        let path = temp_path("pager-reopen");
+        let _ = fs::remove_file(&path);
+        let mut pager = Pager::open(&path, MIN_PAGE_SIZE, 4).unwrap();
+        let page_num = pager.allocate_page().unwrap();
+        pager.get_page_mut(page_num).unwrap().copy_from_slice(0, b"hello world");
+        pager.flush().unwrap();
+        drop(pager);
+
+        let mut pager = Pager::open(&path, MIN_PAGE_SIZE, 4).unwrap();
+        assert_eq!(2, pager.num_pages());
+        let contents = pager.get_page(page_num).unwrap().read_from_index(0, 11).unwrap();
+        assert_eq!(b"hello world", &*contents);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn opening_with_different_page_size_returns_error() {
+        let path = temp_path("pager-page-size");
+        let _ = fs::remove_file(&path);
+        Pager::open(&path, MIN_PAGE_SIZE, 4).unwrap().flush().unwrap();
+        let result = Pager::open(&path, MIN_PAGE_SIZE * 2, 4);
+        assert!(matches!(result, Err(PagerError::InvalidHeader)));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn cache_never_holds_more_than_capacity_pages() {
+        let mut pager = Pager::in_memory(MIN_PAGE_SIZE, 3).unwrap();
+        for i in 0..10u8 {
+            let page_num = pager.allocate_page().unwrap();
+            pager.get_page_mut(page_num).unwrap().copy_from_slice(0, &[i]);
+            assert!(pager.cached_pages() <= 3);
+        }
+        for i in 0..10u8 {
+            let contents = pager.get_page(i as usize + 1).unwrap().read_from_index(0, 1).unwrap();
+            assert_eq!(i, contents[0]);
+        }
+    }
+
+    #[test]
+    fn freed_pages_are_reused_before_the_file_grows() {
+        let path = temp_path("pager-freelist");
+        let _ = fs::remove_file(&path);
+        let mut pager = Pager::open(&path, MIN_PAGE_SIZE, 2).unwrap();
+        let pages: Vec<usize> = (0..5).map(|_| pager.allocate_page().unwrap()).collect();
+        pager.get_page_mut(pages[1]).unwrap().copy_from_slice(0, b"stale");
+        pager.free_page(pages[1]).unwrap();
+        pager.free_page(pages[3]).unwrap();
+        assert_eq!(2, pager.free_page_count().unwrap());
+        pager.flush().unwrap();
+        drop(pager);
+
+        let mut pager = Pager::open(&path, MIN_PAGE_SIZE, 2).unwrap();
+        assert_eq!(2, pager.free_page_count().unwrap());
+        assert_eq!(pages[3], pager.allocate_page().unwrap());
+        assert_eq!(pages[1], pager.allocate_page().unwrap());
+        assert!(pager.get_page(pages[1]).unwrap().as_slice().iter().all(|byte| *byte == 0));
+        assert_eq!(0, pager.free_page_count().unwrap());
+        assert_eq!(6, pager.allocate_page().unwrap());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn rolled_back_statements_leave_pages_as_they_were() {
+        let path = temp_path("pager-rollback");
+        let _ = fs::remove_file(&path);
+        let mut pager = Pager::open(&path, MIN_PAGE_SIZE, 2).unwrap();
+        let kept = pager.allocate_page().unwrap();
+        let freed = pager.allocate_page().unwrap();
+        pager.get_page_mut(kept).unwrap().copy_from_slice(0, b"before");
+        pager.free_page(freed).unwrap();
+        pager.flush().unwrap();
+
+        pager.begin_statement();
+        pager.get_page_mut(kept).unwrap().copy_from_slice(0, b"after!");
+        assert_eq!(freed, pager.allocate_page().unwrap());
+        // Grows the file, since the small cache writes pages back.
+        for _ in 0..4 {
+            let page_num = pager.allocate_page().unwrap();
+            pager.get_page_mut(page_num).unwrap().copy_from_slice(0, b"new");
+        }
+        pager.rollback_statement().unwrap();
+        assert_eq!(3, pager.num_pages());
+        assert_eq!(1, pager.free_page_count().unwrap());
+        assert_eq!(b"before", &*pager.get_page(kept).unwrap().read_from_index(0, 6).unwrap());
+        pager.flush().unwrap();
+        assert_eq!(3 * MIN_PAGE_SIZE as u64, fs::metadata(&path).unwrap().len());
+
+        // Committed changes stay.
+        pager.begin_statement();
+        pager.get_page_mut(kept).unwrap().copy_from_slice(0, b"after!");
+        pager.commit_statement();
+        pager.rollback_statement().unwrap();
+        assert_eq!(b"after!", &*pager.get_page(kept).unwrap().read_from_index(0, 6).unwrap());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn pinned_pages_are_not_evicted() {
+        let mut pager = Pager::in_memory(MIN_PAGE_SIZE, 2).unwrap();
+        let first = pager.allocate_page().unwrap();
+        let second = pager.allocate_page().unwrap();
+        pager.pin(first).unwrap();
+        pager.pin(second).unwrap();
+        assert!(matches!(pager.get_page(0), Err(PagerError::CacheFull)));
+        pager.unpin(second);
+        pager.get_page(0).unwrap();
+        assert!(pager.frames.contains_key(&first));
+        assert!(!pager.frames.contains_key(&second));
+    }
+}
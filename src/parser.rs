@@ -0,0 +1,1013 @@
+use std::fmt;
+use crate::ast::*;
+use crate::lexer::{self, Keyword, Token, TokenKind};
+
+#[derive(Debug, PartialEq)]
+pub enum ParseError {
+    LexError(lexer::LexError),
+    /// The token with the given text cannot appear where it does.
+    UnexpectedToken(String),
+    /// The input ended in the middle of a statement.
+    IncompleteInput,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::LexError(e) => write!(f, "{e}"),
+            ParseError::UnexpectedToken(text) => write!(f, "near \"{text}\": syntax error"),
+            ParseError::IncompleteInput => write!(f, "incomplete input"),
+        }
+    }
+}
+
+impl From<lexer::LexError> for ParseError {
+    fn from(e: lexer::LexError) -> Self {
+        Self::LexError(e)
+    }
+}
+
+/// Parses a single SQL statement, optionally followed by a semicolon.
+pub fn parse(sql: &str) -> Result<Statement, ParseError> {
+    let mut parser = Parser { tokens: lexer::tokenise(sql)?, pos: 0 };
+    let statement = parser.statement()?;
+    parser.consume(&TokenKind::Semicolon);
+    match parser.peek() {
+        Some(token) => Err(ParseError::UnexpectedToken(token.text.clone())),
+        None => Ok(statement),
+    }
+}
+
+/// Recursive descent parser over a list of tokens.
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn peek_kind(&self) -> Option<&TokenKind> {
+        self.peek().map(|token| &token.kind)
+    }
+
+    fn next(&mut self) -> Result<Token, ParseError> {
+        let token = self.tokens.get(self.pos).cloned().ok_or(ParseError::IncompleteInput)?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    /// Returns an error for the token at the current position.
+    fn unexpected<T>(&self) -> Result<T, ParseError> {
+        match self.peek() {
+            Some(token) => Err(ParseError::UnexpectedToken(token.text.clone())),
+            None => Err(ParseError::IncompleteInput),
+        }
+    }
+
+    /// Moves past the next token if it is `kind`, returning whether it
+    /// was.
+    fn consume(&mut self, kind: &TokenKind) -> bool {
+        if self.peek_kind() == Some(kind) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn consume_keyword(&mut self, keyword: Keyword) -> bool {
+        self.consume(&TokenKind::Keyword(keyword))
+    }
+
+    fn expect(&mut self, kind: &TokenKind) -> Result<(), ParseError> {
+        if self.consume(kind) { Ok(()) } else { self.unexpected() }
+    }
+
+    fn expect_keyword(&mut self, keyword: Keyword) -> Result<(), ParseError> {
+        self.expect(&TokenKind::Keyword(keyword))
+    }
+
+    fn identifier(&mut self) -> Result<String, ParseError> {
+        match self.peek_kind() {
+            Some(TokenKind::Identifier(name)) => {
+                let name = name.clone();
+                self.pos += 1;
+                Ok(name)
+            },
+            Some(TokenKind::Keyword(keyword)) if keyword.is_fallback_identifier() => {
+                let name = self.tokens[self.pos].text.clone();
+                self.pos += 1;
+                Ok(name)
+            },
+            _ => self.unexpected(),
+        }
+    }
+
+    /// Parses a comma separated list of at least one item.
+    fn list<T>(&mut self, mut item: impl FnMut(&mut Self) -> Result<T, ParseError>) -> Result<Vec<T>, ParseError> {
+        let mut items = vec![item(self)?];
+        while self.consume(&TokenKind::Comma) {
+            items.push(item(self)?);
+        }
+        Ok(items)
+    }
+
+    fn statement(&mut self) -> Result<Statement, ParseError> {
+        match self.peek_kind() {
+            Some(TokenKind::Keyword(Keyword::Select | Keyword::With)) => Ok(Statement::Select(Box::new(self.select()?))),
+            Some(TokenKind::Keyword(Keyword::Insert)) => Ok(Statement::Insert(self.insert()?)),
+            Some(TokenKind::Keyword(Keyword::Update)) => Ok(Statement::Update(self.update()?)),
+            Some(TokenKind::Keyword(Keyword::Delete)) => Ok(Statement::Delete(self.delete()?)),
+            Some(TokenKind::Keyword(Keyword::Create)) => {
+                self.pos += 1;
+                match self.peek_kind() {
+                    Some(TokenKind::Keyword(Keyword::Table)) => Ok(Statement::CreateTable(self.create_table()?)),
+                    _ => Ok(Statement::CreateIndex(self.create_index()?)),
+                }
+            },
+            Some(TokenKind::Keyword(Keyword::Vacuum)) => {
+                self.pos += 1;
+                Ok(Statement::Vacuum)
+            },
+            Some(TokenKind::Keyword(Keyword::Analyze)) => {
+                self.pos += 1;
+                match self.peek_kind() {
+                    None | Some(TokenKind::Semicolon) => Ok(Statement::Analyze(None)),
+                    _ => Ok(Statement::Analyze(Some(self.identifier()?))),
+                }
+            },
+            Some(TokenKind::Keyword(Keyword::Explain)) => {
+                self.pos += 1;
+                let query_plan = self.consume_keyword(Keyword::Query);
+                if query_plan {
+                    self.expect_keyword(Keyword::Plan)?;
+                }
+                if self.peek_kind() == Some(&TokenKind::Keyword(Keyword::Explain)) {
+                    return self.unexpected();
+                }
+                let statement = Box::new(self.statement()?);
+                Ok(if query_plan { Statement::ExplainQueryPlan(statement) } else { Statement::Explain(statement) })
+            },
+            _ => self.unexpected(),
+        }
+    }
+
+    /// Returns whether the token `offset` places past the current one
+    /// can be read as a name.
+    fn is_name_at(&self, offset: usize) -> bool {
+        match self.tokens.get(self.pos + offset).map(|token| &token.kind) {
+            Some(TokenKind::Identifier(_)) => true,
+            Some(TokenKind::Keyword(keyword)) => keyword.is_fallback_identifier(),
+            _ => false,
+        }
+    }
+
+    /// Returns whether the next token starts a select. `WITH` does only
+    /// if a name follows it, and is a column name otherwise.
+    fn at_select(&self) -> bool {
+        match self.peek_kind() {
+            Some(TokenKind::Keyword(Keyword::Select)) => true,
+            Some(TokenKind::Keyword(Keyword::With)) => self.is_name_at(1),
+            _ => false,
+        }
+    }
+
+    fn select(&mut self) -> Result<Select, ParseError> {
+        let with = if self.consume_keyword(Keyword::With) {
+            // As in SQLite, a table may read itself whether or not
+            // RECURSIVE is given. It is the table's name if no other
+            // name follows it.
+            if self.peek_kind() == Some(&TokenKind::Keyword(Keyword::Recursive)) && self.is_name_at(1) {
+                self.pos += 1;
+            }
+            self.list(Self::cte)?
+        } else {
+            Vec::new()
+        };
+        let mut select = self.select_core()?;
+        while self.consume_keyword(Keyword::Union) {
+            let op = if self.consume_keyword(Keyword::All) { CompoundOp::UnionAll } else { CompoundOp::Union };
+            select.compound.push((op, self.select_core()?));
+        }
+        if self.consume_keyword(Keyword::Order) {
+            self.expect_keyword(Keyword::By)?;
+            select.order_by = self.list(Self::ordering_term)?;
+        }
+        select.limit = if self.consume_keyword(Keyword::Limit) {
+            let count = self.expr()?;
+            if self.consume_keyword(Keyword::Offset) {
+                Some(Limit { count, offset: Some(self.expr()?) })
+            } else if self.consume(&TokenKind::Comma) {
+                Some(Limit { count: self.expr()?, offset: Some(count) })
+            } else {
+                Some(Limit { count, offset: None })
+            }
+        } else {
+            None
+        };
+        Ok(Select { with, ..select })
+    }
+
+    /// Parses `name [(columns)] AS (select)` in a `WITH` clause.
+    fn cte(&mut self) -> Result<Cte, ParseError> {
+        let name = self.identifier()?;
+        let mut columns = Vec::new();
+        if self.consume(&TokenKind::LeftParen) {
+            columns = self.list(Self::identifier)?;
+            self.expect(&TokenKind::RightParen)?;
+        }
+        self.expect_keyword(Keyword::As)?;
+        self.expect(&TokenKind::LeftParen)?;
+        let select = self.select()?;
+        self.expect(&TokenKind::RightParen)?;
+        Ok(Cte { name, columns, select })
+    }
+
+    /// Parses a select up to its `HAVING` clause, which is as much as
+    /// each select of a `UNION` has.
+    fn select_core(&mut self) -> Result<Select, ParseError> {
+        self.expect_keyword(Keyword::Select)?;
+        let columns = self.list(Self::result_column)?;
+        let from = if self.consume_keyword(Keyword::From) { self.joins()? } else { Vec::new() };
+        let where_clause = self.where_clause()?;
+        let mut group_by = Vec::new();
+        if self.consume_keyword(Keyword::Group) {
+            self.expect_keyword(Keyword::By)?;
+            group_by = self.list(Self::expr)?;
+        }
+        let having = if self.consume_keyword(Keyword::Having) {
+            Some(self.expr()?)
+        } else {
+            None
+        };
+        Ok(Select {
+            with: Vec::new(),
+            columns,
+            from,
+            where_clause,
+            group_by,
+            having,
+            compound: Vec::new(),
+            order_by: Vec::new(),
+            limit: None,
+        })
+    }
+
+    /// Parses the tables of a `FROM` clause and the joins between them.
+    fn joins(&mut self) -> Result<Vec<Join>, ParseError> {
+        let mut from = vec![self.join(JoinKind::Inner)?];
+        loop {
+            let kind = if self.consume(&TokenKind::Comma) || self.consume_keyword(Keyword::Join) {
+                JoinKind::Inner
+            } else if self.consume_keyword(Keyword::Inner) || self.consume_keyword(Keyword::Cross) {
+                self.expect_keyword(Keyword::Join)?;
+                JoinKind::Inner
+            } else if self.consume_keyword(Keyword::Left) {
+                self.consume_keyword(Keyword::Outer);
+                self.expect_keyword(Keyword::Join)?;
+                JoinKind::Left
+            } else {
+                return Ok(from);
+            };
+            let mut join = self.join(kind)?;
+            if self.consume_keyword(Keyword::On) {
+                join.on = Some(self.expr()?);
+            }
+            from.push(join);
+        }
+    }
+
+    /// Parses a table name and its alias, if any.
+    fn join(&mut self, kind: JoinKind) -> Result<Join, ParseError> {
+        let table = self.identifier()?;
+        let alias = if self.consume_keyword(Keyword::As) {
+            Some(self.identifier()?)
+        } else if let Some(TokenKind::Identifier(_)) = self.peek_kind() {
+            Some(self.identifier()?)
+        } else {
+            None
+        };
+        Ok(Join { table, alias, kind, on: None })
+    }
+
+    fn ordering_term(&mut self) -> Result<OrderingTerm, ParseError> {
+        let expr = self.expr()?;
+        let descending = if self.consume_keyword(Keyword::Desc) {
+            true
+        } else {
+            self.consume_keyword(Keyword::Asc);
+            false
+        };
+        Ok(OrderingTerm { expr, descending })
+    }
+
+    fn result_column(&mut self) -> Result<ResultColumn, ParseError> {
+        if self.consume(&TokenKind::Star) {
+            return Ok(ResultColumn::All);
+        }
+        let kinds = (self.tokens.get(self.pos + 1).map(|token| &token.kind), self.tokens.get(self.pos + 2).map(|token| &token.kind));
+        if let (Some(TokenKind::Dot), Some(TokenKind::Star)) = kinds {
+            let table = self.identifier()?;
+            self.pos += 2;
+            return Ok(ResultColumn::TableAll(table));
+        }
+        let expr = self.expr()?;
+        let alias = if self.consume_keyword(Keyword::As) {
+            Some(self.identifier()?)
+        } else if let Some(TokenKind::Identifier(_)) = self.peek_kind() {
+            Some(self.identifier()?)
+        } else {
+            None
+        };
+        Ok(ResultColumn::Expr { expr, alias })
+    }
+
+    fn insert(&mut self) -> Result<Insert, ParseError> {
+        self.expect_keyword(Keyword::Insert)?;
+        self.expect_keyword(Keyword::Into)?;
+        let table = self.identifier()?;
+        let mut columns = Vec::new();
+        if self.consume(&TokenKind::LeftParen) {
+            columns = self.list(Self::identifier)?;
+            self.expect(&TokenKind::RightParen)?;
+        }
+        self.expect_keyword(Keyword::Values)?;
+        let rows = self.list(|parser| {
+            parser.expect(&TokenKind::LeftParen)?;
+            let values = parser.list(Self::expr)?;
+            parser.expect(&TokenKind::RightParen)?;
+            Ok(values)
+        })?;
+        Ok(Insert { table, columns, rows })
+    }
+
+    fn update(&mut self) -> Result<Update, ParseError> {
+        self.expect_keyword(Keyword::Update)?;
+        let table = self.identifier()?;
+        self.expect_keyword(Keyword::Set)?;
+        let assignments = self.list(|parser| {
+            let column = parser.identifier()?;
+            parser.expect(&TokenKind::Eq)?;
+            Ok((column, parser.expr()?))
+        })?;
+        let where_clause = self.where_clause()?;
+        Ok(Update { table, assignments, where_clause })
+    }
+
+    fn delete(&mut self) -> Result<Delete, ParseError> {
+        self.expect_keyword(Keyword::Delete)?;
+        self.expect_keyword(Keyword::From)?;
+        let table = self.identifier()?;
+        let where_clause = self.where_clause()?;
+        Ok(Delete { table, where_clause })
+    }
+
+    fn where_clause(&mut self) -> Result<Option<Expr>, ParseError> {
+        if self.consume_keyword(Keyword::Where) {
+            Ok(Some(self.expr()?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Parses `[IF NOT EXISTS]`, returning whether it was present.
+    fn if_not_exists(&mut self) -> Result<bool, ParseError> {
+        let if_not_exists = self.consume_keyword(Keyword::If);
+        if if_not_exists {
+            self.expect_keyword(Keyword::Not)?;
+            self.expect_keyword(Keyword::Exists)?;
+        }
+        Ok(if_not_exists)
+    }
+
+    /// Parses `CREATE TABLE` after the `CREATE`.
+    fn create_table(&mut self) -> Result<CreateTable, ParseError> {
+        self.expect_keyword(Keyword::Table)?;
+        let if_not_exists = self.if_not_exists()?;
+        let name = self.identifier()?;
+        self.expect(&TokenKind::LeftParen)?;
+        let columns = self.list(Self::column_def)?;
+        self.expect(&TokenKind::RightParen)?;
+        Ok(CreateTable { name, if_not_exists, columns })
+    }
+
+    /// Parses `CREATE [UNIQUE] INDEX` after the `CREATE`.
+    fn create_index(&mut self) -> Result<CreateIndex, ParseError> {
+        let unique = self.consume_keyword(Keyword::Unique);
+        self.expect_keyword(Keyword::Index)?;
+        let if_not_exists = self.if_not_exists()?;
+        let name = self.identifier()?;
+        self.expect_keyword(Keyword::On)?;
+        let table = self.identifier()?;
+        self.expect(&TokenKind::LeftParen)?;
+        let columns = self.list(Self::identifier)?;
+        self.expect(&TokenKind::RightParen)?;
+        Ok(CreateIndex { name, table, columns, unique, if_not_exists })
+    }
+
+    fn column_def(&mut self) -> Result<ColumnDef, ParseError> {
+        let name = self.identifier()?;
+        let type_name = self.type_name()?;
+        let mut column = ColumnDef { name, type_name, primary_key: false, not_null: false };
+        loop {
+            if self.consume_keyword(Keyword::Primary) {
+                self.expect_keyword(Keyword::Key)?;
+                column.primary_key = true;
+            } else if self.consume_keyword(Keyword::Not) {
+                self.expect_keyword(Keyword::Null)?;
+                column.not_null = true;
+            } else {
+                return Ok(column);
+            }
+        }
+    }
+
+    /// Parses a declared column type: one or more words, optionally
+    /// followed by one or two sizes in brackets, as in `VARCHAR(20)`.
+    fn type_name(&mut self) -> Result<Option<String>, ParseError> {
+        let mut words = Vec::new();
+        while let Some(TokenKind::Identifier(_)) = self.peek_kind() {
+            words.push(self.identifier()?);
+        }
+        if words.is_empty() {
+            return Ok(None);
+        }
+        let mut type_name = words.join(" ");
+        if self.consume(&TokenKind::LeftParen) {
+            let sizes = self.list(|parser| {
+                let negative = parser.consume(&TokenKind::Minus);
+                match parser.peek_kind() {
+                    Some(TokenKind::Integer(size)) => {
+                        let size = if negative { -size } else { *size };
+                        parser.pos += 1;
+                        Ok(size)
+                    },
+                    _ => parser.unexpected(),
+                }
+            })?;
+            self.expect(&TokenKind::RightParen)?;
+            let sizes: Vec<String> = sizes.iter().map(|size| size.to_string()).collect();
+            type_name.push_str(&format!("({})", sizes.join(",")));
+        }
+        Ok(Some(type_name))
+    }
+
+    fn expr(&mut self) -> Result<Expr, ParseError> {
+        self.binary_expr(0)
+    }
+
+    /// Parses binary operators from precedence level `level` upwards,
+    /// grouping operators of equal precedence from the left.
+    fn binary_expr(&mut self, level: usize) -> Result<Expr, ParseError> {
+        // Operators from lowest to highest precedence. NOT binds more
+        // loosely than comparisons, so it is handled between AND and =.
+        const LEVELS: &[&[(TokenKind, BinaryOp)]] = &[
+            &[(TokenKind::Keyword(Keyword::Or), BinaryOp::Or)],
+            &[(TokenKind::Keyword(Keyword::And), BinaryOp::And)],
+            &[],
+            &[(TokenKind::Eq, BinaryOp::Eq), (TokenKind::NotEq, BinaryOp::NotEq)],
+            &[
+                (TokenKind::Lt, BinaryOp::Lt),
+                (TokenKind::LtEq, BinaryOp::LtEq),
+                (TokenKind::Gt, BinaryOp::Gt),
+                (TokenKind::GtEq, BinaryOp::GtEq),
+            ],
+            &[(TokenKind::Plus, BinaryOp::Add), (TokenKind::Minus, BinaryOp::Subtract)],
+            &[
+                (TokenKind::Star, BinaryOp::Multiply),
+                (TokenKind::Slash, BinaryOp::Divide),
+                (TokenKind::Percent, BinaryOp::Remainder),
+            ],
+            &[(TokenKind::Concat, BinaryOp::Concat)],
+        ];
+        const NOT_LEVEL: usize = 2;
+        // `IS` and `IS NOT` take more than one token, so they are
+        // matched separately from the other operators at their level.
+        const IS_LEVEL: usize = 3;
+        if level == LEVELS.len() {
+            return self.unary_expr();
+        }
+        if level == NOT_LEVEL {
+            if self.consume_keyword(Keyword::Not) {
+                let operand = self.binary_expr(NOT_LEVEL)?;
+                return Ok(Expr::Unary { op: UnaryOp::Not, operand: Box::new(operand) });
+            }
+            return self.binary_expr(level + 1);
+        }
+        let mut left = self.binary_expr(level + 1)?;
+        'outer: loop {
+            for (kind, op) in LEVELS[level] {
+                if self.consume(kind) {
+                    let right = self.binary_expr(level + 1)?;
+                    left = Expr::Binary { op: *op, left: Box::new(left), right: Box::new(right) };
+                    continue 'outer;
+                }
+            }
+            if level == IS_LEVEL && self.consume_keyword(Keyword::Is) {
+                let op = if self.consume_keyword(Keyword::Not) { BinaryOp::IsNot } else { BinaryOp::Is };
+                let right = self.binary_expr(level + 1)?;
+                left = Expr::Binary { op, left: Box::new(left), right: Box::new(right) };
+                continue;
+            }
+            if level == IS_LEVEL {
+                let negated = self.peek_kind() == Some(&TokenKind::Keyword(Keyword::Not))
+                    && self.tokens.get(self.pos + 1).map(|token| &token.kind) == Some(&TokenKind::Keyword(Keyword::In));
+                if negated {
+                    self.pos += 1;
+                }
+                if self.consume_keyword(Keyword::In) {
+                    left = self.in_expr(left, negated)?;
+                    continue;
+                }
+            }
+            return Ok(left);
+        }
+    }
+
+    fn unary_expr(&mut self) -> Result<Expr, ParseError> {
+        let op = if self.consume(&TokenKind::Minus) {
+            UnaryOp::Negate
+        } else if self.consume(&TokenKind::Plus) {
+            UnaryOp::Plus
+        } else {
+            return self.primary_expr();
+        };
+        let operand = self.unary_expr()?;
+        Ok(match (op, operand) {
+            (UnaryOp::Negate, Expr::Literal(Literal::Integer(value))) => {
+                Expr::Literal(Literal::Integer(-value))
+            },
+            (UnaryOp::Negate, Expr::Literal(Literal::Float(value))) => {
+                Expr::Literal(Literal::Float(-value))
+            },
+            (op, operand) => Expr::Unary { op, operand: Box::new(operand) },
+        })
+    }
+
+    fn primary_expr(&mut self) -> Result<Expr, ParseError> {
+        let token = self.next()?;
+        let expr = match token.kind {
+            TokenKind::Integer(value) => Expr::Literal(Literal::Integer(value)),
+            TokenKind::Float(value) => Expr::Literal(Literal::Float(value)),
+            TokenKind::String(value) => Expr::Literal(Literal::String(value)),
+            TokenKind::Blob(value) => Expr::Literal(Literal::Blob(value)),
+            TokenKind::Keyword(Keyword::Null) => Expr::Literal(Literal::Null),
+            TokenKind::Identifier(name) if self.consume(&TokenKind::LeftParen) => self.function_call(name)?,
+            TokenKind::Identifier(name) => self.column_ref(name)?,
+            TokenKind::Keyword(keyword) if keyword.is_fallback_identifier() => self.column_ref(token.text)?,
+            TokenKind::LeftParen if self.at_select() => {
+                let select = self.select()?;
+                self.expect(&TokenKind::RightParen)?;
+                Expr::Subquery(Box::new(select))
+            },
+            TokenKind::LeftParen => {
+                let expr = self.expr()?;
+                self.expect(&TokenKind::RightParen)?;
+                expr
+            },
+            TokenKind::Keyword(Keyword::Exists) => {
+                self.expect(&TokenKind::LeftParen)?;
+                let select = self.select()?;
+                self.expect(&TokenKind::RightParen)?;
+                Expr::Exists(Box::new(select))
+            },
+            _ => return Err(ParseError::UnexpectedToken(token.text)),
+        };
+        Ok(expr)
+    }
+
+    /// Parses the parenthesised list of values or select after `IN`,
+    /// which `expr` is compared with.
+    fn in_expr(&mut self, expr: Expr, negated: bool) -> Result<Expr, ParseError> {
+        self.expect(&TokenKind::LeftParen)?;
+        let expr = Box::new(expr);
+        let in_expr = if self.at_select() {
+            Expr::InSelect { expr, select: Box::new(self.select()?), negated }
+        } else if self.peek_kind() == Some(&TokenKind::RightParen) {
+            Expr::InList { expr, list: Vec::new(), negated }
+        } else {
+            Expr::InList { expr, list: self.list(Self::expr)?, negated }
+        };
+        self.expect(&TokenKind::RightParen)?;
+        Ok(in_expr)
+    }
+
+    /// Parses the rest of a column reference starting with `name`,
+    /// which is the table name if followed by a dot.
+    fn column_ref(&mut self, name: String) -> Result<Expr, ParseError> {
+        if self.consume(&TokenKind::Dot) {
+            Ok(Expr::Column { table: Some(name), name: self.identifier()? })
+        } else {
+            Ok(Expr::Column { table: None, name })
+        }
+    }
+
+    /// Parses the arguments and closing parenthesis of a call to the
+    /// function `name`.
+    fn function_call(&mut self, name: String) -> Result<Expr, ParseError> {
+        let args = if self.consume(&TokenKind::Star) || self.peek_kind() == Some(&TokenKind::RightParen) {
+            Vec::new()
+        } else {
+            self.list(Self::expr)?
+        };
+        self.expect(&TokenKind::RightParen)?;
+        Ok(Expr::Function { name, args })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn column(name: &str) -> Expr {
+        Expr::Column { table: None, name: String::from(name) }
+    }
+
+    fn binary(op: BinaryOp, left: Expr, right: Expr) -> Expr {
+        Expr::Binary { op, left: Box::new(left), right: Box::new(right) }
+    }
+
+    #[test]
+    fn insert_with_columns_and_several_rows_is_parsed() {
+        let statement = parse("INSERT INTO users (id, name) VALUES (1, 'a b'), (-2, NULL);").unwrap();
+        assert_eq!(
+            Statement::Insert(Insert {
+                table: String::from("users"),
+                columns: vec![String::from("id"), String::from("name")],
+                rows: vec![
+                    vec![Expr::Literal(Literal::Integer(1)), Expr::Literal(Literal::String(String::from("a b")))],
+                    vec![Expr::Literal(Literal::Integer(-2)), Expr::Literal(Literal::Null)],
+                ],
+            }),
+            statement
+        );
+    }
+
+    #[test]
+    fn select_with_aliases_is_parsed() {
+        let statement = parse("select *, users.id as key, name n from users").unwrap();
+        assert_eq!(
+            Statement::Select(Box::new(Select {
+                with: Vec::new(),
+                columns: vec![
+                    ResultColumn::All,
+                    ResultColumn::Expr {
+                        expr: Expr::Column { table: Some(String::from("users")), name: String::from("id") },
+                        alias: Some(String::from("key")),
+                    },
+                    ResultColumn::Expr { expr: column("name"), alias: Some(String::from("n")) },
+                ],
+                from: vec![Join { table: String::from("users"), alias: None, kind: JoinKind::Inner, on: None }],
+                where_clause: None,
+                group_by: Vec::new(),
+                having: None,
+                compound: Vec::new(),
+                order_by: Vec::new(),
+                limit: None,
+            })),
+            statement
+        );
+    }
+
+    #[test]
+    fn joins_are_parsed_in_order() {
+        let Statement::Select(select) = parse(
+            "select a.*, b.x from t1 a join t2 as b on a.id = b.id left outer join t3 on t3.k = b.k, t4 cross join t5"
+        ).unwrap() else {
+            panic!("expected select");
+        };
+        let qualified = |table: &str, name: &str| Expr::Column { table: Some(String::from(table)), name: String::from(name) };
+        let join = |table: &str, alias: Option<&str>, kind, on| Join {
+            table: String::from(table),
+            alias: alias.map(String::from),
+            kind,
+            on,
+        };
+        assert_eq!(
+            vec![ResultColumn::TableAll(String::from("a")), ResultColumn::Expr { expr: qualified("b", "x"), alias: None }],
+            select.columns
+        );
+        assert_eq!(
+            vec![
+                join("t1", Some("a"), JoinKind::Inner, None),
+                join("t2", Some("b"), JoinKind::Inner, Some(binary(BinaryOp::Eq, qualified("a", "id"), qualified("b", "id")))),
+                join("t3", None, JoinKind::Left, Some(binary(BinaryOp::Eq, qualified("t3", "k"), qualified("b", "k")))),
+                join("t4", None, JoinKind::Inner, None),
+                join("t5", None, JoinKind::Inner, None),
+            ],
+            select.from
+        );
+        assert!(parse("select * from a left b").is_err());
+        assert!(parse("select * from a inner left join b").is_err());
+        // Join keywords can name tables and columns, but are never taken
+        // as aliases.
+        let Statement::Select(select) = parse("select left, outer.inner from cross left join outer on left = 1").unwrap() else {
+            panic!("expected select");
+        };
+        assert_eq!(
+            vec![
+                ResultColumn::Expr { expr: column("left"), alias: None },
+                ResultColumn::Expr { expr: qualified("outer", "inner"), alias: None },
+            ],
+            select.columns
+        );
+        assert_eq!(
+            vec![
+                join("cross", None, JoinKind::Inner, None),
+                join("outer", None, JoinKind::Left, Some(binary(BinaryOp::Eq, column("left"), Expr::Literal(Literal::Integer(1))))),
+            ],
+            select.from
+        );
+    }
+
+    #[test]
+    fn binary_operators_follow_precedence() {
+        let Statement::Select(select) = parse("select a + b * c = d or not e and f").unwrap() else {
+            panic!("expected select");
+        };
+        let expected = binary(
+            BinaryOp::Or,
+            binary(
+                BinaryOp::Eq,
+                binary(BinaryOp::Add, column("a"), binary(BinaryOp::Multiply, column("b"), column("c"))),
+                column("d"),
+            ),
+            binary(
+                BinaryOp::And,
+                Expr::Unary { op: UnaryOp::Not, operand: Box::new(column("e")) },
+                column("f"),
+            ),
+        );
+        assert_eq!(vec![ResultColumn::Expr { expr: expected, alias: None }], select.columns);
+    }
+
+    #[test]
+    fn select_with_where_order_by_and_limit_is_parsed() {
+        let integer = |value| Expr::Literal(Literal::Integer(value));
+        let Statement::Select(select) = parse("select a from t where b is not null and c is d order by a desc, 2 limit 5 offset 1").unwrap() else {
+            panic!("expected select");
+        };
+        assert_eq!(
+            Some(binary(
+                BinaryOp::And,
+                binary(BinaryOp::IsNot, column("b"), Expr::Literal(Literal::Null)),
+                binary(BinaryOp::Is, column("c"), column("d")),
+            )),
+            select.where_clause
+        );
+        assert_eq!(
+            vec![
+                OrderingTerm { expr: column("a"), descending: true },
+                OrderingTerm { expr: integer(2), descending: false },
+            ],
+            select.order_by
+        );
+        assert_eq!(Some(Limit { count: integer(5), offset: Some(integer(1)) }), select.limit);
+        let Statement::Select(select) = parse("select a from t limit 1, 5").unwrap() else {
+            panic!("expected select");
+        };
+        assert_eq!(Some(Limit { count: integer(5), offset: Some(integer(1)) }), select.limit);
+        assert!(parse("select a from t order a").is_err());
+        assert!(parse("select a from t limit").is_err());
+    }
+
+    #[test]
+    fn select_with_aggregates_group_by_and_having_is_parsed() {
+        let function = |name: &str, args| Expr::Function { name: String::from(name), args };
+        let Statement::Select(select) = parse("select a, count(*), group_concat(b, '-') from t group by a, c having max(b) > 1").unwrap() else {
+            panic!("expected select");
+        };
+        assert_eq!(
+            vec![
+                ResultColumn::Expr { expr: column("a"), alias: None },
+                ResultColumn::Expr { expr: function("count", Vec::new()), alias: None },
+                ResultColumn::Expr {
+                    expr: function("group_concat", vec![column("b"), Expr::Literal(Literal::String(String::from("-")))]),
+                    alias: None,
+                },
+            ],
+            select.columns
+        );
+        assert_eq!(vec![column("a"), column("c")], select.group_by);
+        assert_eq!(
+            Some(binary(BinaryOp::Gt, function("max", vec![column("b")]), Expr::Literal(Literal::Integer(1)))),
+            select.having
+        );
+        assert!(parse("select count(a from t").is_err());
+        assert!(parse("select a from t group a").is_err());
+    }
+
+    #[test]
+    fn subqueries_and_in_lists_are_parsed() {
+        let integer = |value| Expr::Literal(Literal::Integer(value));
+        let Statement::Select(select) = parse(
+            "select (select max(b) from u) from t where a not in (1, 2) and not exists (select * from u where u.b = t.a) or c in (select b from u) or d in ()"
+        ).unwrap() else {
+            panic!("expected select");
+        };
+        let Statement::Select(max_b) = parse("select max(b) from u").unwrap() else {
+            panic!("expected select");
+        };
+        let Statement::Select(matching) = parse("select * from u where u.b = t.a").unwrap() else {
+            panic!("expected select");
+        };
+        let Statement::Select(all_b) = parse("select b from u").unwrap() else {
+            panic!("expected select");
+        };
+        assert_eq!(vec![ResultColumn::Expr { expr: Expr::Subquery(max_b), alias: None }], select.columns);
+        let not_in = Expr::InList { expr: Box::new(column("a")), list: vec![integer(1), integer(2)], negated: true };
+        let not_exists = Expr::Unary { op: UnaryOp::Not, operand: Box::new(Expr::Exists(matching)) };
+        let in_select = Expr::InSelect { expr: Box::new(column("c")), select: all_b, negated: false };
+        let in_empty = Expr::InList { expr: Box::new(column("d")), list: Vec::new(), negated: false };
+        assert_eq!(
+            Some(binary(BinaryOp::Or, binary(BinaryOp::Or, binary(BinaryOp::And, not_in, not_exists), in_select), in_empty)),
+            select.where_clause
+        );
+        assert!(parse("select a in 1").is_err());
+        assert!(parse("select exists select 1").is_err());
+        assert!(parse("select a not (1)").is_err());
+    }
+
+    #[test]
+    fn common_table_expressions_and_unions_are_parsed() {
+        let Statement::Select(select) = parse(
+            "with recursive tree(id, depth) as (select 1, 0 union all select id + 1, depth from tree limit 5), t2 as (select * from tree) select id from tree union select id from t2 order by 1"
+        ).unwrap() else {
+            panic!("expected select");
+        };
+        let Statement::Select(anchor) = parse("select 1, 0").unwrap() else {
+            panic!("expected select");
+        };
+        let Statement::Select(step) = parse("select id + 1, depth from tree").unwrap() else {
+            panic!("expected select");
+        };
+        let Statement::Select(from_tree) = parse("select id from tree").unwrap() else {
+            panic!("expected select");
+        };
+        let tree = Cte {
+            name: String::from("tree"),
+            columns: vec![String::from("id"), String::from("depth")],
+            select: Select {
+                compound: vec![(CompoundOp::UnionAll, *step)],
+                limit: Some(Limit { count: Expr::Literal(Literal::Integer(5)), offset: None }),
+                ..*anchor
+            },
+        };
+        assert_eq!(vec![String::from("tree"), String::from("t2")], select.with.iter().map(|cte| cte.name.clone()).collect::<Vec<_>>());
+        assert_eq!(tree, select.with[0]);
+        assert!(select.with[1].columns.is_empty());
+        assert_eq!(from_tree.columns, select.columns);
+        assert_eq!(1, select.compound.len());
+        assert_eq!(CompoundOp::Union, select.compound[0].0);
+        assert!(select.compound[0].1.order_by.is_empty());
+        assert_eq!(1, select.order_by.len());
+        assert!(matches!(parse("select (with t as (select 1) select * from t)"), Ok(Statement::Select(_))));
+        // WITH and RECURSIVE can also be names.
+        let Statement::Select(select) = parse("with recursive as (select with from t) select (with), recursive.with from recursive").unwrap() else {
+            panic!("expected select");
+        };
+        assert_eq!("recursive", select.with[0].name);
+        assert_eq!(vec![ResultColumn::Expr { expr: column("with"), alias: None }], select.with[0].select.columns);
+        assert_eq!(
+            vec![
+                ResultColumn::Expr { expr: column("with"), alias: None },
+                ResultColumn::Expr { expr: Expr::Column { table: Some(String::from("recursive")), name: String::from("with") }, alias: None },
+            ],
+            select.columns
+        );
+        let Statement::Select(select) = parse("with recursive recursive(with) as (select 1) select * from recursive").unwrap() else {
+            panic!("expected select");
+        };
+        assert_eq!(vec![String::from("with")], select.with[0].columns);
+        assert!(parse("with t as select 1 select * from t").is_err());
+        assert!(parse("select 1 order by 1 union select 2").is_err());
+    }
+
+    #[test]
+    fn create_table_with_types_and_constraints_is_parsed() {
+        let statement = parse("create table if not exists t (id integer primary key, name varchar(20) not null, x)").unwrap();
+        let Statement::CreateTable(create) = statement else {
+            panic!("expected create table");
+        };
+        assert!(create.if_not_exists);
+        assert_eq!(
+            vec![
+                ColumnDef { name: String::from("id"), type_name: Some(String::from("integer")), primary_key: true, not_null: false },
+                ColumnDef { name: String::from("name"), type_name: Some(String::from("varchar(20)")), primary_key: false, not_null: true },
+                ColumnDef { name: String::from("x"), type_name: None, primary_key: false, not_null: false },
+            ],
+            create.columns
+        );
+        assert_eq!(
+            "CREATE TABLE t (id integer PRIMARY KEY, name varchar(20) NOT NULL, x)",
+            create.to_string()
+        );
+        let reparsed = CreateTable { if_not_exists: false, ..create.clone() };
+        assert_eq!(Statement::CreateTable(reparsed), parse(&create.to_string()).unwrap());
+        let Statement::CreateTable(create) = parse("create table \"select\" (\"a b\")").unwrap() else {
+            panic!("expected create table");
+        };
+        assert_eq!("CREATE TABLE \"select\" (\"a b\")", create.to_string());
+        let Statement::CreateTable(create) = parse("create table v (id integer primary key, vacuum text)").unwrap() else {
+            panic!("expected create table");
+        };
+        assert_eq!("CREATE TABLE v (id integer PRIMARY KEY, \"vacuum\" text)", create.to_string());
+        let Statement::Select(select) = parse("select vacuum from v where vacuum is not null").unwrap() else {
+            panic!("expected select");
+        };
+        assert_eq!(vec![ResultColumn::Expr { expr: column("vacuum"), alias: None }], select.columns);
+        assert_eq!(Statement::Vacuum, parse("vacuum").unwrap());
+    }
+
+    #[test]
+    fn create_index_is_parsed() {
+        let statement = parse("create unique index if not exists by_name on users (last, \"first name\")").unwrap();
+        let Statement::CreateIndex(create) = statement else {
+            panic!("expected create index");
+        };
+        assert!(create.unique && create.if_not_exists);
+        assert_eq!(("by_name", "users"), (create.name.as_str(), create.table.as_str()));
+        assert_eq!(vec![String::from("last"), String::from("first name")], create.columns);
+        assert_eq!("CREATE UNIQUE INDEX by_name ON users (last, \"first name\")", create.to_string());
+        assert!(matches!(parse("create index i on t (a)"), Ok(Statement::CreateIndex(CreateIndex { unique: false, .. }))));
+        assert!(parse("create index i on t ()").is_err());
+        assert!(parse("create unique table t (a)").is_err());
+    }
+
+    #[test]
+    fn delete_with_and_without_where_is_parsed() {
+        assert_eq!(
+            Statement::Delete(Delete {
+                table: String::from("t"),
+                where_clause: Some(binary(BinaryOp::GtEq, column("a"), Expr::Literal(Literal::Integer(2)))),
+            }),
+            parse("delete from t where a >= 2").unwrap()
+        );
+        assert_eq!(
+            Statement::Delete(Delete { table: String::from("t"), where_clause: None }),
+            parse("DELETE FROM t;").unwrap()
+        );
+        assert!(parse("delete t").is_err());
+    }
+
+    #[test]
+    fn update_with_several_assignments_is_parsed() {
+        assert_eq!(
+            Statement::Update(Update {
+                table: String::from("t"),
+                assignments: vec![
+                    (String::from("a"), binary(BinaryOp::Add, column("a"), Expr::Literal(Literal::Integer(1)))),
+                    (String::from("b"), Expr::Literal(Literal::Null)),
+                ],
+                where_clause: Some(binary(BinaryOp::Eq, column("id"), Expr::Literal(Literal::Integer(3)))),
+            }),
+            parse("update t set a = a + 1, b = null where id = 3").unwrap()
+        );
+        assert!(parse("update t set where a = 1").is_err());
+        assert!(parse("update t a = 1").is_err());
+    }
+
+    #[test]
+    fn explain_wraps_the_explained_statement() {
+        assert_eq!(
+            Statement::Explain(Box::new(Statement::Select(Box::new(Select {
+                with: Vec::new(),
+                columns: vec![ResultColumn::All],
+                from: vec![Join { table: String::from("users"), alias: None, kind: JoinKind::Inner, on: None }],
+                where_clause: None,
+                group_by: Vec::new(),
+                having: None,
+                compound: Vec::new(),
+                order_by: Vec::new(),
+                limit: None,
+            })))),
+            parse("EXPLAIN SELECT * FROM users").unwrap()
+        );
+        assert_eq!(Err(ParseError::UnexpectedToken(String::from("explain"))), parse("explain explain select 1"));
+        assert_eq!(
+            Err(ParseError::UnexpectedToken(String::from("explain"))),
+            parse("explain query plan explain select 1")
+        );
+        assert_eq!(Statement::Explain(Box::new(Statement::Vacuum)), parse("explain vacuum;").unwrap());
+        assert_eq!(
+            Statement::ExplainQueryPlan(Box::new(Statement::Vacuum)),
+            parse("explain query plan vacuum").unwrap()
+        );
+        assert!(parse("explain query vacuum").is_err());
+        assert_eq!(
+            Statement::Explain(Box::new(Statement::Analyze(Some(String::from("users"))))),
+            parse("explain analyze users;").unwrap()
+        );
+        assert_eq!(Statement::Analyze(None), parse("ANALYZE").unwrap());
+    }
+
+    #[test]
+    fn invalid_statements_return_errors() {
+        assert_eq!(Err(ParseError::UnexpectedToken(String::from("values"))), parse("insert into values (1)"));
+        assert_eq!(Err(ParseError::IncompleteInput), parse("select 1 +"));
+        assert_eq!(Err(ParseError::UnexpectedToken(String::from("2"))), parse("select 1 2"));
+    }
+}
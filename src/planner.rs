@@ -0,0 +1,459 @@
+use std::fmt;
+use crate::{ast, schema::{self, IndexSchema, TableSchema}};
+
+/// Number of rows assumed to be in a table without statistics.
+const DEFAULT_TABLE_ROWS: f64 = 1_000_000.0;
+/// Number of rows assumed to share a value in the first column of an
+/// index without statistics. Each further column divides it by 2.
+const DEFAULT_ROWS_PER_KEY: f64 = 10.0;
+/// Fraction of rows assumed to be kept by each bound of a range.
+const RANGE_SELECTIVITY: f64 = 0.25;
+/// Cost of reading a row from an index compared with reading it from
+/// its table, since index entries are smaller and more fit in a page.
+const INDEX_ROW_COST: f64 = 0.5;
+
+/// How the rows of a table are found.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Access<'a> {
+    /// Every row in row id order.
+    FullScan,
+    /// The one row whose id equals the value.
+    RowidEq(&'a ast::Expr),
+    /// The entries of `index` whose first columns equal `equal`, and
+    /// whose next column lies between `lower` and `upper` if given, in
+    /// index order.
+    Index {
+        index: &'a IndexSchema,
+        /// Position in the table of each indexed column.
+        columns: Vec<usize>,
+        equal: Vec<&'a ast::Expr>,
+        lower: Option<Bound<'a>>,
+        upper: Option<Bound<'a>>,
+        /// Whether every column the statement uses is in the index, so
+        /// the table itself is never read.
+        covering: bool,
+    },
+}
+
+/// One end of a range of values.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bound<'a> {
+    pub value: &'a ast::Expr,
+    /// Whether values equal to the bound are in the range.
+    pub inclusive: bool,
+}
+
+/// A table in the `FROM` clause of a statement, under the name the
+/// statement calls it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TableRef<'a> {
+    /// The table's alias, or its own name if it has none.
+    pub name: &'a str,
+    pub schema: &'a TableSchema,
+}
+
+impl<'a> TableRef<'a> {
+    /// Refers to `schema` by its own name.
+    pub fn new(schema: &'a TableSchema) -> Self {
+        Self { name: &schema.name, schema }
+    }
+}
+
+impl fmt::Display for TableRef<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.name == self.schema.name {
+            write!(f, "{}", self.name)
+        } else {
+            write!(f, "{} AS {}", self.schema.name, self.name)
+        }
+    }
+}
+
+/// The cheapest way found to read the rows of a table.
+///
+/// Displaying a plan describes it in the form used by `EXPLAIN QUERY
+/// PLAN`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Plan<'a> {
+    pub table: TableRef<'a>,
+    pub access: Access<'a>,
+    /// Estimated cost, in rows read.
+    pub cost: f64,
+    /// Estimated number of rows found.
+    pub rows: f64,
+}
+
+impl fmt::Display for Plan<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.access {
+            Access::FullScan => write!(f, "SCAN {}", self.table),
+            Access::RowidEq(_) => write!(f, "SEARCH {} USING INTEGER PRIMARY KEY (rowid=?)", self.table),
+            Access::Index { index, columns, equal, lower, upper, covering } => {
+                let verb = if equal.is_empty() && lower.is_none() && upper.is_none() { "SCAN" } else { "SEARCH" };
+                let covering = if *covering { "COVERING " } else { "" };
+                write!(f, "{verb} {} USING {covering}INDEX {}", self.table, index.name)?;
+                let name = |i: usize| &self.table.schema.columns[columns[i]].name;
+                let mut terms: Vec<String> = (0..equal.len()).map(|i| format!("{}=?", name(i))).collect();
+                for (bound, op) in [(lower, ">"), (upper, "<")] {
+                    if bound.is_some() {
+                        terms.push(format!("{}{op}?", name(equal.len())));
+                    }
+                }
+                if !terms.is_empty() {
+                    write!(f, " ({})", terms.join(" AND "))?;
+                }
+                Ok(())
+            },
+        }
+    }
+}
+
+/// Chooses the cheapest way to find the rows of the table at `position`
+/// in `tables` where each of `conditions` holds, using one of `indexes`
+/// if that is cheaper than reading the whole table. The tables before
+/// `position` are read in loops outside the one planned, so terms can
+/// look up values from their current rows. `used_columns` are the
+/// positions of the table's columns that the statement reads, which
+/// decide whether an index covers it.
+///
+/// Row counts come from the statistics gathered by `ANALYZE` where the
+/// table and its indexes have them, and from fixed guesses otherwise.
+///
+/// Every plan may find rows where the conditions do not hold, so they
+/// still have to be checked for each row found.
+pub fn plan<'a>(
+    tables: &[TableRef<'a>],
+    position: usize,
+    indexes: &[&'a IndexSchema],
+    conditions: &[&'a ast::Expr],
+    used_columns: &[usize],
+) -> Plan<'a> {
+    let mut terms = Vec::new();
+    for condition in conditions {
+        collect_conjuncts(condition, &mut terms);
+    }
+    let constraints: Vec<Constraint> = terms.into_iter().filter_map(|term| Constraint::from_term(tables, position, term)).collect();
+    let table = tables[position];
+    let table_rows = table.schema.row_count.map_or(DEFAULT_TABLE_ROWS, |rows| rows as f64).max(1.0);
+    let seek_cost = table_rows.log2();
+
+    let mut best = Plan { table, access: Access::FullScan, cost: table_rows, rows: table_rows };
+    let mut consider = |candidate: Plan<'a>| {
+        if candidate.cost < best.cost {
+            best = candidate;
+        }
+    };
+    if let Some(constraint) = constraints.iter().find(|constraint| constraint.column.is_none() && constraint.op == Op::Eq) {
+        consider(Plan { table, access: Access::RowidEq(constraint.value), cost: seek_cost, rows: 1.0 });
+    }
+    for index in indexes {
+        let columns = index.column_positions(table.schema);
+        let equal: Vec<&ast::Expr> = columns.iter()
+            .map_while(|position| constraints.iter().find(|constraint| constraint.is_on(*position, Op::Eq)).map(|constraint| constraint.value))
+            .collect();
+        let (lower, upper) = match columns.get(equal.len()) {
+            Some(position) => (
+                constraints.iter().find(|constraint| constraint.is_on(*position, Op::Lower)).map(Constraint::bound),
+                constraints.iter().find(|constraint| constraint.is_on(*position, Op::Upper)).map(Constraint::bound),
+            ),
+            None => (None, None),
+        };
+        let covering = used_columns.iter().all(|position| columns.contains(position) || table.schema.rowid_alias() == Some(*position));
+        let is_search = !equal.is_empty() || lower.is_some() || upper.is_some();
+        if !is_search && !covering {
+            continue;
+        }
+        let rows = match equal.len() {
+            0 => table_rows,
+            num_equal if index.unique && num_equal == columns.len() => 1.0,
+            num_equal => match index.rows_per_key.get(num_equal - 1) {
+                Some(rows) => *rows as f64,
+                None => DEFAULT_ROWS_PER_KEY / 2f64.powi(num_equal as i32 - 1),
+            },
+        };
+        let rows = rows.min(table_rows);
+        let num_bounds = usize::from(lower.is_some()) + usize::from(upper.is_some());
+        let rows = (rows * RANGE_SELECTIVITY.powi(num_bounds as i32)).max(1.0);
+        let row_cost = if covering { INDEX_ROW_COST } else { INDEX_ROW_COST + seek_cost };
+        let cost = if is_search { seek_cost } else { 0.0 } + rows * row_cost;
+        consider(Plan { table, access: Access::Index { index, columns, equal, lower, upper, covering }, cost, rows });
+    }
+    best
+}
+
+/// Returns each column that `name` could refer to among `tables`, or
+/// among the tables called `qualifier` if given: the position of its
+/// table in `tables`, and its position in the table or `None` for the
+/// row id. A name refers to a column only if exactly one is returned.
+pub fn find_column(tables: &[TableRef], qualifier: Option<&str>, name: &str) -> Vec<(usize, Option<usize>)> {
+    tables.iter()
+        .enumerate()
+        .filter(|(_, table)| qualifier.is_none_or(|qualifier| qualifier.eq_ignore_ascii_case(table.name)))
+        .filter_map(|(i, table)| {
+            let column = match table.schema.column_index(name) {
+                Some(position) if table.schema.rowid_alias() == Some(position) => None,
+                Some(position) => Some(position),
+                None if schema::ROWID_NAMES.iter().any(|rowid| rowid.eq_ignore_ascii_case(name)) => None,
+                None => return None,
+            };
+            Some((i, column))
+        })
+        .collect()
+}
+
+/// Returns the positions of the columns of the table at `position` in
+/// `tables`, other than the row id, that `exprs` refer to.
+pub fn used_columns<'e>(tables: &[TableRef], position: usize, exprs: impl IntoIterator<Item = &'e ast::Expr>) -> Vec<usize> {
+    fn collect(tables: &[TableRef], position: usize, expr: &ast::Expr, used: &mut Vec<usize>) {
+        match expr {
+            ast::Expr::Column { .. } => {
+                if let Some((table, Some(column))) = resolve_column(tables, expr) {
+                    if table == position && !used.contains(&column) {
+                        used.push(column);
+                    }
+                }
+            },
+            ast::Expr::Unary { operand, .. } => collect(tables, position, operand, used),
+            ast::Expr::Binary { left, right, .. } => {
+                collect(tables, position, left, used);
+                collect(tables, position, right, used);
+            },
+            ast::Expr::Function { args, .. } => args.iter().for_each(|arg| collect(tables, position, arg, used)),
+            ast::Expr::InList { expr, list, .. } => {
+                collect(tables, position, expr, used);
+                list.iter().for_each(|item| collect(tables, position, item, used));
+            },
+            ast::Expr::InSelect { expr, select, .. } => {
+                collect(tables, position, expr, used);
+                collect_select(tables, position, select, used);
+            },
+            ast::Expr::Exists(select) | ast::Expr::Subquery(select) => collect_select(tables, position, select, used),
+            ast::Expr::Literal(_) => {},
+        }
+    }
+    // Names in a subquery may refer to its own tables instead, which at
+    // worst counts columns that are not used.
+    fn collect_select(tables: &[TableRef], position: usize, select: &ast::Select, used: &mut Vec<usize>) {
+        select.exprs().for_each(|expr| collect(tables, position, expr, used));
+        for (_, select) in &select.compound {
+            collect_select(tables, position, select, used);
+        }
+        for cte in &select.with {
+            collect_select(tables, position, &cte.select, used);
+        }
+    }
+    let mut used = Vec::new();
+    for expr in exprs {
+        collect(tables, position, expr, &mut used);
+    }
+    used
+}
+
+/// How a term of a `WHERE` clause compares a column with a constant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Eq,
+    /// `>` or `>=`, bounding the column from below.
+    Lower,
+    /// `<` or `<=`, bounding the column from above.
+    Upper,
+}
+
+/// A term of a `WHERE` clause that compares a column, or the row id if
+/// `column` is `None`, with a value that is the same for every row.
+struct Constraint<'a> {
+    column: Option<usize>,
+    op: Op,
+    inclusive: bool,
+    value: &'a ast::Expr,
+}
+
+impl<'a> Constraint<'a> {
+    /// Returns the constraint `term` puts on a column of the table at
+    /// `position` in `tables`, if any.
+    fn from_term(tables: &[TableRef], position: usize, term: &'a ast::Expr) -> Option<Self> {
+        let ast::Expr::Binary { op, left, right } = term else {
+            return None;
+        };
+        // Operators as seen with the column on the left.
+        let (op, flipped, inclusive) = match op {
+            ast::BinaryOp::Eq => (Op::Eq, Op::Eq, true),
+            ast::BinaryOp::Gt => (Op::Lower, Op::Upper, false),
+            ast::BinaryOp::GtEq => (Op::Lower, Op::Upper, true),
+            ast::BinaryOp::Lt => (Op::Upper, Op::Lower, false),
+            ast::BinaryOp::LtEq => (Op::Upper, Op::Lower, true),
+            _ => return None,
+        };
+        for (column, value, op) in [(left, right, op), (right, left, flipped)] {
+            if let (Some((table, column)), true) = (resolve_column(tables, column), is_known(tables, position, value)) {
+                if table == position {
+                    return Some(Self { column, op, inclusive, value });
+                }
+            }
+        }
+        None
+    }
+
+    /// Returns whether the constraint compares the column at `position`
+    /// with `op`.
+    fn is_on(&self, position: usize, op: Op) -> bool {
+        self.column == Some(position) && self.op == op
+    }
+
+    fn bound(&self) -> Bound<'a> {
+        Bound { value: self.value, inclusive: self.inclusive }
+    }
+}
+
+/// Returns the column `expr` refers to among `tables`, as for
+/// [`find_column`], or `None` if `expr` is not a column or does not
+/// refer to exactly one.
+fn resolve_column(tables: &[TableRef], expr: &ast::Expr) -> Option<(usize, Option<usize>)> {
+    let ast::Expr::Column { table: qualifier, name } = expr else {
+        return None;
+    };
+    match find_column(tables, qualifier.as_deref(), name)[..] {
+        [column] => Some(column),
+        _ => None,
+    }
+}
+
+/// Adds the terms of `expr` joined by `AND` to `terms`.
+fn collect_conjuncts<'a>(expr: &'a ast::Expr, terms: &mut Vec<&'a ast::Expr>) {
+    match expr {
+        ast::Expr::Binary { op: ast::BinaryOp::And, left, right } => {
+            collect_conjuncts(left, terms);
+            collect_conjuncts(right, terms);
+        },
+        _ => terms.push(expr),
+    }
+}
+
+/// Returns whether `expr` has the same value for every row of the table
+/// at `position` in `tables`, because it only reads the tables before
+/// and those of the statements a subquery is in, so it can be computed
+/// before the loop over the table.
+fn is_known(tables: &[TableRef], position: usize, expr: &ast::Expr) -> bool {
+    match expr {
+        ast::Expr::Literal(_) => true,
+        ast::Expr::Column { table: qualifier, name } => match find_column(tables, qualifier.as_deref(), name)[..] {
+            [] => true,
+            [(table, _)] => table < position,
+            _ => false,
+        },
+        ast::Expr::Unary { operand, .. } => is_known(tables, position, operand),
+        ast::Expr::Binary { left, right, .. } => is_known(tables, position, left) && is_known(tables, position, right),
+        ast::Expr::InList { expr, list, .. } => {
+            is_known(tables, position, expr) && list.iter().all(|item| is_known(tables, position, item))
+        },
+        ast::Expr::Function { .. } | ast::Expr::InSelect { .. } | ast::Expr::Exists(_) | ast::Expr::Subquery(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+
+    fn users() -> TableSchema {
+        let Ok(ast::Statement::CreateTable(create)) = parser::parse("create table users (id integer primary key, name text, age integer, email text)") else {
+            panic!("expected create table");
+        };
+        TableSchema { name: create.name, root_page: 2, columns: create.columns, row_count: None }
+    }
+
+    fn index(name: &str, columns: &[&str], unique: bool) -> IndexSchema {
+        IndexSchema {
+            name: String::from(name),
+            table: String::from("users"),
+            root_page: 3,
+            columns: columns.iter().map(|column| String::from(*column)).collect(),
+            unique,
+            rows_per_key: Vec::new(),
+        }
+    }
+
+    fn condition(sql: &str) -> ast::Expr {
+        let Ok(ast::Statement::Select(select)) = parser::parse(&format!("select * from users where {sql}")) else {
+            panic!("expected select");
+        };
+        select.where_clause.unwrap()
+    }
+
+    fn describe(condition_sql: &str, indexes: &[&IndexSchema], used_columns: &[usize]) -> String {
+        let table = users();
+        let condition = condition(condition_sql);
+        plan(&[TableRef::new(&table)], 0, indexes, &[&condition], used_columns).to_string()
+    }
+
+    #[test]
+    fn cheapest_access_is_chosen() {
+        let by_name = index("by_name", &["name", "age"], false);
+        let by_email = index("by_email", &["email"], true);
+        let indexes = [&by_name, &by_email];
+        assert_eq!("SCAN users", describe("age > 3 or name = 'a'", &indexes, &[1, 2, 3]));
+        assert_eq!("SEARCH users USING INTEGER PRIMARY KEY (rowid=?)", describe("name = 'a' and 2 = id", &indexes, &[1]));
+        assert_eq!("SEARCH users USING INDEX by_name (name=? AND age>?)", describe("name = 'a' and age >= 18", &indexes, &[3]));
+        assert_eq!("SEARCH users USING INDEX by_email (email=?)", describe("name = 'a' and email = 'e'", &indexes, &[1, 3]));
+        assert_eq!(
+            "SEARCH users USING COVERING INDEX by_name (name=? AND age>? AND age<?)",
+            describe("users.name = 'a' and 10 < age and age < 20", &indexes, &[0, 1, 2])
+        );
+        assert_eq!("SCAN users USING COVERING INDEX by_name", describe("age + 1 = 3", &indexes, &[2]));
+        // Terms comparing two columns cannot be looked up.
+        assert_eq!("SCAN users", describe("name = email", &[&by_email], &[1, 3]));
+    }
+
+    #[test]
+    fn estimates_favour_more_selective_indexes() {
+        let table = users();
+        let by_name = index("by_name", &["name"], false);
+        let by_name_age = index("by_name_age", &["name", "age"], false);
+        let condition = condition("name = 'a' and age = 3");
+        let plan = plan(&[TableRef::new(&table)], 0, &[&by_name, &by_name_age], &[&condition], &[3]);
+        assert_eq!("SEARCH users USING INDEX by_name_age (name=? AND age=?)", plan.to_string());
+        assert!(plan.rows < DEFAULT_ROWS_PER_KEY && plan.cost < DEFAULT_TABLE_ROWS);
+    }
+
+    #[test]
+    fn statistics_replace_default_estimates() {
+        let mut table = users();
+        table.row_count = Some(1000);
+        let mut by_age = index("by_age", &["age"], false);
+        by_age.rows_per_key = vec![400];
+        let condition = condition("age = 30");
+        assert_eq!("SCAN users", plan(&[TableRef::new(&table)], 0, &[&by_age], &[&condition], &[1, 2]).to_string());
+        by_age.rows_per_key = vec![2];
+        let plan = plan(&[TableRef::new(&table)], 0, &[&by_age], &[&condition], &[1, 2]);
+        assert_eq!("SEARCH users USING INDEX by_age (age=?)", plan.to_string());
+        assert_eq!(2.0, plan.rows);
+    }
+
+    #[test]
+    fn inner_tables_look_up_values_from_outer_rows() {
+        let table = users();
+        let tables = [TableRef { name: "a", schema: &table }, TableRef { name: "b", schema: &table }];
+        let by_email = index("by_email", &["email"], true);
+        let condition = condition("b.email = a.name and a.age > b.age and b.rowid > 0");
+        let describe = |position| plan(&tables, position, &[&by_email], &[&condition], &[1, 2, 3]).to_string();
+        assert_eq!("SCAN users AS a", describe(0));
+        assert_eq!("SEARCH users AS b USING INDEX by_email (email=?)", describe(1));
+        assert_eq!(vec![(0, Some(1))], find_column(&tables, Some("A"), "name"));
+        assert_eq!(vec![(0, None), (1, None)], find_column(&tables, None, "id"));
+        assert!(find_column(&tables, Some("users"), "name").is_empty());
+    }
+
+    #[test]
+    fn subqueries_look_up_values_from_the_statements_around_them() {
+        let table = users();
+        let by_email = index("by_email", &["email"], true);
+        let correlated = condition("email = outer_row.name and age > (select max(age) from users)");
+        let tables = [TableRef::new(&table)];
+        assert_eq!(
+            "SEARCH users USING COVERING INDEX by_email (email=?)",
+            plan(&tables, 0, &[&by_email], &[&correlated], &[3]).to_string()
+        );
+        let exists = condition("exists (select * from users as o where o.email = name)");
+        assert_eq!(vec![1], used_columns(&tables, 0, [&exists]));
+    }
+}
@@ -0,0 +1,237 @@
+use std::{cell::RefCell, rc::Rc};
+use crate::{ast, cursor, pager, parser, table::{Row, TableError}, value::{Affinity, Value}};
+
+/// Page holding the root node of the schema catalog's B-tree.
+pub const SCHEMA_ROOT_PAGE: usize = 1;
+/// Name under which the schema catalog can be queried like a table.
+pub const SCHEMA_TABLE_NAME: &str = "sqlite_schema";
+/// Name of the table where `ANALYZE` stores statistics for the query
+/// planner. Each row holds, for the table `tbl`, either its row count
+/// if `idx` is NULL, or if `idx` names an index, the number of entries
+/// in the index followed by the average number of entries sharing each
+/// prefix of its columns, separated by spaces in `stat`.
+pub const STAT_TABLE_NAME: &str = "sqlite_stat1";
+/// Columns of the statistics table, in order.
+pub const STAT_COLUMNS: [&str; 3] = ["tbl", "idx", "stat"];
+/// Names that refer to the row id of any table without a column of the
+/// same name.
+pub const ROWID_NAMES: [&str; 3] = ["rowid", "oid", "_rowid_"];
+
+// Columns of the schema catalog:
+// | type | name | tbl_name | rootpage | sql |
+const SCHEMA_COLUMNS: [(&str, &str); 5] = [
+    ("type", "TEXT"),
+    ("name", "TEXT"),
+    ("tbl_name", "TEXT"),
+    ("rootpage", "INTEGER"),
+    ("sql", "TEXT"),
+];
+
+/// A table and the columns it was created with.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableSchema {
+    pub name: String,
+    pub root_page: usize,
+    pub columns: Vec<ast::ColumnDef>,
+    /// Number of rows counted by the last `ANALYZE`, if any.
+    pub row_count: Option<u64>,
+}
+
+impl TableSchema {
+    /// Returns the position of the column called `name`, ignoring case.
+    pub fn column_index(&self, name: &str) -> Option<usize> {
+        self.columns.iter().position(|column| column.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Returns the `CREATE TABLE` statement that creates the table, as
+    /// stored in the schema catalog.
+    pub fn sql(&self) -> String {
+        ast::CreateTable { name: self.name.clone(), if_not_exists: false, columns: self.columns.clone() }.to_string()
+    }
+
+    /// Returns the affinity of each column, in column order.
+    pub fn affinities(&self) -> Vec<Affinity> {
+        self.columns.iter().map(|column| Affinity::from_type_name(column.type_name.as_deref())).collect()
+    }
+
+    /// Returns the position of the `INTEGER PRIMARY KEY` column, whose
+    /// values are the row ids of the table rather than being stored in
+    /// its rows.
+    pub fn rowid_alias(&self) -> Option<usize> {
+        self.columns.iter().position(|column| {
+            column.primary_key
+                && column.type_name.as_deref().is_some_and(|type_name| type_name.eq_ignore_ascii_case("INTEGER"))
+        })
+    }
+}
+
+/// An index on some of the columns of a table, whose B-tree holds a
+/// record of the indexed values followed by the row id for each row.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexSchema {
+    pub name: String,
+    pub table: String,
+    pub root_page: usize,
+    pub columns: Vec<String>,
+    pub unique: bool,
+    /// Average number of entries sharing the values of the first `i + 1`
+    /// columns at position `i`, as counted by the last `ANALYZE`. Empty
+    /// if the index has not been analysed.
+    pub rows_per_key: Vec<u64>,
+}
+
+impl IndexSchema {
+    /// Returns the `CREATE INDEX` statement that creates the index, as
+    /// stored in the schema catalog.
+    pub fn sql(&self) -> String {
+        ast::CreateIndex {
+            name: self.name.clone(),
+            table: self.table.clone(),
+            columns: self.columns.clone(),
+            unique: self.unique,
+            if_not_exists: false,
+        }
+        .to_string()
+    }
+
+    /// Returns the position in `table` of each indexed column, in index
+    /// order.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `table` does not have one of the indexed columns.
+    pub fn column_positions(&self, table: &TableSchema) -> Vec<usize> {
+        self.columns.iter().map(|name| table.column_index(name).expect("indexed column exists")).collect()
+    }
+}
+
+/// The in-memory copy of the schema catalog, which records every table
+/// and index in the database along with the statement that created it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Schema {
+    tables: Vec<TableSchema>,
+    indexes: Vec<IndexSchema>,
+}
+
+impl Schema {
+    /// Reads the schema catalog stored in the database.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Err`] if the catalog cannot be read or holds a
+    /// statement that cannot be parsed.
+    pub fn load(pager: &Rc<RefCell<pager::Pager>>) -> Result<Self, TableError> {
+        let mut tables = vec![catalog_schema()];
+        let mut indexes = Vec::new();
+        let mut cursor = cursor::Cursor::table_start(Rc::clone(pager), SCHEMA_ROOT_PAGE)?;
+        while !cursor.end_of_table() {
+            let row = Row::deserialise(&cursor.value()?)?;
+            let (Some(Value::Integer(root_page)), Some(Value::Text(sql))) = (row.values.get(3), row.values.get(4)) else {
+                return Err(TableError::CorruptSchema(String::from("missing root page or sql")));
+            };
+            let root_page = usize::try_from(*root_page)
+                .map_err(|_| TableError::CorruptSchema(format!("invalid root page {root_page}")))?;
+            match parser::parse(sql) {
+                Ok(ast::Statement::CreateTable(create)) => {
+                    tables.push(TableSchema { name: create.name, root_page, columns: create.columns, row_count: None });
+                },
+                Ok(ast::Statement::CreateIndex(create)) => {
+                    let table = tables.iter().find(|table| table.name.eq_ignore_ascii_case(&create.table));
+                    if !table.is_some_and(|table| create.columns.iter().all(|column| table.column_index(column).is_some())) {
+                        return Err(TableError::CorruptSchema(format!("index {} on missing table or column", create.name)));
+                    }
+                    indexes.push(IndexSchema {
+                        name: create.name,
+                        table: create.table,
+                        root_page,
+                        columns: create.columns,
+                        unique: create.unique,
+                        rows_per_key: Vec::new(),
+                    });
+                },
+                _ => return Err(TableError::CorruptSchema(format!("invalid statement {sql}"))),
+            }
+            cursor.advance()?;
+        }
+        let mut schema = Self { tables, indexes };
+        schema.load_stats(pager)?;
+        Ok(schema)
+    }
+
+    /// Reads the statistics stored by `ANALYZE`, if it has been run,
+    /// into the tables and indexes they describe. Rows that describe no
+    /// table or index, or whose numbers cannot be read, are ignored.
+    fn load_stats(&mut self, pager: &Rc<RefCell<pager::Pager>>) -> Result<(), TableError> {
+        let Some(root_page) = self.table(STAT_TABLE_NAME).map(|table| table.root_page) else {
+            return Ok(());
+        };
+        let mut cursor = cursor::Cursor::table_start(Rc::clone(pager), root_page)?;
+        while !cursor.end_of_table() {
+            let row = Row::deserialise(&cursor.value()?)?;
+            cursor.advance()?;
+            let (Some(Value::Text(table)), Some(Value::Text(stat))) = (row.values.first(), row.values.get(2)) else {
+                continue;
+            };
+            let Ok(numbers) = stat.split_whitespace().map(str::parse).collect::<Result<Vec<u64>, _>>() else {
+                continue;
+            };
+            let Some(&row_count) = numbers.first() else {
+                continue;
+            };
+            if let Some(table) = self.tables.iter_mut().find(|other| other.name.eq_ignore_ascii_case(table)) {
+                table.row_count = Some(row_count);
+            }
+            if let Some(Value::Text(name)) = row.values.get(1) {
+                if let Some(index) = self.indexes.iter_mut().find(|index| index.name.eq_ignore_ascii_case(name)) {
+                    index.rows_per_key = numbers[1..].iter().take(index.columns.len()).copied().collect();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the table called `name`, ignoring case.
+    pub fn table(&self, name: &str) -> Option<&TableSchema> {
+        self.tables.iter().find(|table| table.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Returns every table created by the user, in the order they were
+    /// created.
+    pub fn tables(&self) -> &[TableSchema] {
+        &self.tables[1..]
+    }
+
+    /// Returns the index called `name`, ignoring case.
+    pub fn index(&self, name: &str) -> Option<&IndexSchema> {
+        self.indexes.iter().find(|index| index.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Returns every index, in the order they were created.
+    pub fn indexes(&self) -> &[IndexSchema] {
+        &self.indexes
+    }
+
+    /// Returns the indexes on the table called `table`, ignoring case.
+    pub fn indexes_of(&self, table: &str) -> Vec<&IndexSchema> {
+        self.indexes.iter().filter(|index| index.table.eq_ignore_ascii_case(table)).collect()
+    }
+}
+
+/// Describes the schema catalog itself, so it can be read with
+/// `SELECT` like any other table.
+fn catalog_schema() -> TableSchema {
+    TableSchema {
+        name: String::from(SCHEMA_TABLE_NAME),
+        root_page: SCHEMA_ROOT_PAGE,
+        columns: SCHEMA_COLUMNS
+            .iter()
+            .map(|(name, type_name)| ast::ColumnDef {
+                name: String::from(*name),
+                type_name: Some(String::from(*type_name)),
+                primary_key: false,
+                not_null: false,
+            })
+            .collect(),
+        row_count: None,
+    }
+}
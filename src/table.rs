@@ -1,171 +1,241 @@
-use std::{mem, panic, str};
-use crate::page;
-
-pub struct Table {
-    buffer: Vec<page::Page>,
-    page_size: usize,
-    num_rows: usize,
-    row_size: Option<usize>,
-    max_string_len: Option<usize>,
+use std::{fmt, str};
+use crate::{btree, pager, value::Value};
+
+// Serial types recorded in a row's header for each of its values. Types
+// from 12 upwards give the length of a blob (even) or text (odd).
+const NULL_TYPE: u64 = 0;
+/// Serial types 1 to 6 are integers of these many bytes.
+const INTEGER_SIZES: [usize; 6] = [1, 2, 3, 4, 6, 8];
+const REAL_TYPE: u64 = 7;
+const ZERO_TYPE: u64 = 8;
+const ONE_TYPE: u64 = 9;
+const BLOB_TYPE_BASE: u64 = 12;
+const TEXT_TYPE_BASE: u64 = 13;
+/// Largest number of bytes in a varint.
+const MAX_VARINT_LEN: usize = 9;
+
+#[derive(Debug)]
+pub enum TableError {
+    BTreeError(btree::BTreeError),
+    SerialiseError(SerialiseError),
+    /// The schema catalog holds an entry that cannot be understood.
+    CorruptSchema(String),
 }
 
-impl Table {
-    /// Constructs a new empty `Table` with specified `page_size` and 
-    /// returns it, or `None` if `page_size` is 0. 
-    pub fn build(page_size: usize) -> Option<Self> {
-        if page_size == 0 {
-            return None;
-        } else {
-            return Some(
-                Self { 
-                    buffer: Vec::new(), 
-                    page_size, 
-                    num_rows: 0, 
-                    row_size: None, 
-                    max_string_len: None 
-                }
-            );
+impl fmt::Display for TableError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TableError::BTreeError(e) => write!(f, "{e}"),
+            TableError::SerialiseError(e) => write!(f, "{e}"),
+            TableError::CorruptSchema(reason) => write!(f, "malformed database schema ({reason})"),
         }
     }
+}
 
-    pub fn len(&self) -> usize {
-        self.num_rows
-    }
-
-    pub fn push(&mut self, contents: &[u8]) {
-        match self.row_size {
-            Some(size) => if contents.len() != size { 
-                panic!(
-                    "Error: input size {} does not match table row size {}.",
-                    contents.len(), 
-                    size
-                )
-            },
-            None => {
-                if contents.len() > self.page_size { panic!(
-                    "Error: row size greater than page size.") }
-                self.row_size = Some(contents.len());
-            },
-        }
-        let row_num = self.num_rows + 1;
-        // Can unwrap here since self.row_size will never be None at
-        // this point.
-        let row_size = self.row_size.unwrap();
-        let rows_per_page: usize = self.page_size / row_size;
-        let page_num: usize = row_num / rows_per_page;
-        if page_num >= self.buffer.len() {
-            self.buffer.push(
-                // Can unwrap here since self.page_size cannot be 0
-                // which is the main cause for error.
-                unsafe { page::Page::alloc_zeroed(self.page_size).unwrap() }
-            );
-        }
-        // Page_num should always exist since it would have been 
-        // allocated above if it didn't.
-        let page = self.buffer.get_mut(page_num).unwrap();
-        let write_point: usize = (self.num_rows - rows_per_page * page_num) * row_size;
-        page.copy_from_slice(write_point, contents);
-        self.num_rows = row_num;
-    }
-
-    /// Returns a reference to `Row` number `row_id` if it exists, 
-    /// or `None` if it doesn't.
-    pub fn get(&self, row_id: usize, max_string_len: usize) -> Option<Row> {
-        if row_id >= self.num_rows {
-            return None;
-        }
-        let rows_per_page = self.page_size/ self.row_size?;
-        let page_num = row_id / rows_per_page;
-        let read_point = (row_id - rows_per_page * page_num) * self.row_size?;
-        let row_buffer = self.buffer
-            .get(page_num)
-            .unwrap()
-            .read_from_index(read_point, self.row_size?)?;
-        Row::deserialise(row_buffer, max_string_len).ok()
+impl From<btree::BTreeError> for TableError {
+    fn from(e: btree::BTreeError) -> Self {
+        Self::BTreeError(e)
+    }
+}
+
+impl From<pager::PagerError> for TableError {
+    fn from(e: pager::PagerError) -> Self {
+        Self::BTreeError(btree::BTreeError::PagerError(e))
+    }
+}
+
+impl From<SerialiseError> for TableError {
+    fn from(e: SerialiseError) -> Self {
+        Self::SerialiseError(e)
     }
 }
 
 #[derive(Debug)]
 pub enum SerialiseError {
-    NoContents,
-    StringWriteError,
     StringReadError,
-    BufferLenError
+    BufferLenError,
+    /// A row's header holds a serial type that does not exist.
+    InvalidSerialType(u64),
+}
+
+impl fmt::Display for SerialiseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SerialiseError::StringReadError => write!(f, "unable to read string"),
+            SerialiseError::BufferLenError => write!(f, "row has the wrong size"),
+            SerialiseError::InvalidSerialType(serial_type) => write!(f, "invalid serial type {serial_type}"),
+        }
+    }
 }
 
-/// Temporary container for simple tabel rows.
-#[derive(Clone)]
+/// The values of a single table row, in column order. The row's id is
+/// the key it is stored under rather than part of the row.
+#[derive(Debug, Clone, PartialEq, Default)]
 pub struct Row {
-    pub id: Option<usize>,
-    pub username: String,
-    pub email: String,
-    max_string_len: usize,
+    pub values: Vec<Value>,
 }
 
 impl Row {
-
-    /// Construct a `Row` with maximum length of internal strings given 
-    /// by `max_string_len`.
-    pub fn with_max_str_len(max_string_len: usize) -> Self {
-        Self { 
-            id: None, 
-            username: String::new(), 
-            email: String::new(), 
-            max_string_len 
+    /// Serialises the row in SQLite's record format: a header holding
+    /// its own length and the serial type of each value as varints,
+    /// followed by the values themselves, each taking only as many bytes
+    /// as its serial type calls for.
+    pub fn serialise(&self) -> Box<[u8]> {
+        let mut types = Vec::new();
+        let mut body = Vec::new();
+        for value in &self.values {
+            let serial_type = match value {
+                Value::Null => NULL_TYPE,
+                Value::Integer(0) => ZERO_TYPE,
+                Value::Integer(1) => ONE_TYPE,
+                Value::Integer(value) => {
+                    // Can unwrap here since 8 bytes hold any i64.
+                    let index = INTEGER_SIZES.iter()
+                        .position(|size| fits_in_bytes(*value, *size))
+                        .unwrap();
+                    let size = INTEGER_SIZES[index];
+                    body.extend_from_slice(&value.to_be_bytes()[8 - size..]);
+                    index as u64 + 1
+                },
+                Value::Real(value) => {
+                    body.extend_from_slice(&value.to_be_bytes());
+                    REAL_TYPE
+                },
+                Value::Text(value) => {
+                    body.extend_from_slice(value.as_bytes());
+                    TEXT_TYPE_BASE + value.len() as u64 * 2
+                },
+                Value::Blob(value) => {
+                    body.extend_from_slice(value);
+                    BLOB_TYPE_BASE + value.len() as u64 * 2
+                },
+            };
+            write_varint(&mut types, serial_type);
         }
+        // The header length counts itself, which may make it long
+        // enough to need another byte.
+        let mut prefix_len = 1;
+        while varint_len((types.len() + prefix_len) as u64) > prefix_len {
+            prefix_len += 1;
+        }
+        let header_len = types.len() + prefix_len;
+        let mut buffer = Vec::with_capacity(header_len + body.len());
+        write_varint(&mut buffer, header_len as u64);
+        buffer.extend_from_slice(&types);
+        buffer.extend_from_slice(&body);
+        buffer.into_boxed_slice()
     }
 
-    /// Serialises contents and returns buffer, or `None` if `self.id` was 
-    /// never set to `Some(value)`.
-    pub fn serialise(&self) -> Result<Box<[u8]>, SerialiseError> {
-        let id = self.id.map_or_else(|| Err(SerialiseError::NoContents), |x| Ok(x))?;
-        let buffer_len = self.max_string_len * 2 + mem::size_of::<usize>();
-        // Buffer must be zeroed since this is used to determine the  
-        // length of each string during deserialisation.
-        let mut buffer = vec![0u8; buffer_len].into_boxed_slice();
-        let username = self.username.as_bytes();
-        let email = self.email.as_bytes();
-        panic::catch_unwind(panic::AssertUnwindSafe(|| {
-            buffer[0..username.len()]
-                .copy_from_slice(self.username.as_bytes());
-            buffer[self.max_string_len..(self.max_string_len + email.len())]
-                .copy_from_slice(self.email.as_bytes());
-            buffer[self.max_string_len*2..(self.max_string_len * 2 + mem::size_of::<usize>())]
-                .copy_from_slice(&id.to_le_bytes());
-        })).map_err(|_| SerialiseError::StringWriteError)?;
-        Ok(buffer)
-    }
-
-    pub fn deserialise(serial: Box<[u8]>, max_string_len: usize) -> Result<Self, SerialiseError> {
-        if serial.len() != max_string_len * 2 + mem::size_of::<usize>() {
+    pub fn deserialise(serial: &[u8]) -> Result<Self, SerialiseError> {
+        if serial.is_empty() {
+            return Ok(Self::default());
+        }
+        let (header_len, mut loc) = read_varint(serial).ok_or(SerialiseError::BufferLenError)?;
+        let header = usize::try_from(header_len).ok()
+            .and_then(|header_len| serial.get(..header_len))
+            .ok_or(SerialiseError::BufferLenError)?;
+        let mut body = header.len();
+        let mut values = Vec::new();
+        while loc < header.len() {
+            let (serial_type, len) = read_varint(&header[loc..]).ok_or(SerialiseError::BufferLenError)?;
+            loc += len;
+            let value = match serial_type {
+                NULL_TYPE => Value::Null,
+                1..=6 => {
+                    let size = INTEGER_SIZES[serial_type as usize - 1];
+                    let bytes = take(serial, &mut body, size)?;
+                    // Sign extend from the top byte.
+                    let fill = if bytes[0] & 0x80 != 0 { 0xff } else { 0 };
+                    let mut buffer = [fill; 8];
+                    buffer[8 - size..].copy_from_slice(bytes);
+                    Value::Integer(i64::from_be_bytes(buffer))
+                },
+                REAL_TYPE => match f64::from_be_bytes(take(serial, &mut body, 8)?.try_into().unwrap()) {
+                    // As in SQLite, a stored NaN reads as NULL.
+                    value if value.is_nan() => Value::Null,
+                    value => Value::Real(value),
+                },
+                ZERO_TYPE => Value::Integer(0),
+                ONE_TYPE => Value::Integer(1),
+                serial_type if serial_type >= BLOB_TYPE_BASE => {
+                    let len = usize::try_from((serial_type - BLOB_TYPE_BASE) / 2)
+                        .map_err(|_| SerialiseError::BufferLenError)?;
+                    let bytes = take(serial, &mut body, len)?;
+                    if serial_type % 2 == TEXT_TYPE_BASE % 2 {
+                        let text = str::from_utf8(bytes).map_err(|_| SerialiseError::StringReadError)?;
+                        Value::Text(String::from(text))
+                    } else {
+                        Value::Blob(bytes.to_vec())
+                    }
+                },
+                serial_type => return Err(SerialiseError::InvalidSerialType(serial_type)),
+            };
+            values.push(value);
+        }
+        if body != serial.len() {
             return Err(SerialiseError::BufferLenError);
         }
-        fn extract_string(buffer: &[u8]) -> Result<String, SerialiseError> {
-            let string_len = find_first_zero(buffer.iter())
-                .map_or_else(|| Err(SerialiseError::StringReadError), |x| Ok(x))?;
-            let string = String::from(
-                str::from_utf8(&buffer[0..string_len])
-                    .map_err(|_| SerialiseError::StringReadError)?
-            );
-            Ok(string)
+        Ok(Self { values })
+    }
+}
+
+/// Returns whether `value` can be stored in `size` bytes as a two's
+/// complement integer.
+fn fits_in_bytes(value: i64, size: usize) -> bool {
+    let bits = size as u32 * 8;
+    bits >= 64 || (-(1i64 << (bits - 1))..(1i64 << (bits - 1))).contains(&value)
+}
+
+/// Returns the `len` bytes of `serial` starting at `loc` and moves `loc`
+/// past them.
+fn take<'a>(serial: &'a [u8], loc: &mut usize, len: usize) -> Result<&'a [u8], SerialiseError> {
+    let bytes = serial.get(*loc..loc.saturating_add(len)).ok_or(SerialiseError::BufferLenError)?;
+    *loc += len;
+    Ok(bytes)
+}
+
+/// Appends `value` to `buffer` as a varint: seven bits per byte, most
+/// significant first, with the top bit set on every byte but the last,
+/// except that a ninth byte holds a full eight bits.
+fn write_varint(buffer: &mut Vec<u8>, value: u64) {
+    if value >> 56 != 0 {
+        // Nine bytes: eight of seven bits then one of eight bits.
+        for i in (0..8).rev() {
+            buffer.push(((value >> (8 + i * 7)) & 0x7f) as u8 | 0x80);
+        }
+        buffer.push(value as u8);
+        return;
+    }
+    let len = varint_len(value);
+    for i in (0..len).rev() {
+        let continuation = if i == 0 { 0 } else { 0x80 };
+        buffer.push(((value >> (i * 7)) & 0x7f) as u8 | continuation);
+    }
+}
+
+/// Reads a varint from the start of `bytes`, returning its value and
+/// length, or `None` if `bytes` ends before the varint does.
+fn read_varint(bytes: &[u8]) -> Option<(u64, usize)> {
+    let mut value = 0u64;
+    for (i, byte) in bytes.iter().take(MAX_VARINT_LEN).enumerate() {
+        if i == MAX_VARINT_LEN - 1 {
+            return Some(((value << 8) | *byte as u64, MAX_VARINT_LEN));
+        }
+        value = (value << 7) | (byte & 0x7f) as u64;
+        if byte & 0x80 == 0 {
+            return Some((value, i + 1));
         }
-        let username = extract_string(&serial[0..max_string_len])?;
-        let email = extract_string(&serial[max_string_len..(max_string_len*2)])?;
-        let id = usize::from_le_bytes(serial[(max_string_len*2)..].try_into().unwrap());
-        Ok(Self { id: Some(id), username, email, max_string_len })
     }
+    None
 }
 
-/// Finds the location of the first zero byte and returns it, or `None` if 
-/// all bytes are none zero.
-fn find_first_zero<'a, T: Iterator<Item = &'a u8>>(x: T) -> Option<usize> {
-    let mut counter: usize = 0;
-    x.into_iter().find(|x| {
-        counter += 1;
-        **x == 0
-    })?;
-    counter -= 1;
-    Some(counter)
+/// Number of bytes [`write_varint`] uses for `value`.
+fn varint_len(value: u64) -> usize {
+    if value >> 56 != 0 {
+        return MAX_VARINT_LEN;
+    }
+    let bits = 64 - value.leading_zeros() as usize;
+    bits.div_ceil(7).max(1)
 }
 
 #[cfg(test)]
@@ -173,52 +243,61 @@ mod tests {
     use super::*;
 
     #[test]
-    fn find_first_zero_returns_correct_index() {
-        let first_zero_id = (0..90).step_by(10);
-        for id_expected in first_zero_id {
-            let mut array = [1u8; 100];
-            array[id_expected] = 0;
-            let id = find_first_zero(array.iter()).unwrap();
-            assert_eq!(id_expected, id);
-        }
+    fn assert_deserialised_serialised_row_is_unchanged() {
+        let row = Row {
+            values: vec![
+                Value::Text(String::from("hello world")),
+                Value::Null,
+                Value::Integer(-7),
+                Value::Real(2.5),
+                Value::Blob(vec![0, 255]),
+                Value::Text(String::new()),
+            ],
+        };
+        assert_eq!(row, Row::deserialise(&row.serialise()).unwrap());
+        assert_eq!(Row::default(), Row::deserialise(&Row::default().serialise()).unwrap());
+        let row = Row { values: vec![Value::Real(f64::NAN)] };
+        assert_eq!(Row { values: vec![Value::Null] }, Row::deserialise(&row.serialise()).unwrap());
     }
-    
+
     #[test]
-    fn find_first_zero_returns_none_when_no_zeros() {
-        let zero_id = find_first_zero([1;20].iter());
-        assert!(matches!(zero_id, None));
+    fn truncated_row_returns_error() {
+        let row = Row { values: vec![Value::Text(String::from("hello"))] };
+        let serial = row.serialise();
+        assert!(matches!(
+            Row::deserialise(&serial[..serial.len() - 1]),
+            Err(SerialiseError::BufferLenError)
+        ));
+        assert!(matches!(Row::deserialise(&[2, 10]), Err(SerialiseError::InvalidSerialType(10))));
     }
 
     #[test]
-    fn assert_deserialised_serialised_row_is_unchanged() {
-        let max_string_len = 100;
-        let mut row = Row::with_max_str_len(max_string_len);
-        row.id = Some(0);
-        row.username = String::from("hello world");
-        row.email = String::from("helloworld@something.fun");
-        let row_serialised = row.serialise().unwrap();
-        let row_deserialised = Row::deserialise(
-            row_serialised, 
-            max_string_len
-        ).unwrap();
-        assert_eq!(row.id, row_deserialised.id);
-        assert_eq!(row.username, row_deserialised.username);
-        assert_eq!(row.email, row_deserialised.email);
+    fn integers_take_the_fewest_bytes_that_hold_them() {
+        let sizes = [(0, 0), (1, 0), (-1, 1), (127, 1), (-129, 2), (1 << 23, 4), (-(1 << 40), 6), (i64::MIN, 8)];
+        for (value, size) in sizes {
+            let row = Row { values: vec![Value::Integer(value)] };
+            let serial = row.serialise();
+            assert_eq!(2 + size, serial.len(), "size of {value}");
+            assert_eq!(row, Row::deserialise(&serial).unwrap());
+        }
+        let row = Row { values: vec![Value::Integer(3), Value::Text(String::from("bob"))] };
+        assert_eq!(7, row.serialise().len());
     }
 
     #[test]
-    fn assert_data_written_and_read_from_table_is_correct() {
-        let max_string_len = 100;
-        let page_size = 1024;
-        let mut table = Table::build(page_size).unwrap();
-        let mut row = Row::with_max_str_len(max_string_len);
-        row.id = Some(0);
-        row.username = String::from("hello world");
-        row.email = String::from("helloworld@funmail.com");
-        table.push(&row.serialise().unwrap());
-        let row_output = table.get(0, max_string_len).unwrap();
-        assert_eq!(row.id, row_output.id);
-        assert_eq!(row.username, row_output.username);
-        assert_eq!(row.email, row_output.email);
-    }
-}
\ No newline at end of file
+    fn varints_roundtrip_at_length_boundaries() {
+        for value in [0, 127, 128, 16383, 16384, (1 << 56) - 1, 1 << 56, u64::MAX] {
+            let mut buffer = Vec::new();
+            write_varint(&mut buffer, value);
+            assert_eq!(varint_len(value), buffer.len());
+            assert_eq!(Some((value, buffer.len())), read_varint(&buffer));
+        }
+        assert_eq!(None, read_varint(&[0x80, 0x80]));
+    }
+
+    #[test]
+    fn long_headers_count_their_own_length() {
+        let row = Row { values: vec![Value::Null; 200] };
+        assert_eq!(row, Row::deserialise(&row.serialise()).unwrap());
+    }
+}
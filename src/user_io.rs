@@ -24,26 +24,31 @@ impl InputBuffer {
 }
 
 pub fn configure_env(mut stream: impl Write) -> io::Result<()> {
-    stream.write(format!("SQLite rust clone version {}\n", 
+    stream.write_all(format!("SQLite rust clone version {}\n", 
         env!("CARGO_PKG_VERSION")).as_bytes())?;
-    stream.write(b"Enter \".help\" for instructions\n")?;
+    stream.write_all(b"Enter \".help\" for instructions\n")?;
     stream.flush()?;
     Ok(())
 }
 
-pub fn prompt_user_input(input_stream: impl BufRead, mut output_stream: impl Write, input_buffer: &mut InputBuffer) -> io::Result<()>{
+/// Prompts for a line of input and reads it into `input_buffer`, 
+/// returning the number of bytes read. Zero bytes are read once the 
+/// input stream is exhausted.
+pub fn prompt_user_input(input_stream: impl BufRead, mut output_stream: impl Write, input_buffer: &mut InputBuffer) -> io::Result<usize>{
     input_buffer.buffer().clear();
-    output_stream.write(b"db> ")?;
+    output_stream.write_all(b"db> ")?;
     output_stream.flush()?;
-    input_stream
-        .take(input_buffer.buffer_length().clone() as u64)
+    let bytes_read = input_stream
+        .take(*input_buffer.buffer_length() as u64)
         .read_line(input_buffer.buffer())?;
-    input_buffer.buffer().pop();
-    Ok(())
+    if input_buffer.buffer().ends_with('\n') {
+        input_buffer.buffer().pop();
+    }
+    Ok(bytes_read)
 }
 
 pub fn display_bad_statement_message(mut steam: impl Write, statement: String) -> io::Result<()>{
-    steam.write(statement.as_bytes())?;
+    steam.write_all(statement.as_bytes())?;
     Ok(())
 
 }
\ No newline at end of file
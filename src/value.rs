@@ -0,0 +1,387 @@
+use std::{cmp::Ordering, fmt};
+
+/// A single SQL value, as held in a virtual machine register.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Integer(i64),
+    Real(f64),
+    Text(String),
+    Blob(Vec<u8>),
+}
+
+impl Value {
+    /// Converts the value to the type preferred by `affinity`, leaving
+    /// it unchanged where the conversion would lose information.
+    pub fn apply_affinity(self, affinity: Affinity) -> Self {
+        match (affinity, self) {
+            (Affinity::Text, value @ (Value::Integer(_) | Value::Real(_))) => Value::Text(value.to_string()),
+            (Affinity::Numeric | Affinity::Integer, Value::Text(text)) => match parse_numeric(&text) {
+                Some(value) => value.apply_affinity(affinity),
+                None => Value::Text(text),
+            },
+            (Affinity::Numeric | Affinity::Integer, Value::Real(value)) => match real_to_integer(value) {
+                Some(value) => Value::Integer(value),
+                None => Value::Real(value),
+            },
+            (Affinity::Real, Value::Integer(value)) => Value::Real(value as f64),
+            (Affinity::Real, Value::Text(text)) => match parse_numeric(&text) {
+                Some(value) => value.apply_affinity(affinity),
+                None => Value::Text(text),
+            },
+            (_, value) => value,
+        }
+    }
+}
+
+impl Value {
+    /// Compares two values in SQLite's sort order, where numbers come
+    /// before text and text before blobs, or returns `None` if either
+    /// is NULL.
+    pub fn compare(&self, other: &Value) -> Option<Ordering> {
+        fn rank(value: &Value) -> u8 {
+            match value {
+                Value::Null => 0,
+                Value::Integer(_) | Value::Real(_) => 1,
+                Value::Text(_) => 2,
+                Value::Blob(_) => 3,
+            }
+        }
+        let ordering = match (self, other) {
+            (Value::Null, _) | (_, Value::Null) => return None,
+            (Value::Integer(a), Value::Integer(b)) => a.cmp(b),
+            (Value::Integer(a), Value::Real(b)) => compare_integer_to_real(*a, *b),
+            (Value::Real(a), Value::Integer(b)) => compare_integer_to_real(*b, *a).reverse(),
+            // Can unwrap here since arithmetic gives NULL, never NaN.
+            (Value::Real(a), Value::Real(b)) => a.partial_cmp(b).unwrap(),
+            (Value::Text(a), Value::Text(b)) => a.cmp(b),
+            (Value::Blob(a), Value::Blob(b)) => a.cmp(b),
+            (a, b) => rank(a).cmp(&rank(b)),
+        };
+        Some(ordering)
+    }
+
+    /// Compares two values in the order `ORDER BY` sorts them, which is
+    /// that of [`compare`] with NULL before every other value.
+    ///
+    /// [`compare`]: Self::compare
+    pub fn sort_cmp(&self, other: &Value) -> Ordering {
+        match (self, other) {
+            (Value::Null, Value::Null) => Ordering::Equal,
+            (Value::Null, _) => Ordering::Less,
+            (_, Value::Null) => Ordering::Greater,
+            // Can unwrap here since neither value is NULL.
+            (a, b) => a.compare(b).unwrap(),
+        }
+    }
+
+    /// Returns whether the value counts as true in a condition, or
+    /// `None` if it is NULL. Text and blobs are true if they start with
+    /// a non-zero number.
+    pub fn is_true(&self) -> Option<bool> {
+        match self {
+            Value::Null => None,
+            Value::Integer(value) => Some(*value != 0),
+            Value::Real(value) => Some(*value != 0.0),
+            Value::Text(_) | Value::Blob(_) => self.to_numeric().is_true(),
+        }
+    }
+
+    /// Returns the value as an integer or floating point number, taking
+    /// the number at the start of text and blobs, or 0 if there is none.
+    /// NULL stays NULL.
+    pub fn to_numeric(&self) -> Value {
+        match self {
+            Value::Text(text) => numeric_prefix(text),
+            Value::Blob(blob) => numeric_prefix(&String::from_utf8_lossy(blob)),
+            value => value.clone(),
+        }
+    }
+
+    /// Applies the arithmetic operator `op` to the two values, giving an
+    /// integer if both are integers and the result does not overflow, a
+    /// floating point number otherwise, and NULL if either is NULL, the
+    /// operator divides by zero, or the result is not a number.
+    pub fn arithmetic(&self, op: Arithmetic, other: &Value) -> Value {
+        let (a, b) = (self.to_numeric(), other.to_numeric());
+        if let (Value::Integer(a), Value::Integer(b)) = (&a, &b) {
+            let result = match op {
+                Arithmetic::Add => a.checked_add(*b),
+                Arithmetic::Subtract => a.checked_sub(*b),
+                Arithmetic::Multiply => a.checked_mul(*b),
+                Arithmetic::Divide | Arithmetic::Remainder if *b == 0 => return Value::Null,
+                Arithmetic::Divide => a.checked_div(*b),
+                Arithmetic::Remainder => Some(a.checked_rem(*b).unwrap_or(0)),
+            };
+            if let Some(result) = result {
+                return Value::Integer(result);
+            }
+        }
+        let (a, b) = match (a, b) {
+            (Value::Null, _) | (_, Value::Null) => return Value::Null,
+            (a, b) => (as_real(&a), as_real(&b)),
+        };
+        let result = match op {
+            Arithmetic::Add => a + b,
+            Arithmetic::Subtract => a - b,
+            Arithmetic::Multiply => a * b,
+            Arithmetic::Divide if b == 0.0 => return Value::Null,
+            Arithmetic::Divide => a / b,
+            // The remainder is taken of the integer parts.
+            Arithmetic::Remainder => match (a as i64, b as i64) {
+                (_, 0) => return Value::Null,
+                (a, b) => a.checked_rem(b).unwrap_or(0) as f64,
+            },
+        };
+        // As in SQLite, such as infinity less infinity.
+        if result.is_nan() { Value::Null } else { Value::Real(result) }
+    }
+
+    /// Joins the text of two values, or returns NULL if either is NULL.
+    pub fn concat(&self, other: &Value) -> Value {
+        let text = |value: &Value| match value {
+            Value::Blob(blob) => String::from_utf8_lossy(blob).into_owned(),
+            value => value.to_string(),
+        };
+        match (self, other) {
+            (Value::Null, _) | (_, Value::Null) => Value::Null,
+            (a, b) => Value::Text(text(a) + &text(b)),
+        }
+    }
+}
+
+/// The arithmetic operators of [`Value::arithmetic`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arithmetic {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Value::Integer(value as i64)
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Null => write!(f, "NULL"),
+            Value::Integer(value) => write!(f, "{value}"),
+            // Debug formatting keeps the decimal point on whole numbers.
+            Value::Real(value) => write!(f, "{value:?}"),
+            Value::Text(value) => write!(f, "{value}"),
+            Value::Blob(value) => {
+                write!(f, "x'")?;
+                for byte in value {
+                    write!(f, "{byte:02x}")?;
+                }
+                write!(f, "'")
+            },
+        }
+    }
+}
+
+/// The type a column prefers its values to be stored as, which is
+/// worked out from the column's declared type using SQLite's rules.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Affinity {
+    /// Values are stored as given.
+    Blob,
+    Text,
+    /// Text that looks like a number is stored as a number, preferring
+    /// integers.
+    Numeric,
+    Integer,
+    /// Numbers are stored as floating point.
+    Real,
+}
+
+impl Affinity {
+    /// Returns the affinity of a column declared with type `type_name`.
+    pub fn from_type_name(type_name: Option<&str>) -> Self {
+        let Some(type_name) = type_name else {
+            return Affinity::Blob;
+        };
+        let type_name = type_name.to_ascii_uppercase();
+        if type_name.contains("INT") {
+            Affinity::Integer
+        } else if ["CHAR", "CLOB", "TEXT"].iter().any(|word| type_name.contains(word)) {
+            Affinity::Text
+        } else if type_name.contains("BLOB") {
+            Affinity::Blob
+        } else if ["REAL", "FLOA", "DOUB"].iter().any(|word| type_name.contains(word)) {
+            Affinity::Real
+        } else {
+            Affinity::Numeric
+        }
+    }
+
+    /// The letter standing for the affinity in an `EXPLAIN` listing.
+    pub fn code(self) -> char {
+        match self {
+            Affinity::Blob => 'A',
+            Affinity::Text => 'B',
+            Affinity::Numeric => 'C',
+            Affinity::Integer => 'D',
+            Affinity::Real => 'E',
+        }
+    }
+
+    /// Returns the affinity standing for the letter `code`.
+    pub fn from_code(code: char) -> Option<Self> {
+        let affinity = match code {
+            'A' => Affinity::Blob,
+            'B' => Affinity::Text,
+            'C' => Affinity::Numeric,
+            'D' => Affinity::Integer,
+            'E' => Affinity::Real,
+            _ => return None,
+        };
+        Some(affinity)
+    }
+}
+
+/// Reads `text` as an integer or floating point number, ignoring
+/// surrounding whitespace, or returns `None` if it is not one.
+fn parse_numeric(text: &str) -> Option<Value> {
+    let text = text.trim();
+    let is_number_like = text.chars().any(|c| c.is_ascii_digit())
+        && text.chars().all(|c| c.is_ascii_digit() || matches!(c, '+' | '-' | '.' | 'e' | 'E'));
+    if !is_number_like {
+        return None;
+    }
+    if let Ok(value) = text.parse() {
+        return Some(Value::Integer(value));
+    }
+    text.parse().ok().map(Value::Real)
+}
+
+/// Returns the number at the start of `text`, ignoring leading
+/// whitespace, or 0 if it does not start with one.
+fn numeric_prefix(text: &str) -> Value {
+    let text = text.trim_start();
+    let number_like = text
+        .find(|c: char| !(c.is_ascii_digit() || matches!(c, '+' | '-' | '.' | 'e' | 'E')))
+        .unwrap_or(text.len());
+    // The longest prefix that parses, trying longer prefixes first.
+    (1..=number_like)
+        .rev()
+        .find_map(|end| parse_numeric(&text[..end]))
+        .unwrap_or(Value::Integer(0))
+}
+
+fn as_real(value: &Value) -> f64 {
+    match value {
+        Value::Integer(value) => *value as f64,
+        Value::Real(value) => *value,
+        _ => 0.0,
+    }
+}
+
+/// Returns `value` as an integer if it is a whole number in range.
+fn real_to_integer(value: f64) -> Option<i64> {
+    let in_range = value >= i64::MIN as f64 && value < i64::MAX as f64;
+    (value.fract() == 0.0 && in_range).then_some(value as i64)
+}
+
+/// Compares an integer with a real exactly, without rounding the
+/// integer to the nearest real.
+fn compare_integer_to_real(integer: i64, real: f64) -> Ordering {
+    // The bounds are -2^63 and 2^63, both exact as reals.
+    if real < i64::MIN as f64 {
+        return Ordering::Greater;
+    }
+    if real >= -(i64::MIN as f64) {
+        return Ordering::Less;
+    }
+    let whole = real.trunc();
+    // Can unwrap here since the real is not NaN.
+    integer.cmp(&(whole as i64)).then_with(|| 0.0.partial_cmp(&(real - whole)).unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn affinity_follows_declared_type() {
+        assert_eq!(Affinity::Integer, Affinity::from_type_name(Some("BIGINT")));
+        assert_eq!(Affinity::Text, Affinity::from_type_name(Some("varchar(20)")));
+        assert_eq!(Affinity::Blob, Affinity::from_type_name(Some("blob")));
+        assert_eq!(Affinity::Blob, Affinity::from_type_name(None));
+        assert_eq!(Affinity::Real, Affinity::from_type_name(Some("DOUBLE PRECISION")));
+        assert_eq!(Affinity::Numeric, Affinity::from_type_name(Some("DECIMAL(10,5)")));
+    }
+
+    #[test]
+    fn affinity_converts_values_without_losing_information() {
+        let text = |value: &str| Value::Text(String::from(value));
+        assert_eq!(Value::Integer(42), text(" 42 ").apply_affinity(Affinity::Integer));
+        assert_eq!(Value::Integer(3), text("3.0").apply_affinity(Affinity::Numeric));
+        assert_eq!(Value::Real(2.5), text("2.5").apply_affinity(Affinity::Integer));
+        assert_eq!(text("12abc"), text("12abc").apply_affinity(Affinity::Integer));
+        assert_eq!(Value::Real(7.0), Value::Integer(7).apply_affinity(Affinity::Real));
+        assert_eq!(text("2.5"), Value::Real(2.5).apply_affinity(Affinity::Text));
+        assert_eq!(text("7"), text("7").apply_affinity(Affinity::Blob));
+        assert_eq!(Value::Null, Value::Null.apply_affinity(Affinity::Integer));
+    }
+
+    #[test]
+    fn values_compare_numbers_before_text_before_blobs() {
+        let text = |value: &str| Value::Text(String::from(value));
+        assert_eq!(Some(Ordering::Less), Value::Integer(2).compare(&Value::Real(2.5)));
+        assert_eq!(Some(Ordering::Equal), Value::Real(3.0).compare(&Value::Integer(3)));
+        assert_eq!(Some(Ordering::Less), Value::Integer(100).compare(&text("1")));
+        assert_eq!(Some(Ordering::Less), text("abc").compare(&text("abd")));
+        assert_eq!(Some(Ordering::Greater), Value::Blob(Vec::new()).compare(&text("z")));
+        assert_eq!(None, Value::Null.compare(&Value::Null));
+    }
+
+    #[test]
+    fn numbers_compare_exactly() {
+        assert_eq!(Some(Ordering::Equal), Value::Real(0.0).compare(&Value::Real(-0.0)));
+        assert_eq!(Some(Ordering::Equal), Value::Integer(0).compare(&Value::Real(-0.0)));
+        // 2^53 + 1 is not a real, and rounds to 2^53 as one.
+        let (large, rounded) = (9_007_199_254_740_993, 9_007_199_254_740_992.0);
+        assert_eq!(Some(Ordering::Greater), Value::Integer(large).compare(&Value::Real(rounded)));
+        assert_eq!(Some(Ordering::Less), Value::Real(rounded).compare(&Value::Integer(large)));
+        assert_eq!(Some(Ordering::Less), Value::Integer(-3).compare(&Value::Real(-2.5)));
+        assert_eq!(Some(Ordering::Greater), Value::Integer(-2).compare(&Value::Real(-2.5)));
+        assert_eq!(Some(Ordering::Less), Value::Integer(i64::MAX).compare(&Value::Real(i64::MAX as f64)));
+        assert_eq!(Some(Ordering::Equal), Value::Integer(i64::MIN).compare(&Value::Real(i64::MIN as f64)));
+        assert_eq!(Some(Ordering::Greater), Value::Integer(i64::MIN).compare(&Value::Real(f64::NEG_INFINITY)));
+    }
+
+    #[test]
+    fn truth_of_text_depends_on_its_numeric_prefix() {
+        let text = |value: &str| Value::Text(String::from(value));
+        assert_eq!(Some(true), text(" 12abc").is_true());
+        assert_eq!(Some(false), text("abc").is_true());
+        assert_eq!(Some(false), text("0.0").is_true());
+        assert_eq!(Some(true), Value::Real(0.5).is_true());
+        assert_eq!(None, Value::Null.is_true());
+    }
+
+    #[test]
+    fn arithmetic_keeps_integers_unless_they_overflow() {
+        let text = |value: &str| Value::Text(String::from(value));
+        assert_eq!(Value::Integer(7), Value::Integer(3).arithmetic(Arithmetic::Add, &text("4x")));
+        assert_eq!(Value::Integer(2), Value::Integer(7).arithmetic(Arithmetic::Divide, &Value::Integer(3)));
+        assert_eq!(Value::Real(3.5), Value::Integer(7).arithmetic(Arithmetic::Divide, &Value::Real(2.0)));
+        assert_eq!(Value::Real(1.0), Value::Real(5.5).arithmetic(Arithmetic::Remainder, &Value::Integer(2)));
+        assert_eq!(Value::Null, Value::Integer(1).arithmetic(Arithmetic::Divide, &Value::Integer(0)));
+        assert_eq!(Value::Null, Value::Null.arithmetic(Arithmetic::Add, &Value::Integer(1)));
+        assert_eq!(Value::Null, Value::Real(f64::INFINITY).arithmetic(Arithmetic::Subtract, &Value::Real(f64::INFINITY)));
+        assert_eq!(Value::Null, Value::Real(f64::INFINITY).arithmetic(Arithmetic::Multiply, &Value::Integer(0)));
+        assert_eq!(
+            Value::Real(i64::MAX as f64 * 2.0),
+            Value::Integer(i64::MAX).arithmetic(Arithmetic::Multiply, &Value::Integer(2))
+        );
+        assert_eq!(text("a2.5"), text("a").concat(&Value::Real(2.5)));
+        assert_eq!(Value::Null, text("a").concat(&Value::Null));
+    }
+}
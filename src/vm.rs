@@ -0,0 +1,918 @@
+use std::{cell::RefCell, collections::{HashMap, HashSet}, fmt, rc::Rc};
+use crate::{aggregate, btree, cursor, database, pager, schema, table, value::{Affinity, Arithmetic, Value}};
+
+/// Operations understood by the virtual machine.
+///
+/// Operands are described in terms of the `p1`, `p2`, `p3` and `p4`
+/// fields of [`Instruction`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Opcode {
+    /// Start of the program: jump to `p2`.
+    Init,
+    /// Jump to `p2`.
+    Goto,
+    /// Store the address of the next instruction in register `p1` and
+    /// jump to `p2`.
+    Gosub,
+    /// Jump to the address in register `p1`, returning from a `Gosub`.
+    Return,
+    /// Stop the program.
+    Halt,
+    /// Jump to `p2` if this instruction has run before.
+    Once,
+    /// Do nothing.
+    Noop,
+    /// Open cursor `p1` for reading the table or index rooted at page
+    /// `p2`.
+    OpenRead,
+    /// Open cursor `p1` for writing the table or index rooted at page
+    /// `p2`, or at the page in register `p2` if `p3` is 1.
+    OpenWrite,
+    /// Open cursor `p1` on a new, empty table, or index if `p3` is 1,
+    /// that is kept in memory and dropped when the program halts or the
+    /// cursor is opened again.
+    OpenEphemeral,
+    /// Open cursor `p1` on the same table as cursor `p2`, which is
+    /// usually ephemeral.
+    OpenDup,
+    /// Move cursor `p1` to the first row, or jump to `p2` if the table
+    /// is empty.
+    Rewind,
+    /// Move cursor `p1` to the next row and jump to `p2` if there is
+    /// one.
+    Next,
+    /// Store column `p2` of the row at cursor `p1` in register `p3`,
+    /// where the columns of an index entry are the indexed values
+    /// followed by the row id.
+    Column,
+    /// Store the key of the row at cursor `p1` in register `p2`.
+    Rowid,
+    /// Move cursor `p1` to a row of NULLs, which `Column` and `Rowid`
+    /// read as NULL and `Next` moves past to the end, until it is moved
+    /// to another row.
+    NullRow,
+    /// Move cursor `p1` to the row with the key in register `p3`, or
+    /// jump to `p2` if there is no such row.
+    NotExists,
+    /// Move cursor `p1` to the row whose key is the value in register
+    /// `p3`, or jump to `p2` if that value is not an integer or there is
+    /// no such row.
+    SeekRowid,
+    /// Store a key one larger than the largest key in cursor `p1` in
+    /// register `p2`.
+    NewRowid,
+    /// Output registers `p1` to `p1 + p2 - 1` as a result row.
+    ResultRow,
+    /// Store the integer `p4` in register `p2`.
+    Integer,
+    /// Store the floating point number `p4` in register `p2`.
+    Real,
+    /// Store the string `p4` in register `p2`.
+    String,
+    /// Store the blob `p4` in register `p2`.
+    Blob,
+    /// Store NULL in register `p2`, which also resets an aggregate
+    /// accumulated there.
+    Null,
+    /// Copy the `p3` registers starting at `p1` into those starting at
+    /// `p2`.
+    Copy,
+    /// Jump to `p2` if register `p1` is not NULL.
+    NotNull,
+    /// Convert register `p1` to an integer, failing if that would lose
+    /// information.
+    MustBeInt,
+    /// Fail with a NOT NULL constraint error on column `p4` if register
+    /// `p1` is NULL.
+    HaltIfNull,
+    /// Serialise registers `p1` to `p1 + p2 - 1` into a record and store
+    /// it in register `p3`, first applying the affinities given by the
+    /// letters of `p4`, if any.
+    MakeRecord,
+    /// Insert the record in register `p2` into cursor `p1` under the key
+    /// in register `p3`. Fails with a UNIQUE constraint error on column
+    /// `p4`, if given, when another row already has the key.
+    Insert,
+    /// Replace the row at cursor `p1` with the record in register `p2`,
+    /// moving it to the key in register `p3` if that differs from its
+    /// current key. Fails with a UNIQUE constraint error on column `p4`
+    /// if another row already has the new key.
+    Update,
+    /// Delete the row at cursor `p1`, leaving the cursor so that the
+    /// next `Next` moves to the row after it.
+    Delete,
+    /// Add the record in register `p2` to the index at cursor `p1`.
+    IdxInsert,
+    /// Remove the record in register `p2` from the index at cursor
+    /// `p1`, if it is there.
+    IdxDelete,
+    /// Fail with a UNIQUE constraint error on columns `p4` if the index
+    /// at cursor `p1` has an entry for another row id whose first `p3`
+    /// values equal those of the record in register `p2`. Records with
+    /// a NULL among those values never conflict.
+    NoConflict,
+    /// Move the index at cursor `p1` to the first entry whose first
+    /// `p4` values are at least those in the registers starting at
+    /// `p3`, or jump to `p2` if there is none.
+    SeekGe,
+    /// Jump to `p2` if the first `p4` values of the entry at index
+    /// cursor `p1` are greater than those in the registers starting at
+    /// `p3`.
+    IdxGt,
+    /// Store the row id of the entry at index cursor `p1`, its last
+    /// value, in register `p2`.
+    IdxRowid,
+    /// Store whether register `p1` equals register `p2` in register
+    /// `p3`, or NULL if either is NULL. `Ne`, `Lt`, `Le`, `Gt` and `Ge`
+    /// compare in the same way.
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    /// Store whether register `p1` equals register `p2` in register
+    /// `p3`, where NULL equals NULL and no other value. `IsNot` stores
+    /// the opposite.
+    Is,
+    IsNot,
+    /// Store the sum of registers `p1` and `p2` in register `p3`.
+    /// `Subtract`, `Multiply`, `Divide` and `Remainder` apply their
+    /// operators in the same way, with `p1` on the left.
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+    /// Store the text of register `p1` followed by that of register
+    /// `p2` in register `p3`.
+    Concat,
+    /// Store the logical AND of registers `p1` and `p2` in register
+    /// `p3`, where NULL stands for an unknown value.
+    And,
+    /// Store the logical OR of registers `p1` and `p2` in register
+    /// `p3`, where NULL stands for an unknown value.
+    Or,
+    /// Store the logical negation of register `p1` in register `p2`.
+    Not,
+    /// Jump to `p2` if register `p1` is true.
+    If,
+    /// Jump to `p2` if register `p1` is false or NULL.
+    IfNot,
+    /// Jump to `p2` if register `p1` holds a positive integer, first
+    /// subtracting `p3` from it.
+    IfPos,
+    /// Subtract 1 from the integer in register `p1` and jump to `p2` if
+    /// the result is 0.
+    DecrJumpZero,
+    /// Open cursor `p1` as a sorter for records whose first `p2` columns
+    /// are sort keys, each sorted in ascending order if the matching
+    /// character of `p4` is `+` and descending order if it is `-`.
+    SorterOpen,
+    /// Add the record in register `p2` to the sorter at cursor `p1`.
+    SorterInsert,
+    /// Sort the records in the sorter at cursor `p1` and move to the
+    /// first, or jump to `p2` if there are none. `Column` then reads
+    /// from the current record.
+    SorterSort,
+    /// Move the sorter at cursor `p1` to the next record and jump to
+    /// `p2` if there is one.
+    SorterNext,
+    /// Add a row with the `p2` arguments in registers starting at `p1`
+    /// to the aggregate function `p4` accumulated in register `p3`.
+    AggStep,
+    /// Replace the aggregate function `p4` accumulated in register `p1`
+    /// with its result.
+    AggFinal,
+    /// Create an empty B-tree, for an index if `p3` is 1 and a table
+    /// otherwise, and store its root page number in register `p2`.
+    CreateBtree,
+    /// Reread the schema catalog after it has been changed.
+    ParseSchema,
+    /// Rebuild the database into a new file without free space and
+    /// replace the old file with it.
+    Vacuum,
+}
+
+/// The fourth operand of an instruction, holding constants that do not
+/// fit in the integer operands.
+#[derive(Debug, Clone, PartialEq)]
+pub enum P4 {
+    None,
+    Integer(i64),
+    Real(f64),
+    Text(String),
+    Blob(Vec<u8>),
+    Aggregate(aggregate::Function),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Instruction {
+    pub opcode: Opcode,
+    pub p1: usize,
+    pub p2: usize,
+    pub p3: usize,
+    pub p4: P4,
+    /// Human readable description of what the instruction is for.
+    pub comment: String,
+}
+
+impl fmt::Display for P4 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            P4::None => Ok(()),
+            P4::Integer(value) => write!(f, "{value}"),
+            P4::Real(value) => write!(f, "{value:?}"),
+            P4::Text(value) => write!(f, "{value}"),
+            P4::Blob(value) => write!(f, "{}", Value::Blob(value.clone())),
+            P4::Aggregate(function) => write!(f, "{function}"),
+        }
+    }
+}
+
+/// A compiled statement.
+///
+/// Displaying a program lists its instructions one per line, in the
+/// format used by `EXPLAIN`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    pub instructions: Vec<Instruction>,
+    pub num_registers: usize,
+    pub num_cursors: usize,
+    /// Whether the statement was prefixed with `EXPLAIN`, in which case
+    /// the program should be listed rather than run.
+    pub explain: bool,
+    /// Past tense verb, such as "deleted", used to report how many rows
+    /// the program changed, or `None` if the count is not reported.
+    pub changes_verb: Option<&'static str>,
+}
+
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "addr  opcode         p1    p2    p3    p4             comment")?;
+        writeln!(f, "----  -------------  ----  ----  ----  -------------  -------------")?;
+        for (addr, instruction) in self.instructions.iter().enumerate() {
+            let opcode = format!("{:?}", instruction.opcode);
+            let p4 = instruction.p4.to_string();
+            let line = format!(
+                "{addr:<4}  {opcode:<13}  {:<4}  {:<4}  {:<4}  {p4:<13}  {}",
+                instruction.p1, instruction.p2, instruction.p3, instruction.comment
+            );
+            writeln!(f, "{}", line.trim_end())?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum VmError {
+    TableError(table::TableError),
+    AggregateError(aggregate::AggregateError),
+    /// A value has the wrong type for where it is used.
+    TypeMismatch,
+    /// A `constraint` such as NOT NULL does not hold for `column`.
+    ConstraintFailed { constraint: &'static str, column: String },
+    /// There are no row ids left for new rows.
+    Full,
+}
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VmError::TableError(e) => write!(f, "{e}"),
+            VmError::AggregateError(e) => write!(f, "{e}"),
+            VmError::TypeMismatch => write!(f, "datatype mismatch"),
+            VmError::ConstraintFailed { constraint, column } => {
+                write!(f, "{constraint} constraint failed: {column}")
+            },
+            VmError::Full => write!(f, "database or disk is full"),
+        }
+    }
+}
+
+impl From<table::TableError> for VmError {
+    fn from(e: table::TableError) -> Self {
+        Self::TableError(e)
+    }
+}
+
+impl From<aggregate::AggregateError> for VmError {
+    fn from(e: aggregate::AggregateError) -> Self {
+        Self::AggregateError(e)
+    }
+}
+
+impl From<btree::BTreeError> for VmError {
+    fn from(e: btree::BTreeError) -> Self {
+        Self::TableError(e.into())
+    }
+}
+
+impl From<table::SerialiseError> for VmError {
+    fn from(e: table::SerialiseError) -> Self {
+        Self::TableError(e.into())
+    }
+}
+
+pub enum StepResult {
+    /// The program produced a result row.
+    Row(Vec<Value>),
+    /// The program has halted.
+    Done,
+}
+
+/// A cursor opened by a program.
+enum VmCursor {
+    Table(cursor::Cursor),
+    Sorter(Sorter),
+}
+
+/// Records added to a sorter, read back in order once sorted.
+struct Sorter {
+    /// Whether each sort key is sorted in descending order.
+    descending: Vec<bool>,
+    rows: Vec<Vec<Value>>,
+    /// Index of the current row once sorted.
+    position: usize,
+}
+
+impl Sorter {
+    fn sort(&mut self) {
+        let descending = &self.descending;
+        // A stable sort keeps rows with equal keys in the order they
+        // were added.
+        self.rows.sort_by(|a, b| {
+            descending.iter().enumerate()
+                .map(|(i, descending)| {
+                    let ordering = a[i].sort_cmp(&b[i]);
+                    if *descending { ordering.reverse() } else { ordering }
+                })
+                .find(|ordering| ordering.is_ne())
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+        self.position = 0;
+    }
+}
+
+/// Number of pages of an ephemeral table kept in its page cache.
+const EPHEMERAL_CACHE_CAPACITY: usize = 16;
+
+/// Register based virtual machine that runs a [`Program`].
+pub struct Vm {
+    program: Program,
+    pc: usize,
+    registers: Vec<Value>,
+    cursors: Vec<Option<VmCursor>>,
+    /// Whether each cursor is on a row of NULLs.
+    null_rows: Vec<bool>,
+    /// Aggregates part way through a group, by the register they are
+    /// accumulated in.
+    accumulators: HashMap<usize, aggregate::Accumulator>,
+    /// Addresses of the `Once` instructions that have run.
+    once: HashSet<usize>,
+    pager: Rc<RefCell<pager::Pager>>,
+    schema: Rc<RefCell<schema::Schema>>,
+    changes: usize,
+}
+
+impl Vm {
+    /// Prepares `program` to run against `database`.
+    pub fn new(program: Program, database: &database::Database) -> Self {
+        let registers = vec![Value::Null; program.num_registers];
+        let cursors = (0..program.num_cursors).map(|_| None).collect();
+        let null_rows = vec![false; program.num_cursors];
+        Self {
+            program,
+            pc: 0,
+            registers,
+            cursors,
+            null_rows,
+            accumulators: HashMap::new(),
+            once: HashSet::new(),
+            pager: Rc::clone(database.pager()),
+            schema: Rc::clone(database.schema()),
+            changes: 0,
+        }
+    }
+
+    /// Number of rows inserted, updated or deleted so far.
+    pub fn changes(&self) -> usize {
+        self.changes
+    }
+
+    /// Runs the program until it produces a row or halts. If the program
+    /// fails, every change it made to the database is undone.
+    pub fn step(&mut self) -> Result<StepResult, VmError> {
+        if self.pc == 0 {
+            self.pager.borrow_mut().begin_statement();
+        }
+        match self.execute() {
+            Ok(result) => Ok(result),
+            Err(e) => {
+                self.pager.borrow_mut().rollback_statement().map_err(table::TableError::from)?;
+                self.halt();
+                Err(e)
+            },
+        }
+    }
+
+    /// Runs instructions from the current one until one produces a row
+    /// or halts.
+    fn execute(&mut self) -> Result<StepResult, VmError> {
+        loop {
+            let Some(instruction) = self.program.instructions.get(self.pc) else {
+                return Ok(self.halt());
+            };
+            let Instruction { opcode, p1, p2, p3, .. } = *instruction;
+            self.pc += 1;
+            match opcode {
+                Opcode::Init | Opcode::Goto => self.pc = p2,
+                Opcode::Gosub => {
+                    self.registers[p1] = Value::Integer(self.pc as i64);
+                    self.pc = p2;
+                },
+                Opcode::Return => {
+                    let Value::Integer(addr) = self.registers[p1] else {
+                        return Err(VmError::TypeMismatch);
+                    };
+                    self.pc = addr as usize;
+                },
+                Opcode::Halt => return Ok(self.halt()),
+                Opcode::Once => {
+                    if !self.once.insert(self.pc - 1) {
+                        self.pc = p2;
+                    }
+                },
+                Opcode::Noop => {},
+                Opcode::OpenRead | Opcode::OpenWrite => {
+                    let root_page = if p3 == 1 {
+                        let Value::Integer(root_page) = self.registers[p2] else {
+                            return Err(VmError::TypeMismatch);
+                        };
+                        root_page as usize
+                    } else {
+                        p2
+                    };
+                    self.cursors[p1] = Some(VmCursor::Table(cursor::Cursor::new(Rc::clone(&self.pager), root_page)));
+                    self.null_rows[p1] = false;
+                },
+                Opcode::OpenEphemeral => {
+                    let page_size = self.pager.borrow().page_size();
+                    let mut pager = pager::Pager::in_memory(page_size, EPHEMERAL_CACHE_CAPACITY)
+                        .map_err(table::TableError::from)?;
+                    let kind = if p3 == 1 { btree::TreeKind::Index } else { btree::TreeKind::Table };
+                    let root_page = btree::create(&mut pager, kind)?;
+                    self.cursors[p1] = Some(VmCursor::Table(cursor::Cursor::new(Rc::new(RefCell::new(pager)), root_page)));
+                },
+                Opcode::OpenDup => {
+                    let duplicate = self.cursor(p2).duplicate();
+                    self.cursors[p1] = Some(VmCursor::Table(duplicate));
+                    self.null_rows[p1] = false;
+                },
+                Opcode::Rewind => {
+                    self.null_rows[p1] = false;
+                    let cursor = self.cursor(p1);
+                    cursor.rewind()?;
+                    if cursor.end_of_table() {
+                        self.pc = p2;
+                    }
+                },
+                Opcode::Next if self.null_rows[p1] => {},
+                Opcode::Next => {
+                    let cursor = self.cursor(p1);
+                    cursor.advance()?;
+                    if !cursor.end_of_table() {
+                        self.pc = p2;
+                    }
+                },
+                Opcode::Column | Opcode::Rowid | Opcode::IdxRowid if self.null_rows[p1] => {
+                    let target = if opcode == Opcode::Column { p3 } else { p2 };
+                    self.registers[target] = Value::Null;
+                },
+                Opcode::NullRow => self.null_rows[p1] = true,
+                Opcode::Column => {
+                    let values = match &self.cursors[p1] {
+                        Some(VmCursor::Sorter(sorter)) => sorter.rows[sorter.position].clone(),
+                        _ => table::Row::deserialise(&self.cursor(p1).record()?)?.values,
+                    };
+                    // Rows written before a column existed end early.
+                    self.registers[p3] = values.into_iter().nth(p2).unwrap_or(Value::Null);
+                },
+                Opcode::Rowid => self.registers[p2] = Value::Integer(self.cursor(p1).key()?),
+                Opcode::NotExists => {
+                    self.null_rows[p1] = false;
+                    let Value::Integer(key) = self.registers[p3] else {
+                        return Err(VmError::TypeMismatch);
+                    };
+                    let cursor = self.cursor(p1);
+                    cursor.seek(key)?;
+                    if cursor.end_of_table() || cursor.key()? != key {
+                        self.pc = p2;
+                    }
+                },
+                Opcode::SeekRowid => {
+                    self.null_rows[p1] = false;
+                    let key = match self.registers[p3] {
+                        Value::Integer(key) => Some(key),
+                        Value::Real(value) if value.fract() == 0.0 && value.abs() < i64::MAX as f64 => Some(value as i64),
+                        _ => None,
+                    };
+                    let found = match key {
+                        Some(key) => {
+                            let cursor = self.cursor(p1);
+                            cursor.seek(key)?;
+                            !cursor.end_of_table() && cursor.key()? == key
+                        },
+                        None => false,
+                    };
+                    if !found {
+                        self.pc = p2;
+                    }
+                },
+                Opcode::NewRowid => {
+                    let cursor = self.cursor(p1);
+                    cursor.last()?;
+                    let rowid = if cursor.end_of_table() {
+                        1
+                    } else {
+                        cursor.key()?.checked_add(1).ok_or(VmError::Full)?
+                    };
+                    self.registers[p2] = Value::Integer(rowid);
+                },
+                Opcode::ResultRow => {
+                    return Ok(StepResult::Row(self.registers[p1..p1 + p2].to_vec()));
+                },
+                Opcode::Integer | Opcode::Real | Opcode::String | Opcode::Blob => {
+                    self.registers[p2] = match &self.program.instructions[self.pc - 1].p4 {
+                        P4::Integer(value) => Value::Integer(*value),
+                        P4::Real(value) => Value::Real(*value),
+                        P4::Text(value) => Value::Text(value.clone()),
+                        P4::Blob(value) => Value::Blob(value.clone()),
+                        P4::None | P4::Aggregate(_) => Value::Null,
+                    };
+                },
+                Opcode::Null => {
+                    self.registers[p2] = Value::Null;
+                    self.accumulators.remove(&p2);
+                },
+                Opcode::Copy => {
+                    for i in 0..p3 {
+                        self.registers[p2 + i] = self.registers[p1 + i].clone();
+                    }
+                },
+                Opcode::NotNull => {
+                    if self.registers[p1] != Value::Null {
+                        self.pc = p2;
+                    }
+                },
+                Opcode::MustBeInt => {
+                    self.registers[p1] = match &self.registers[p1] {
+                        Value::Integer(value) => Value::Integer(*value),
+                        Value::Real(value) if value.fract() == 0.0 && value.abs() < i64::MAX as f64 => {
+                            Value::Integer(*value as i64)
+                        },
+                        Value::Text(text) => match text.trim().parse() {
+                            Ok(value) => Value::Integer(value),
+                            Err(_) => return Err(VmError::TypeMismatch),
+                        },
+                        _ => return Err(VmError::TypeMismatch),
+                    };
+                },
+                Opcode::HaltIfNull => {
+                    if self.registers[p1] == Value::Null {
+                        let column = self.program.instructions[self.pc - 1].p4.to_string();
+                        return Err(VmError::ConstraintFailed { constraint: "NOT NULL", column });
+                    }
+                },
+                Opcode::MakeRecord => {
+                    let affinities = match &self.program.instructions[self.pc - 1].p4 {
+                        P4::Text(codes) => codes.chars().map(Affinity::from_code).collect(),
+                        _ => Vec::new(),
+                    };
+                    let values = self.registers[p1..p1 + p2].iter().enumerate().map(|(i, value)| {
+                        match affinities.get(i).copied().flatten() {
+                            Some(affinity) => value.clone().apply_affinity(affinity),
+                            None => value.clone(),
+                        }
+                    });
+                    let row = table::Row { values: values.collect() };
+                    self.registers[p3] = Value::Blob(row.serialise().into_vec());
+                },
+                Opcode::Insert => {
+                    let (Value::Blob(record), Value::Integer(key)) = (&self.registers[p2], &self.registers[p3]) else {
+                        return Err(VmError::TypeMismatch);
+                    };
+                    let (record, key) = (record.clone(), *key);
+                    let result = self.cursor(p1).insert(key, &record);
+                    if let (Err(btree::BTreeError::DuplicateKey(_)), P4::Text(column)) = (&result, &self.program.instructions[self.pc - 1].p4) {
+                        return Err(VmError::ConstraintFailed { constraint: "UNIQUE", column: column.clone() });
+                    }
+                    result?;
+                },
+                Opcode::Update => {
+                    let (Value::Blob(record), Value::Integer(new_key)) = (&self.registers[p2], &self.registers[p3]) else {
+                        return Err(VmError::TypeMismatch);
+                    };
+                    let (record, new_key) = (record.clone(), *new_key);
+                    let column = self.program.instructions[self.pc - 1].p4.to_string();
+                    let cursor = self.cursor(p1);
+                    let key = cursor.key()?;
+                    if new_key == key {
+                        cursor.update(&record)?;
+                    } else {
+                        // Check the new key is free before the row is
+                        // removed from its old one.
+                        cursor.seek(new_key)?;
+                        if !cursor.end_of_table() && cursor.key()? == new_key {
+                            return Err(VmError::ConstraintFailed { constraint: "UNIQUE", column });
+                        }
+                        cursor.seek(key)?;
+                        cursor.delete()?;
+                        cursor.insert(new_key, &record)?;
+                    }
+                    self.changes += 1;
+                },
+                Opcode::Delete => {
+                    self.cursor(p1).delete()?;
+                    self.changes += 1;
+                },
+                Opcode::IdxInsert => {
+                    let record = self.record(p2)?;
+                    self.cursor(p1).insert_record(&record)?;
+                },
+                Opcode::IdxDelete => {
+                    let key = btree::Key::Record(self.record(p2)?);
+                    let cursor = self.cursor(p1);
+                    cursor.seek_key(&key)?;
+                    if !cursor.end_of_table() && cursor.cell_key()? == key {
+                        cursor.delete()?;
+                    }
+                },
+                Opcode::NoConflict => {
+                    let mut values = table::Row::deserialise(&self.record(p2)?)?.values;
+                    let rowid = values.pop();
+                    values.truncate(p3);
+                    if !values.contains(&Value::Null) {
+                        let column = self.program.instructions[self.pc - 1].p4.to_string();
+                        let prefix = table::Row { values: values.clone() }.serialise().into_vec();
+                        let cursor = self.cursor(p1);
+                        cursor.seek_key(&btree::Key::Record(prefix))?;
+                        while !cursor.end_of_table() {
+                            let mut entry = table::Row::deserialise(&cursor.record()?)?.values;
+                            let entry_rowid = entry.pop();
+                            if compare_prefix(&entry, &values).is_ne() {
+                                break;
+                            }
+                            if entry_rowid != rowid {
+                                return Err(VmError::ConstraintFailed { constraint: "UNIQUE", column });
+                            }
+                            cursor.advance()?;
+                        }
+                    }
+                },
+                Opcode::SeekGe => {
+                    self.null_rows[p1] = false;
+                    let values = self.registers[p3..p3 + self.p4_count()].to_vec();
+                    let prefix = table::Row { values }.serialise().into_vec();
+                    let cursor = self.cursor(p1);
+                    cursor.seek_key(&btree::Key::Record(prefix))?;
+                    if cursor.end_of_table() {
+                        self.pc = p2;
+                    }
+                },
+                Opcode::IdxGt => {
+                    let count = self.p4_count();
+                    let entry = table::Row::deserialise(&self.cursor(p1).record()?)?.values;
+                    if compare_prefix(&entry, &self.registers[p3..p3 + count]).is_gt() {
+                        self.pc = p2;
+                    }
+                },
+                Opcode::IdxRowid => {
+                    let entry = table::Row::deserialise(&self.cursor(p1).record()?)?.values;
+                    self.registers[p2] = entry.into_iter().last().unwrap_or(Value::Null);
+                },
+                Opcode::Eq | Opcode::Ne | Opcode::Lt | Opcode::Le | Opcode::Gt | Opcode::Ge => {
+                    let ordering = self.registers[p1].compare(&self.registers[p2]);
+                    self.registers[p3] = match ordering {
+                        Some(ordering) => Value::from(match opcode {
+                            Opcode::Eq => ordering.is_eq(),
+                            Opcode::Ne => ordering.is_ne(),
+                            Opcode::Lt => ordering.is_lt(),
+                            Opcode::Le => ordering.is_le(),
+                            Opcode::Gt => ordering.is_gt(),
+                            _ => ordering.is_ge(),
+                        }),
+                        None => Value::Null,
+                    };
+                },
+                Opcode::Is | Opcode::IsNot => {
+                    let (a, b) = (&self.registers[p1], &self.registers[p2]);
+                    let equal = match (a, b) {
+                        (Value::Null, Value::Null) => true,
+                        (Value::Null, _) | (_, Value::Null) => false,
+                        (a, b) => a.compare(b).is_some_and(|ordering| ordering.is_eq()),
+                    };
+                    self.registers[p3] = Value::from(equal == (opcode == Opcode::Is));
+                },
+                Opcode::Add | Opcode::Subtract | Opcode::Multiply | Opcode::Divide | Opcode::Remainder => {
+                    let op = match opcode {
+                        Opcode::Add => Arithmetic::Add,
+                        Opcode::Subtract => Arithmetic::Subtract,
+                        Opcode::Multiply => Arithmetic::Multiply,
+                        Opcode::Divide => Arithmetic::Divide,
+                        _ => Arithmetic::Remainder,
+                    };
+                    self.registers[p3] = self.registers[p1].arithmetic(op, &self.registers[p2]);
+                },
+                Opcode::Concat => self.registers[p3] = self.registers[p1].concat(&self.registers[p2]),
+                Opcode::And | Opcode::Or => {
+                    let (a, b) = (self.registers[p1].is_true(), self.registers[p2].is_true());
+                    // The value that decides the result on its own.
+                    let decisive = opcode == Opcode::Or;
+                    self.registers[p3] = if a == Some(decisive) || b == Some(decisive) {
+                        Value::from(decisive)
+                    } else if a.is_none() || b.is_none() {
+                        Value::Null
+                    } else {
+                        Value::from(!decisive)
+                    };
+                },
+                Opcode::Not => {
+                    self.registers[p2] = match self.registers[p1].is_true() {
+                        Some(value) => Value::from(!value),
+                        None => Value::Null,
+                    };
+                },
+                Opcode::If => {
+                    if self.registers[p1].is_true() == Some(true) {
+                        self.pc = p2;
+                    }
+                },
+                Opcode::IfNot => {
+                    if self.registers[p1].is_true() != Some(true) {
+                        self.pc = p2;
+                    }
+                },
+                Opcode::IfPos => {
+                    if let Value::Integer(value) = self.registers[p1] {
+                        if value > 0 {
+                            self.registers[p1] = Value::Integer(value - p3 as i64);
+                            self.pc = p2;
+                        }
+                    }
+                },
+                Opcode::DecrJumpZero => {
+                    if let Value::Integer(value) = self.registers[p1] {
+                        let value = value.saturating_sub(1);
+                        self.registers[p1] = Value::Integer(value);
+                        if value == 0 {
+                            self.pc = p2;
+                        }
+                    }
+                },
+                Opcode::SorterOpen => {
+                    let descending = match &self.program.instructions[self.pc - 1].p4 {
+                        P4::Text(order) => order.chars().map(|c| c == '-').collect(),
+                        _ => vec![false; p2],
+                    };
+                    self.cursors[p1] = Some(VmCursor::Sorter(Sorter { descending, rows: Vec::new(), position: 0 }));
+                },
+                Opcode::SorterInsert => {
+                    let Value::Blob(record) = &self.registers[p2] else {
+                        return Err(VmError::TypeMismatch);
+                    };
+                    let row = table::Row::deserialise(record)?;
+                    self.sorter(p1).rows.push(row.values);
+                },
+                Opcode::SorterSort => {
+                    let sorter = self.sorter(p1);
+                    sorter.sort();
+                    if sorter.rows.is_empty() {
+                        self.pc = p2;
+                    }
+                },
+                Opcode::SorterNext => {
+                    let sorter = self.sorter(p1);
+                    sorter.position += 1;
+                    if sorter.position < sorter.rows.len() {
+                        self.pc = p2;
+                    }
+                },
+                Opcode::AggStep | Opcode::AggFinal => {
+                    let P4::Aggregate(function) = self.program.instructions[self.pc - 1].p4 else {
+                        return Err(VmError::TypeMismatch);
+                    };
+                    if opcode == Opcode::AggStep {
+                        let accumulator = self.accumulators.entry(p3).or_insert_with(|| aggregate::Accumulator::new(function));
+                        accumulator.step(&self.registers[p1..p1 + p2])?;
+                    } else {
+                        let accumulator = self.accumulators.remove(&p1).unwrap_or_else(|| aggregate::Accumulator::new(function));
+                        self.registers[p1] = accumulator.finish();
+                    }
+                },
+                Opcode::CreateBtree => {
+                    let kind = if p3 == 0 { btree::TreeKind::Table } else { btree::TreeKind::Index };
+                    let root_page = btree::create(&mut self.pager.borrow_mut(), kind)?;
+                    self.registers[p2] = Value::Integer(root_page as i64);
+                },
+                Opcode::ParseSchema => {
+                    *self.schema.borrow_mut() = schema::Schema::load(&self.pager)?;
+                },
+                Opcode::Vacuum => database::vacuum(&self.pager)?,
+            }
+        }
+    }
+
+    /// Returns the table cursor `index`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the cursor is not open on a table, which the compiler
+    /// never does since it only emits cursor instructions after the
+    /// matching open instruction.
+    fn cursor(&mut self, index: usize) -> &mut cursor::Cursor {
+        match &mut self.cursors[index] {
+            Some(VmCursor::Table(cursor)) => cursor,
+            _ => panic!("cursor {index} is not open on a table"),
+        }
+    }
+
+    /// Returns the record in register `index`.
+    fn record(&self, index: usize) -> Result<Vec<u8>, VmError> {
+        match &self.registers[index] {
+            Value::Blob(record) => Ok(record.clone()),
+            _ => Err(VmError::TypeMismatch),
+        }
+    }
+
+    /// Returns the count in `p4` of the current instruction.
+    fn p4_count(&self) -> usize {
+        match self.program.instructions[self.pc - 1].p4 {
+            P4::Integer(count) => count as usize,
+            _ => 0,
+        }
+    }
+
+    /// Returns the sorter at cursor `index`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the cursor is not open as a sorter.
+    fn sorter(&mut self, index: usize) -> &mut Sorter {
+        match &mut self.cursors[index] {
+            Some(VmCursor::Sorter(sorter)) => sorter,
+            _ => panic!("cursor {index} is not a sorter"),
+        }
+    }
+
+    /// Closes every cursor so their pages can be evicted.
+    fn halt(&mut self) -> StepResult {
+        self.pc = self.program.instructions.len();
+        self.cursors.iter_mut().for_each(|cursor| *cursor = None);
+        self.pager.borrow_mut().commit_statement();
+        StepResult::Done
+    }
+}
+
+/// Compares the first `prefix.len()` values of `entry` with `prefix`
+/// in index order.
+fn compare_prefix(entry: &[Value], prefix: &[Value]) -> std::cmp::Ordering {
+    prefix.iter().enumerate()
+        .map(|(i, value)| entry.get(i).unwrap_or(&Value::Null).sort_cmp(value))
+        .find(|ordering| ordering.is_ne())
+        .unwrap_or(std::cmp::Ordering::Equal)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn program_is_listed_one_instruction_per_line() {
+        let instruction = |opcode, p2, p4, comment: &str| Instruction {
+            opcode,
+            p1: 0,
+            p2,
+            p3: 0,
+            p4,
+            comment: String::from(comment),
+        };
+        let program = Program {
+            instructions: vec![
+                instruction(Opcode::Init, 1, P4::None, "Start at 1"),
+                instruction(Opcode::String, 0, P4::Text(String::from("hello")), ""),
+                instruction(Opcode::Halt, 0, P4::None, ""),
+            ],
+            num_registers: 1,
+            num_cursors: 0,
+            explain: true,
+            changes_verb: None,
+        };
+        let listing = program.to_string();
+        let lines: Vec<&str> = listing.lines().collect();
+        assert_eq!(5, lines.len());
+        assert_eq!("0     Init           0     1     0                    Start at 1", lines[2]);
+        assert_eq!("1     String         0     0     0     hello", lines[3]);
+        assert_eq!("2     Halt           0     0     0", lines[4]);
+    }
+}